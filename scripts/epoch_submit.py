@@ -73,7 +73,7 @@ def build_epoch_snapshot(epoch: int) -> bool:
         "canister", "call",
         BACKEND_CANISTER_ID,
         "build_epoch_snapshot",
-        f"({epoch} : nat64)"
+        f"({epoch} : nat64, record {{}})"
     ])
     
     if not success:
@@ -126,7 +126,7 @@ echo ""
 # 使用 dfx_call 确保在根目录执行
 echo "正在调用 build_epoch_snapshot，请稍候..."
 # 直接执行并捕获输出，但不屏蔽标准错误
-if ! dfx_call canister call "$BACKEND_CANISTER_ID" build_epoch_snapshot "($EPOCH : nat64)"; then
+if ! dfx_call canister call "$BACKEND_CANISTER_ID" build_epoch_snapshot "($EPOCH : nat64, record {})"; then
     echo "❌ Error: Failed to build epoch snapshot"
     exit 1
 fi
@@ -120,7 +120,7 @@ echo ""
 # ===== Step 1: Build Epoch Snapshot on ICP =====
 
 echo "[Step 1/4] Building epoch snapshot on ICP backend..."
-if ! dfx_call canister call "$BACKEND_CANISTER_ID" build_epoch_snapshot "($EPOCH : nat64)"; then
+if ! dfx_call canister call "$BACKEND_CANISTER_ID" build_epoch_snapshot "($EPOCH : nat64, record {})"; then
     echo "❌ Error: Failed to build epoch snapshot"
     exit 1
 fi
@@ -471,7 +471,6 @@ echo ""
 
 SUCCESS_COUNT=0
 FAIL_COUNT=0
-TIMESTAMP=$(get_timestamp)
 
 for wallet in "${WALLETS[@]}"; do
     echo "处理钱包: $wallet"
@@ -583,9 +582,9 @@ PYEOF
         
         # 调用 complete_task
         # 注意：evidence 参数是可选的，这里传 null
-        # Candid 格式：complete_task(wallet: text, taskid: text, evidence: opt text, ts: nat64)
+        # Candid 格式：complete_task(wallet: text, taskid: text, evidence: opt text)，完成时间由 canister 决定
         COMPLETE_RESULT=$(dfx_call canister call "$BACKEND_CANISTER_ID" complete_task \
-            "(\"$wallet\", \"$taskid\", null : opt text, $TIMESTAMP : nat64)" 2>&1)
+            "(\"$wallet\", \"$taskid\", null : opt text)" 2>&1)
         
         if echo "$COMPLETE_RESULT" | grep -q "Ok"; then
             echo "    ✅ 任务完成成功"
@@ -643,7 +642,7 @@ PYEOF
 fi
 
 echo "正在为 Epoch $EPOCH 构建快照..."
-SNAPSHOT_RESULT=$(dfx_call canister call "$BACKEND_CANISTER_ID" build_epoch_snapshot "($EPOCH : nat64)" 2>&1)
+SNAPSHOT_RESULT=$(dfx_call canister call "$BACKEND_CANISTER_ID" build_epoch_snapshot "($EPOCH : nat64, record {})" 2>&1)
 
 if echo "$SNAPSHOT_RESULT" | grep -q "Ok"; then
     echo "✅ Epoch $EPOCH 快照构建成功，奖励已进入 RewardPrepared 状态"
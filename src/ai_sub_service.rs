@@ -2,9 +2,13 @@
 // See prompts_ai_subscribe.md and ai_subscription_types.rs.
 
 use crate::ai_subscription_types::{
-    PriceLevel, PrincipalSubscriptionKey, ServiceType, SubscriptionRecord, SubscriptionStatus,
+    PriceLevel, PrincipalSubscriptionKey, ServiceType, Subscription, SubscriptionPlan,
+    SubscriptionRecord, SubscriptionStatus,
+};
+use crate::stable_mem_storage::{
+    AI_SERVICES, SUBSCRIPTION_PLANS, SUBSCRIPTION_PRINCIPAL_INDEX, SUBSCRIPTION_RECORDS,
+    SUBSCRIPTIONS, WALLET_PRINCIPALS,
 };
-use crate::stable_mem_storage::{AI_SERVICES, SUBSCRIPTION_PRINCIPAL_INDEX, SUBSCRIPTION_RECORDS};
 
 // ---------- ServiceType CRUD ----------
 
@@ -168,3 +172,194 @@ pub fn get_active_subscriptions(principal_id: &str) -> Vec<SubscriptionRecord> {
         .filter(|r| r.status == SubscriptionStatus::Normal)
         .collect()
 }
+
+// ---------- Payment-backed entitlements ----------
+
+/// payfor category sold as the AI subscription
+pub const AI_SUBSCRIPTION_PAYFOR: &str = "ai_subscription";
+
+const DAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Plan used for "ai_subscription" until a controller configures one
+fn default_plan(payfor: &str) -> Option<SubscriptionPlan> {
+    (payfor == AI_SUBSCRIPTION_PAYFOR).then(|| SubscriptionPlan {
+        tier: "premium".to_string(),
+        duration_ns: 30 * DAY_NS,
+    })
+}
+
+pub fn get_subscription_plan(payfor: &str) -> Option<SubscriptionPlan> {
+    SUBSCRIPTION_PLANS
+        .with(|m| m.borrow().get(&payfor.to_string()))
+        .or_else(|| default_plan(payfor))
+}
+
+/// Configure the entitlement granted by payments for `payfor` (controller only)
+pub fn set_subscription_plan(payfor: String, plan: SubscriptionPlan) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can set subscription plans".to_string());
+    }
+    if plan.duration_ns == 0 || plan.tier.is_empty() {
+        return Err("tier and a non-zero duration_ns are required".to_string());
+    }
+    SUBSCRIPTION_PLANS.with(|m| m.borrow_mut().insert(payfor, plan));
+    Ok(())
+}
+
+/// Bind a payment wallet to a principal so its payments grant entitlements (admin only).
+/// Users bind their own wallets with bind_wallet_with_proof.
+pub fn bind_wallet_principal(wallet: String, principal_id: String) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err(format!("NotAuthorized: caller {} cannot bind wallets without a proof", caller));
+    }
+    let wallet = crate::task_rewards::normalize_wallet(&wallet)?;
+    WALLET_PRINCIPALS.with(|m| m.borrow_mut().insert(wallet, principal_id));
+    Ok(())
+}
+
+/// The exact text a wallet owner signs to bind it to a principal:
+/// `AIO_BIND:{principal_id}:{wallet}` as UTF-8, with the principal in textual form and the
+/// wallet in base58, no whitespace or trailing newline. The ed25519 signature is made over
+/// SHA256 of those bytes, not over the text itself.
+pub fn get_binding_message(principal_id: String, wallet: String) -> String {
+    format!("AIO_BIND:{}:{}", principal_id, wallet)
+}
+
+/// Check that `signature` is the wallet's ed25519 signature over SHA256 of the binding message
+fn verify_binding_proof(principal_id: &str, wallet: &str, signature: &[u8], message: &[u8]) -> Result<(), String> {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+    use sha2::{Digest, Sha256};
+
+    // Every unauthenticated call comes from the anonymous principal, so it can own no wallet
+    if principal_id == candid::Principal::anonymous().to_text() {
+        return Err("NotAuthorized: the anonymous principal cannot bind wallets".to_string());
+    }
+    if message != get_binding_message(principal_id.to_string(), wallet.to_string()).as_bytes() {
+        return Err("Binding message does not match get_binding_message".to_string());
+    }
+    let pubkey = crate::task_rewards::decode_wallet_base58(wallet)?;
+    let key = VerifyingKey::from_bytes(&pubkey).map_err(|_| "Signature verification failed".to_string())?;
+    let signature = Signature::from_slice(signature).map_err(|_| "Signature verification failed".to_string())?;
+    key.verify(&Sha256::digest(message), &signature)
+        .map_err(|_| "Signature verification failed".to_string())
+}
+
+/// Bind a Solana wallet to the caller, proven by a signature from the wallet's key
+pub fn bind_wallet_with_proof(wallet: String, signature: Vec<u8>, message: Vec<u8>) -> Result<(), String> {
+    let principal_id = crate::env::caller().to_text();
+    let wallet = crate::task_rewards::normalize_wallet(&wallet)?;
+    verify_binding_proof(&principal_id, &wallet, &signature, &message)?;
+    WALLET_PRINCIPALS.with(|m| m.borrow_mut().insert(wallet, principal_id));
+    Ok(())
+}
+
+pub fn get_wallet_principal(wallet: &str) -> Option<String> {
+    WALLET_PRINCIPALS.with(|m| m.borrow().get(&wallet.to_string()))
+}
+
+/// Move a wallet's principal binding to another wallet (used by wallet migration)
+pub fn rebind_wallet(old_wallet: &str, new_wallet: &str) {
+    WALLET_PRINCIPALS.with(|m| {
+        let mut map = m.borrow_mut();
+        if let Some(principal_id) = map.remove(&old_wallet.to_string()) {
+            map.insert(new_wallet.to_string(), principal_id);
+        }
+    });
+}
+
+/// Renewals extend from whichever is later: now or the current expiry
+fn extended_expiry(current: Option<u64>, now: u64, duration_ns: u64) -> u64 {
+    current.unwrap_or(0).max(now).saturating_add(duration_ns)
+}
+
+/// Extend the subscription of the principal bound to `wallet` after a plan payment.
+/// Returns the updated subscription, or None when the payfor has no plan or the wallet is unbound.
+pub fn apply_subscription_payment(wallet: &str, payfor: &str, now: u64) -> Option<Subscription> {
+    let plan = get_subscription_plan(payfor)?;
+    let principal_id = get_wallet_principal(wallet)?;
+    SUBSCRIPTIONS.with(|m| {
+        let mut map = m.borrow_mut();
+        let current = map.get(&principal_id).map(|s| s.expires_at);
+        let subscription = Subscription {
+            principal_id: principal_id.clone(),
+            tier: plan.tier,
+            expires_at: extended_expiry(current, now, plan.duration_ns),
+        };
+        map.insert(principal_id, subscription.clone());
+        Some(subscription)
+    })
+}
+
+/// Shorten the subscription of the principal bound to `wallet` by one plan period after a refund
+pub fn apply_subscription_refund(wallet: &str, payfor: &str) -> Option<Subscription> {
+    let plan = get_subscription_plan(payfor)?;
+    let principal_id = get_wallet_principal(wallet)?;
+    SUBSCRIPTIONS.with(|m| {
+        let mut map = m.borrow_mut();
+        let mut subscription = map.get(&principal_id)?;
+        subscription.expires_at = subscription.expires_at.saturating_sub(plan.duration_ns);
+        map.insert(principal_id, subscription.clone());
+        Some(subscription)
+    })
+}
+
+pub fn get_subscription(principal_id: &str) -> Option<Subscription> {
+    SUBSCRIPTIONS.with(|m| m.borrow().get(&principal_id.to_string()))
+}
+
+/// `now` is in nanoseconds, as returned by crate::env::time()
+pub fn is_subscription_active(principal_id: &str, now: u64) -> bool {
+    get_subscription(principal_id).map_or(false, |s| now < s.expires_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renewal_extends_from_later_of_now_and_expiry() {
+        // Active: stack on top of the current expiry
+        assert_eq!(extended_expiry(Some(500), 100, 50), 550);
+        // Lapsed: start again from now
+        assert_eq!(extended_expiry(Some(100), 500, 50), 550);
+        // First purchase
+        assert_eq!(extended_expiry(None, 500, 50), 550);
+        assert_eq!(extended_expiry(Some(u64::MAX - 1), 0, 50), u64::MAX);
+    }
+
+    #[test]
+    fn test_default_plan_only_for_ai_subscription() {
+        assert_eq!(default_plan(AI_SUBSCRIPTION_PAYFOR).unwrap().duration_ns, 30 * DAY_NS);
+        assert!(default_plan("voice_clone").is_none());
+    }
+
+    #[test]
+    fn test_binding_proof_requires_wallet_signature() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use sha2::{Digest, Sha256};
+
+        let key = SigningKey::from_bytes(&[9u8; 32]);
+        let wallet = bs58::encode(key.verifying_key().to_bytes()).into_string();
+        let principal_id = "2vxsx-fae";
+        let message = get_binding_message(principal_id.to_string(), wallet.clone()).into_bytes();
+        assert_eq!(message, format!("AIO_BIND:2vxsx-fae:{}", wallet).into_bytes());
+        let signature = key.sign(&Sha256::digest(&message)).to_bytes().to_vec();
+        assert!(verify_binding_proof(principal_id, &wallet, &signature, &message).unwrap_err().contains("anonymous"));
+
+        let principal_id = "aaaaa-aa";
+        let message = get_binding_message(principal_id.to_string(), wallet.clone()).into_bytes();
+        let signature = key.sign(&Sha256::digest(&message)).to_bytes().to_vec();
+        assert!(verify_binding_proof(principal_id, &wallet, &signature, &message).is_ok());
+        // Signature over the raw message instead of its hash
+        let raw = key.sign(&message).to_bytes().to_vec();
+        assert_eq!(verify_binding_proof(principal_id, &wallet, &raw, &message), Err("Signature verification failed".to_string()));
+        // Another wallet's key
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let forged = other.sign(&Sha256::digest(&message)).to_bytes().to_vec();
+        assert_eq!(verify_binding_proof(principal_id, &wallet, &forged, &message), Err("Signature verification failed".to_string()));
+        // Message for a different principal
+        let stolen = get_binding_message("2vxsx-fae".to_string(), wallet.clone()).into_bytes();
+        assert!(verify_binding_proof(principal_id, &wallet, &signature, &stolen).is_err());
+    }
+}
@@ -100,3 +100,45 @@ impl Storable for PrincipalSubscriptionKey {
         is_fixed_size: false,
     };
 }
+
+/// Entitlement granted by a plan payfor, e.g. "ai_subscription"
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SubscriptionPlan {
+    pub tier: String,
+    /// Time added per payment, in nanoseconds
+    pub duration_ns: u64,
+}
+
+/// Current entitlement of a principal; active while now < expires_at (nanoseconds)
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Subscription {
+    pub principal_id: String,
+    pub tier: String,
+    pub expires_at: u64,
+}
+
+impl Storable for SubscriptionPlan {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for Subscription {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+}
@@ -1,11 +1,15 @@
-use candid::{CandidType, Decode, Encode};
+use candid::{CandidType, Decode, Encode, Principal};
 use ic_stable_structures::storable::Bound;
 use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cell::RefCell;
-use crate::stable_mem_storage::USER_AI_CONFIG;
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::{
+    USER_AI_CONFIG, USER_AI_AGENT_CONFIGS, MAX_AGENTS_PER_PRINCIPAL, USER_AI_CONFIG_HISTORY,
+    AI_CONFIGS_BY_VOICE, AI_CONFIGS_BY_AGENT, AGENT_CATALOG, VOICE_CATALOG, DELETED_AI_CONFIGS,
+};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -14,6 +18,236 @@ pub struct UserAiConfig {
     pub principal_id: String,
     pub agent_id: String,
     pub voice_id: String,
+    pub is_default: Option<bool>,
+    // Nanoseconds from ic_cdk::api::time(); None for records written before timestamps existed
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    // Free-form agent settings (model, temperature, language, ...)
+    pub settings: Vec<(String, String)>,
+    // AiCapability bits of the principal; the same on all of its configs
+    pub capabilities: u64,
+    // When the config was soft-deleted; only set on the copies kept in a DeletedAiConfig
+    pub deleted_at: Option<u64>,
+    // When the principal's access ends (ns); the same on all of its configs, None = never
+    pub expires_at: Option<u64>,
+}
+
+// Config shape stored before capabilities were added
+#[derive(CandidType, Deserialize)]
+struct UncappedUserAiConfig {
+    principal_id: String,
+    agent_id: String,
+    voice_id: String,
+    is_default: Option<bool>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+    settings: Vec<(String, String)>,
+}
+
+impl From<UncappedUserAiConfig> for UserAiConfig {
+    fn from(old: UncappedUserAiConfig) -> Self {
+        Self {
+            principal_id: old.principal_id,
+            agent_id: old.agent_id,
+            voice_id: old.voice_id,
+            is_default: old.is_default,
+            created_at: old.created_at,
+            updated_at: old.updated_at,
+            settings: old.settings,
+            capabilities: 0,
+            deleted_at: None,
+            expires_at: None,
+        }
+    }
+}
+
+// Config shape stored before settings were added
+#[derive(CandidType, Deserialize)]
+struct LegacyUserAiConfig {
+    principal_id: String,
+    agent_id: String,
+    voice_id: String,
+    is_default: Option<bool>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+}
+
+// Limits for UserAiConfig::settings
+pub const MAX_AI_CONFIG_SETTINGS: usize = 32;
+pub const MAX_AI_CONFIG_SETTING_KEY_LEN: usize = 64;
+pub const MAX_AI_CONFIG_SETTING_VALUE_LEN: usize = 256;
+
+// Default per-principal agent limit
+pub const DEFAULT_MAX_AGENTS_PER_PRINCIPAL: u64 = 10;
+
+// Number of config versions kept per principal in the history log
+pub const AI_CONFIG_HISTORY_LIMIT: u64 = 20;
+
+// Maximum entries in one admin batch call
+pub const MAX_AI_CONFIG_BATCH: usize = 500;
+
+// deleted_by of the tombstone of a principal whose access expired
+pub const EXPIRED_DELETER: &str = "expired";
+
+// Feature flags of a principal, stored in UserAiConfig::capabilities. Bit assignments:
+//   bit 0  VOICE_CLONE      voice cloning
+//   bit 1  AGENT_V2         second-generation agents
+//   bit 2  BATCH_INFERENCE  batch inference jobs
+// Bits are only ever appended, and each new one is added to AiCapability::ALL; a retired
+// feature keeps its bit reserved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AiCapability(pub u64);
+
+impl AiCapability {
+    pub const VOICE_CLONE: u64 = 1 << 0;
+    pub const AGENT_V2: u64 = 1 << 1;
+    pub const BATCH_INFERENCE: u64 = 1 << 2;
+    // Every assigned bit; grants and checks of other bits are refused
+    pub const ALL: u64 = Self::VOICE_CLONE | Self::AGENT_V2 | Self::BATCH_INFERENCE;
+
+    // Check that `flag` is non-empty and only uses assigned bits
+    pub fn validate(flag: u64) -> Result<(), String> {
+        if flag == 0 {
+            return Err("Capability must have at least one bit set".to_string());
+        }
+        if flag & !Self::ALL != 0 {
+            return Err(format!("Unknown capability bits: {:#x}", flag & !Self::ALL));
+        }
+        Ok(())
+    }
+
+    // Whether every bit of `flag` is set; an empty flag is never held
+    pub fn has(self, flag: u64) -> bool {
+        flag != 0 && self.0 & flag == flag
+    }
+
+    pub fn grant(self, flag: u64) -> Self {
+        AiCapability(self.0 | flag)
+    }
+
+    pub fn revoke(self, flag: u64) -> Self {
+        AiCapability(self.0 & !flag)
+    }
+}
+
+// Admin-managed catalog entry for an agent or a voice
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CatalogEntry {
+    pub id: String,
+    pub display_name: String,
+    pub enabled: bool,
+}
+
+impl ic_stable_structures::Storable for CatalogEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+}
+
+// Tombstone of a soft-deleted principal: agent_id/voice_id are those of its default agent,
+// `configs` holds every agent config so restore can bring them all back
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeletedAiConfig {
+    pub principal_id: String,
+    pub deleted_at: u64,
+    pub agent_id: String,
+    pub voice_id: String,
+    pub deleted_by: String,
+    pub configs: Vec<UserAiConfig>,
+}
+
+// Tombstone shape stored before configs had capabilities
+#[derive(CandidType, Deserialize)]
+struct UncappedDeletedAiConfig {
+    principal_id: String,
+    deleted_at: u64,
+    agent_id: String,
+    voice_id: String,
+    deleted_by: String,
+    configs: Vec<UncappedUserAiConfig>,
+}
+
+impl ic_stable_structures::Storable for DeletedAiConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        if let Ok(tombstone) = Decode!(bytes.as_ref(), Self) {
+            return tombstone;
+        }
+        let old = Decode!(bytes.as_ref(), UncappedDeletedAiConfig).unwrap();
+        Self {
+            principal_id: old.principal_id,
+            deleted_at: old.deleted_at,
+            agent_id: old.agent_id,
+            voice_id: old.voice_id,
+            deleted_by: old.deleted_by,
+            configs: old.configs.into_iter().map(UserAiConfig::from).collect(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Server-side cap on admin listing page size
+pub const MAX_AI_CONFIG_PAGE_SIZE: u64 = 100;
+
+// Secondary index key: (indexed value, principal_id, agent_id); the indexed value is
+// the voice_id or agent_id depending on the index
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AiConfigIndexKey {
+    pub value: String,
+    pub principal_id: String,
+    pub agent_id: String,
+}
+
+impl ic_stable_structures::Storable for AiConfigIndexKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(&self.value, &self.principal_id, &self.agent_id).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (value, principal_id, agent_id) = Decode!(bytes.as_ref(), String, String, String).unwrap();
+        Self { value, principal_id, agent_id }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 600,
+        is_fixed_size: false,
+    };
+}
+
+// Key for the config history log: (principal_id, seq), seq increasing per principal
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AiConfigHistoryKey {
+    pub principal_id: String,
+    pub seq: u64,
+}
+
+impl ic_stable_structures::Storable for AiConfigHistoryKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(&self.principal_id, &self.seq).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (principal_id, seq) = Decode!(bytes.as_ref(), String, u64).unwrap();
+        Self { principal_id, seq }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 300,
+        is_fixed_size: false,
+    };
 }
 
 // Key for user AI config lookup by principal_id
@@ -38,56 +272,1464 @@ impl ic_stable_structures::Storable for PrincipalKey {
     };
 }
 
-impl ic_stable_structures::Storable for UserAiConfig {
+// Key for user AI config lookup by (principal_id, agent_id)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AgentConfigKey {
+    pub principal_id: String,
+    pub agent_id: String,
+}
+
+impl ic_stable_structures::Storable for AgentConfigKey {
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(Encode!(&self.principal_id, &self.agent_id).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        let (principal_id, agent_id) = Decode!(bytes.as_ref(), String, String).unwrap();
+        Self { principal_id, agent_id }
     }
 
     const BOUND: Bound = Bound::Bounded {
-        max_size: 1000,
+        max_size: 400,
         is_fixed_size: false,
     };
 }
 
-// Get user AI config by principal_id
-pub fn get_user_ai_config(principal_id: String) -> Option<UserAiConfig> {
-    USER_AI_CONFIG.with(|config_map| {
-        let key = PrincipalKey { principal_id };
-        config_map.borrow().get(&key)
-    })
+impl ic_stable_structures::Storable for UserAiConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        // Records without deleted_at or expires_at decode here too: candid reads a missing opt
+        // field as None, so they are live and never expire
+        if let Ok(config) = Decode!(bytes.as_ref(), Self) {
+            return config;
+        }
+        if let Ok(old) = Decode!(bytes.as_ref(), UncappedUserAiConfig) {
+            return old.into();
+        }
+        // Old records have no settings field
+        let old = Decode!(bytes.as_ref(), LegacyUserAiConfig).unwrap();
+        Self {
+            principal_id: old.principal_id,
+            agent_id: old.agent_id,
+            voice_id: old.voice_id,
+            is_default: old.is_default,
+            created_at: old.created_at,
+            updated_at: old.updated_at,
+            settings: Vec::new(),
+            capabilities: 0,
+            deleted_at: None,
+            expires_at: None,
+        }
+    }
+
+    // Unbounded: the struct keeps growing with optional fields and a fixed byte cap would
+    // eventually reject valid configs. Existing maps migrate to the V2 layout on load.
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Validate a single setting key/value
+fn validate_setting(key: &str, value: &str) -> Result<(), String> {
+    if key.is_empty() || key.len() > MAX_AI_CONFIG_SETTING_KEY_LEN {
+        return Err(format!("Invalid setting key: must be 1-{} bytes", MAX_AI_CONFIG_SETTING_KEY_LEN));
+    }
+    if !key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.') {
+        return Err(format!("Invalid setting key {}: only [a-zA-Z0-9_.-] allowed", key));
+    }
+    if value.len() > MAX_AI_CONFIG_SETTING_VALUE_LEN {
+        return Err(format!("Setting {} value exceeds {} bytes", key, MAX_AI_CONFIG_SETTING_VALUE_LEN));
+    }
+    Ok(())
+}
+
+// Validate the full settings list
+fn validate_settings(settings: &[(String, String)]) -> Result<(), String> {
+    if settings.len() > MAX_AI_CONFIG_SETTINGS {
+        return Err(format!("Too many settings: at most {} keys", MAX_AI_CONFIG_SETTINGS));
+    }
+    let mut seen = std::collections::BTreeSet::new();
+    for (key, value) in settings {
+        validate_setting(key, value)?;
+        if !seen.insert(key.as_str()) {
+            return Err(format!("Duplicate setting key: {}", key));
+        }
+    }
+    Ok(())
+}
+
+// Check that `caller` may modify configs of `principal_id`: the owner or an admin,
+// never the anonymous principal
+fn authorize_config_write(caller: Principal, principal_id: &str, is_admin: bool) -> Result<(), String> {
+    if caller == Principal::anonymous() {
+        return Err("NotAuthorized: anonymous caller".to_string());
+    }
+    let owner = Principal::from_text(principal_id)
+        .map_err(|e| format!("Invalid principal_id {}: {}", principal_id, e))?;
+    if caller != owner && !is_admin {
+        return Err(format!("NotAuthorized: caller {} cannot modify config of {}", caller, principal_id));
+    }
+    Ok(())
+}
+
+fn authorize_caller_for(principal_id: &str) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    authorize_config_write(caller, principal_id, ic_cdk::api::is_controller(&caller))
 }
 
-// Set or update user AI config
-pub fn set_user_ai_config(config: UserAiConfig) -> Result<(), String> {
-    USER_AI_CONFIG.with(|config_map| {
-        let key = PrincipalKey {
+// Move a legacy single-config entry into the per-agent map as the default agent
+fn migrate_legacy_config(principal_id: &str) {
+    let legacy = USER_AI_CONFIG.with(|config_map| {
+        config_map.borrow_mut().remove(&PrincipalKey { principal_id: principal_id.to_string() })
+    });
+
+    if let Some(mut config) = legacy {
+        let key = AgentConfigKey {
             principal_id: config.principal_id.clone(),
+            agent_id: config.agent_id.clone(),
         };
-        config_map.borrow_mut().insert(key, config);
-        Ok(())
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            if !map.contains_key(&key) {
+                config.is_default = Some(!principal_has_default(&map, principal_id));
+                index_config(&config);
+                map.insert(key, config);
+            }
+        });
+    }
+}
+
+fn principal_configs(
+    map: &StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    principal_id: &str,
+) -> Vec<UserAiConfig> {
+    let start = AgentConfigKey {
+        principal_id: principal_id.to_string(),
+        agent_id: String::new(),
+    };
+    map.range(start..)
+        .take_while(|(key, _)| key.principal_id == principal_id)
+        .map(|(_, config)| config)
+        .collect()
+}
+
+fn principal_has_default(
+    map: &StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    principal_id: &str,
+) -> bool {
+    principal_configs(map, principal_id)
+        .iter()
+        .any(|c| c.is_default == Some(true))
+}
+
+fn voice_index_key(config: &UserAiConfig) -> AiConfigIndexKey {
+    AiConfigIndexKey {
+        value: config.voice_id.clone(),
+        principal_id: config.principal_id.clone(),
+        agent_id: config.agent_id.clone(),
+    }
+}
+
+fn agent_index_key(config: &UserAiConfig) -> AiConfigIndexKey {
+    AiConfigIndexKey {
+        value: config.agent_id.clone(),
+        principal_id: config.principal_id.clone(),
+        agent_id: config.agent_id.clone(),
+    }
+}
+
+// Add a config to the voice/agent secondary indexes
+fn index_config(config: &UserAiConfig) {
+    AI_CONFIGS_BY_VOICE.with(|index| index.borrow_mut().insert(voice_index_key(config), ()));
+    AI_CONFIGS_BY_AGENT.with(|index| index.borrow_mut().insert(agent_index_key(config), ()));
+}
+
+// Remove a config from the voice/agent secondary indexes
+fn unindex_config(config: &UserAiConfig) {
+    AI_CONFIGS_BY_VOICE.with(|index| index.borrow_mut().remove(&voice_index_key(config)));
+    AI_CONFIGS_BY_AGENT.with(|index| index.borrow_mut().remove(&agent_index_key(config)));
+}
+
+fn require_controller(action: &str) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err(format!("Only controller can {}", action));
+    }
+    Ok(())
+}
+
+// Get per-principal agent limit
+pub fn get_max_agents_per_principal() -> u64 {
+    MAX_AGENTS_PER_PRINCIPAL.with(|cell| *cell.borrow().get())
+}
+
+// Set per-principal agent limit (controller only)
+pub fn set_max_agents_per_principal(limit: u64) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only controller can set agent limit".to_string());
+    }
+    if limit == 0 {
+        return Err("Agent limit must be at least 1".to_string());
+    }
+    MAX_AGENTS_PER_PRINCIPAL.with(|cell| {
+        cell.borrow_mut()
+            .set(limit)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store agent limit: {:?}", e))
+    })
+}
+
+// Copy all legacy single-config entries into the per-agent map (controller only)
+pub fn backfill_user_ai_configs() -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only controller can backfill AI configs".to_string());
+    }
+    let principals: Vec<String> = USER_AI_CONFIG.with(|config_map| {
+        config_map.borrow().iter().map(|(key, _)| key.principal_id).collect()
+    });
+    for principal_id in &principals {
+        migrate_legacy_config(principal_id);
+    }
+    Ok(principals.len() as u64)
+}
+
+// Whether a config's access ended at `now`
+fn is_expired(config: &UserAiConfig, now: u64) -> bool {
+    config.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+// Soft-delete a principal's configs inside an open borrow once its access expired; returns
+// the configs moved to the tombstone map, empty if it has not expired
+fn expire_principal_configs(
+    map: &mut StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    principal_id: &str,
+    now: u64,
+) -> Vec<UserAiConfig> {
+    if !principal_configs(map, principal_id).iter().any(|c| is_expired(c, now)) {
+        return Vec::new();
+    }
+    tombstone_user_ai_configs(map, principal_id, EXPIRED_DELETER, now).unwrap_or_default()
+}
+
+// Move a principal's configs to the tombstone map if its access expired. Reads call this
+// too: in a query the move is discarded with the call's other writes, but the expired
+// configs are still not returned, and the next update makes the move stick.
+fn expire_if_due(principal_id: &str) {
+    let has_expiry = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        principal_configs(&config_map.borrow(), principal_id).iter().any(|c| c.expires_at.is_some())
+    });
+    if !has_expiry {
+        return;
+    }
+    let now = ic_cdk::api::time();
+    let expired = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        expire_principal_configs(&mut config_map.borrow_mut(), principal_id, now)
+    });
+    for config in expired {
+        ic_cdk::println!("AI config {}/{} expired at {:?}", config.principal_id, config.agent_id, config.expires_at);
+        event_log::emit(EventKind::ConfigChanged {
+            principal_id: config.principal_id,
+            agent_id: config.agent_id,
+            deleted: true,
+        });
+    }
+}
+
+// List all AI configs of a principal
+pub fn list_user_ai_configs(principal_id: String) -> Vec<UserAiConfig> {
+    migrate_legacy_config(&principal_id);
+    expire_if_due(&principal_id);
+    USER_AI_AGENT_CONFIGS.with(|config_map| {
+        principal_configs(&config_map.borrow(), &principal_id)
     })
 }
 
-// Delete user AI config
+// Get AI config of a specific agent
+pub fn get_user_ai_config_by_agent(principal_id: String, agent_id: String) -> Option<UserAiConfig> {
+    migrate_legacy_config(&principal_id);
+    expire_if_due(&principal_id);
+    USER_AI_AGENT_CONFIGS.with(|config_map| {
+        config_map.borrow().get(&AgentConfigKey { principal_id, agent_id })
+    })
+}
+
+// Delete AI config of a specific agent; promotes another agent to default if needed
+pub fn delete_user_ai_config_by_agent(principal_id: String, agent_id: String) -> Result<(), String> {
+    authorize_caller_for(&principal_id)?;
+    migrate_legacy_config(&principal_id);
+    let removed = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        remove_agent_config(&mut config_map.borrow_mut(), &principal_id, agent_id)
+    })?;
+    event_log::emit(EventKind::ConfigChanged {
+        principal_id: removed.principal_id,
+        agent_id: removed.agent_id,
+        deleted: true,
+    });
+    Ok(())
+}
+
+// Remove one agent's config inside an open borrow of the per-agent map; the first remaining
+// agent becomes the default if the removed one was
+fn remove_agent_config(
+    map: &mut StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    principal_id: &str,
+    agent_id: String,
+) -> Result<UserAiConfig, String> {
+    let key = AgentConfigKey { principal_id: principal_id.to_string(), agent_id };
+    let removed = map.remove(&key).ok_or_else(|| "User AI config not found".to_string())?;
+    unindex_config(&removed);
+
+    if removed.is_default == Some(true) {
+        if let Some(mut next) = principal_configs(map, principal_id).into_iter().next() {
+            next.is_default = Some(true);
+            map.insert(
+                AgentConfigKey { principal_id: next.principal_id.clone(), agent_id: next.agent_id.clone() },
+                next,
+            );
+        }
+    }
+    Ok(removed)
+}
+
+// Get user AI config by principal_id (the default agent)
+pub fn get_user_ai_config(principal_id: String) -> Option<UserAiConfig> {
+    let configs = list_user_ai_configs(principal_id);
+    configs
+        .iter()
+        .find(|c| c.is_default == Some(true))
+        .or_else(|| configs.first())
+        .cloned()
+}
+
+// Append a config version to the principal's history, keeping the newest AI_CONFIG_HISTORY_LIMIT
+fn record_config_history(config: &UserAiConfig) {
+    USER_AI_CONFIG_HISTORY.with(|history| {
+        let mut map = history.borrow_mut();
+        let start = AiConfigHistoryKey { principal_id: config.principal_id.clone(), seq: 0 };
+        let keys: Vec<AiConfigHistoryKey> = map.range(start..)
+            .take_while(|(key, _)| key.principal_id == config.principal_id)
+            .map(|(key, _)| key)
+            .collect();
+
+        let next_seq = keys.last().map_or(0, |key| key.seq + 1);
+        map.insert(
+            AiConfigHistoryKey { principal_id: config.principal_id.clone(), seq: next_seq },
+            config.clone(),
+        );
+
+        let total = keys.len() as u64 + 1;
+        if total > AI_CONFIG_HISTORY_LIMIT {
+            for key in keys.into_iter().take((total - AI_CONFIG_HISTORY_LIMIT) as usize) {
+                map.remove(&key);
+            }
+        }
+    });
+}
+
+// Get config history of a principal, newest first
+pub fn get_user_ai_config_history(principal_id: String, limit: u64) -> Vec<UserAiConfig> {
+    let limit = limit.min(AI_CONFIG_HISTORY_LIMIT) as usize;
+    USER_AI_CONFIG_HISTORY.with(|history| {
+        let map = history.borrow();
+        let start = AiConfigHistoryKey { principal_id: principal_id.clone(), seq: 0 };
+        let mut versions: Vec<UserAiConfig> = map.range(start..)
+            .take_while(|(key, _)| key.principal_id == principal_id)
+            .map(|(_, config)| config)
+            .collect();
+        versions.reverse();
+        versions.truncate(limit);
+        versions
+    })
+}
+
+// Validate and store one config inside an open borrow of the per-agent map
+fn apply_user_ai_config(
+    map: &mut StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    mut config: UserAiConfig,
+    now: u64,
+    limit: u64,
+) -> Result<(), String> {
+    validate_settings(&config.settings)?;
+    AGENT_CATALOG.with(|c| check_catalog_ref(&c.borrow(), &config.agent_id, "UnknownAgent"))?;
+    VOICE_CATALOG.with(|c| check_catalog_ref(&c.borrow(), &config.voice_id, "UnknownVoice"))?;
+
+    let key = AgentConfigKey {
+        principal_id: config.principal_id.clone(),
+        agent_id: config.agent_id.clone(),
+    };
+    let existing = map.get(&key);
+    let mut others: Vec<UserAiConfig> = principal_configs(map, &config.principal_id)
+        .into_iter()
+        .filter(|c| c.agent_id != config.agent_id)
+        .collect();
+
+    if existing.is_none() && others.len() as u64 >= limit {
+        return Err(format!("Agent limit reached: at most {} agents per principal", limit));
+    }
+
+    // Expiry is per principal: the written one applies to all of its configs
+    for other in others.iter_mut().filter(|c| c.expires_at != config.expires_at) {
+        other.expires_at = config.expires_at;
+        map.insert(
+            AgentConfigKey { principal_id: other.principal_id.clone(), agent_id: other.agent_id.clone() },
+            other.clone(),
+        );
+    }
+
+    config.created_at = existing.as_ref().and_then(|c| c.created_at).or(Some(now));
+    config.updated_at = Some(now);
+    // Capabilities change only through grant/revoke_ai_capability
+    config.capabilities = existing.as_ref().or(others.first()).map_or(0, |c| c.capabilities);
+    config.deleted_at = None;
+
+    let keep_default = config.is_default.is_none()
+        && existing.as_ref().map_or(false, |c| c.is_default == Some(true));
+    let make_default = config.is_default == Some(true)
+        || keep_default
+        || !others.iter().any(|c| c.is_default == Some(true));
+    config.is_default = Some(make_default);
+
+    if make_default {
+        for mut other in others.into_iter().filter(|c| c.is_default == Some(true)) {
+            other.is_default = Some(false);
+            map.insert(
+                AgentConfigKey { principal_id: other.principal_id.clone(), agent_id: other.agent_id.clone() },
+                other,
+            );
+        }
+    }
+
+    if let Some(old) = &existing {
+        unindex_config(old);
+    }
+    index_config(&config);
+    record_config_history(&config);
+    map.insert(key, config);
+    Ok(())
+}
+
+// Keep an owner's write from changing its expiry or bringing back access that expired;
+// only admins set expires_at
+fn check_owner_expiry(config: &mut UserAiConfig) -> Result<(), String> {
+    let expired = DELETED_AI_CONFIGS.with(|deleted| deleted.borrow().get(&config.principal_id))
+        .is_some_and(|tombstone| tombstone.deleted_by == EXPIRED_DELETER);
+    let live = USER_AI_AGENT_CONFIGS.with(|config_map| principal_configs(&config_map.borrow(), &config.principal_id));
+    if live.is_empty() && expired {
+        return Err(format!("AI access of {} has expired; an admin must renew it", config.principal_id));
+    }
+    config.expires_at = live.first().and_then(|c| c.expires_at);
+    Ok(())
+}
+
+// Set or update user AI config for (principal_id, agent_id)
+pub fn set_user_ai_config(mut config: UserAiConfig) -> Result<(), String> {
+    authorize_caller_for(&config.principal_id)?;
+    migrate_legacy_config(&config.principal_id);
+    expire_if_due(&config.principal_id);
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        check_owner_expiry(&mut config)?;
+    }
+    let (principal_id, agent_id) = (config.principal_id.clone(), config.agent_id.clone());
+    let now = ic_cdk::api::time();
+    let limit = get_max_agents_per_principal();
+    USER_AI_AGENT_CONFIGS.with(|config_map| {
+        apply_user_ai_config(&mut config_map.borrow_mut(), config, now, limit)
+    })?;
+    event_log::emit(EventKind::ConfigChanged { principal_id, agent_id, deleted: false });
+    Ok(())
+}
+
+// Set one setting on the principal's default config
+pub fn set_ai_config_setting(principal_id: String, key: String, value: String) -> Result<(), String> {
+    authorize_caller_for(&principal_id)?;
+    validate_setting(&key, &value)?;
+    let mut config = get_user_ai_config(principal_id).ok_or_else(|| "User AI config not found".to_string())?;
+    match config.settings.iter_mut().find(|(k, _)| *k == key) {
+        Some(entry) => entry.1 = value,
+        None => config.settings.push((key, value)),
+    }
+    config.is_default = None;
+    set_user_ai_config(config)
+}
+
+// Remove one setting from the principal's default config
+pub fn remove_ai_config_setting(principal_id: String, key: String) -> Result<(), String> {
+    authorize_caller_for(&principal_id)?;
+    let mut config = get_user_ai_config(principal_id).ok_or_else(|| "User AI config not found".to_string())?;
+    let before = config.settings.len();
+    config.settings.retain(|(k, _)| *k != key);
+    if config.settings.len() == before {
+        return Err(format!("Setting {} not found", key));
+    }
+    config.is_default = None;
+    set_user_ai_config(config)
+}
+
+// Delete all AI configs of a principal. The delete is soft: restore_user_ai_config brings the
+// configs back until purge_deleted_ai_configs removes them.
 pub fn delete_user_ai_config(principal_id: String) -> Result<(), String> {
-    USER_AI_CONFIG.with(|config_map| {
-        let key = PrincipalKey { principal_id };
-        if config_map.borrow_mut().remove(&key).is_some() {
-            Ok(())
-        } else {
-            Err("User AI config not found".to_string())
+    soft_delete_user_ai_config(principal_id)
+}
+
+// Move all AI configs of a principal to the tombstone map; get/list no longer see them.
+// A second soft delete replaces the earlier tombstone (old versions stay in the config history).
+pub fn soft_delete_user_ai_config(principal_id: String) -> Result<(), String> {
+    authorize_caller_for(&principal_id)?;
+    migrate_legacy_config(&principal_id);
+    let deleted_by = ic_cdk::caller().to_text();
+    let now = ic_cdk::api::time();
+    let removed = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        tombstone_user_ai_configs(&mut config_map.borrow_mut(), &principal_id, &deleted_by, now)
+    })?;
+    for config in removed {
+        event_log::emit(EventKind::ConfigChanged {
+            principal_id: principal_id.clone(),
+            agent_id: config.agent_id,
+            deleted: true,
+        });
+    }
+    Ok(())
+}
+
+// Move a principal's configs from an open borrow of the per-agent map to the tombstone map
+fn tombstone_user_ai_configs(
+    map: &mut StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    principal_id: &str,
+    deleted_by: &str,
+    now: u64,
+) -> Result<Vec<UserAiConfig>, String> {
+    let configs = principal_configs(map, principal_id);
+    let default = configs
+        .iter()
+        .find(|c| c.is_default == Some(true))
+        .or_else(|| configs.first())
+        .cloned()
+        .ok_or_else(|| "User AI config not found".to_string())?;
+
+    for config in &configs {
+        unindex_config(config);
+        map.remove(&AgentConfigKey { principal_id: principal_id.to_string(), agent_id: config.agent_id.clone() });
+    }
+
+    let tombstone = DeletedAiConfig {
+        principal_id: principal_id.to_string(),
+        deleted_at: now,
+        agent_id: default.agent_id,
+        voice_id: default.voice_id,
+        deleted_by: deleted_by.to_string(),
+        configs: configs.iter().cloned().map(|config| UserAiConfig { deleted_at: Some(now), ..config }).collect(),
+    };
+    DELETED_AI_CONFIGS.with(|deleted| deleted.borrow_mut().insert(principal_id.to_string(), tombstone));
+    Ok(configs)
+}
+
+// Log one summary line and one event for a batch instead of one per config
+fn record_batch_summary(operation: &str, results: &[Result<(), String>]) {
+    let succeeded = results.iter().filter(|r| r.is_ok()).count() as u64;
+    let total = results.len() as u64;
+    ic_cdk::println!("Batch {}: {} configs, {} succeeded, {} failed", operation, total, succeeded, total - succeeded);
+    event_log::emit(EventKind::ConfigBatchApplied {
+        operation: operation.to_string(),
+        total,
+        succeeded,
+        failed: total - succeeded,
+    });
+}
+
+fn check_batch_size(len: usize) -> Result<(), String> {
+    if len > MAX_AI_CONFIG_BATCH {
+        return Err(format!("Batch too large: {} entries, at most {}", len, MAX_AI_CONFIG_BATCH));
+    }
+    Ok(())
+}
+
+// Set many configs in one pass over the per-agent map (controller only); results are in input order
+pub fn set_user_ai_configs_batch(configs: Vec<UserAiConfig>) -> Result<Vec<Result<(), String>>, String> {
+    require_controller("batch update AI configs")?;
+    check_batch_size(configs.len())?;
+    for config in &configs {
+        migrate_legacy_config(&config.principal_id);
+    }
+    let now = ic_cdk::api::time();
+    let limit = get_max_agents_per_principal();
+
+    let results: Vec<Result<(), String>> = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        let mut map = config_map.borrow_mut();
+        configs.into_iter()
+            .map(|config| {
+                Principal::from_text(&config.principal_id)
+                    .map_err(|e| format!("Invalid principal_id {}: {}", config.principal_id, e))?;
+                apply_user_ai_config(&mut map, config, now, limit)
+            })
+            .collect()
+    });
+    record_batch_summary("update", &results);
+    Ok(results)
+}
+
+// Soft-delete the configs of many principals in one pass (controller only); results are in input order
+pub fn delete_user_ai_configs_batch(principal_ids: Vec<String>) -> Result<Vec<Result<(), String>>, String> {
+    require_controller("batch delete AI configs")?;
+    check_batch_size(principal_ids.len())?;
+    for principal_id in &principal_ids {
+        migrate_legacy_config(principal_id);
+    }
+    let deleted_by = ic_cdk::caller().to_text();
+    let now = ic_cdk::api::time();
+
+    let results: Vec<Result<(), String>> = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        let mut map = config_map.borrow_mut();
+        principal_ids.iter()
+            .map(|principal_id| tombstone_user_ai_configs(&mut map, principal_id, &deleted_by, now).map(|_| ()))
+            .collect()
+    });
+    record_batch_summary("soft delete", &results);
+    Ok(results)
+}
+
+// Move a soft-deleted principal's configs back to the live map (owner or admin)
+pub fn restore_user_ai_config(principal_id: String) -> Result<(), String> {
+    authorize_caller_for(&principal_id)?;
+    if has_user_ai_config(principal_id.clone()) {
+        return Err(format!("Principal {} has live AI configs; delete them before restoring", principal_id));
+    }
+    // Expired access comes back through an admin, who restores and then renews it
+    let expired = DELETED_AI_CONFIGS.with(|deleted| deleted.borrow().get(&principal_id))
+        .is_some_and(|tombstone| tombstone.deleted_by == EXPIRED_DELETER);
+    if expired && !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err(format!("AI access of {} has expired; an admin must renew it", principal_id));
+    }
+    let tombstone = DELETED_AI_CONFIGS.with(|deleted| deleted.borrow_mut().remove(&principal_id))
+        .ok_or_else(|| format!("No deleted AI config for {}", principal_id))?;
+
+    USER_AI_AGENT_CONFIGS.with(|config_map| {
+        let mut map = config_map.borrow_mut();
+        for config in tombstone.configs {
+            let config = UserAiConfig { deleted_at: None, ..config };
+            index_config(&config);
+            event_log::emit(EventKind::ConfigChanged {
+                principal_id: principal_id.clone(),
+                agent_id: config.agent_id.clone(),
+                deleted: false,
+            });
+            map.insert(AgentConfigKey { principal_id: principal_id.clone(), agent_id: config.agent_id.clone() }, config);
         }
+    });
+    Ok(())
+}
+
+// List soft-deleted configs (controller only)
+pub fn list_deleted_ai_configs() -> Result<Vec<DeletedAiConfig>, String> {
+    require_controller("list deleted AI configs")?;
+    Ok(DELETED_AI_CONFIGS.with(|deleted| deleted.borrow().iter().map(|(_, tombstone)| tombstone).collect()))
+}
+
+// Configs of every tombstone, in principal order
+fn deleted_ai_configs() -> Vec<UserAiConfig> {
+    DELETED_AI_CONFIGS.with(|deleted| {
+        deleted.borrow().iter().flat_map(|(_, tombstone)| tombstone.configs).collect()
     })
 }
 
+// Remove the tombstones deleted before `older_than_ts`; returns how many were removed
+fn purge_tombstones(older_than_ts: u64) -> u64 {
+    DELETED_AI_CONFIGS.with(|deleted| {
+        let mut map = deleted.borrow_mut();
+        let expired: Vec<String> = map.iter()
+            .filter(|(_, tombstone)| tombstone.deleted_at < older_than_ts)
+            .map(|(principal_id, _)| principal_id)
+            .collect();
+        for principal_id in &expired {
+            map.remove(principal_id);
+        }
+        expired.len() as u64
+    })
+}
+
+// Permanently remove configs soft-deleted before `older_than_ts` (controller only)
+pub fn purge_deleted_ai_configs(older_than_ts: u64) -> Result<u64, String> {
+    require_controller("purge deleted AI configs")?;
+    let purged = purge_tombstones(older_than_ts);
+    ic_cdk::println!("Purged {} deleted AI config tombstones older than {}", purged, older_than_ts);
+    Ok(purged)
+}
+
+// Set or clear capability bits on every config of a principal inside an open borrow
+fn update_capabilities(
+    map: &mut StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    principal_id: &str,
+    apply: impl Fn(AiCapability) -> AiCapability,
+) -> Result<Vec<String>, String> {
+    let configs = principal_configs(map, principal_id);
+    if configs.is_empty() {
+        return Err("User AI config not found".to_string());
+    }
+    let mut agent_ids = Vec::new();
+    for mut config in configs {
+        config.capabilities = apply(AiCapability(config.capabilities)).0;
+        agent_ids.push(config.agent_id.clone());
+        map.insert(AgentConfigKey { principal_id: principal_id.to_string(), agent_id: config.agent_id.clone() }, config);
+    }
+    Ok(agent_ids)
+}
+
+fn change_capability(principal_id: String, capability: u64, grant: bool) -> Result<(), String> {
+    AiCapability::validate(capability)?;
+    migrate_legacy_config(&principal_id);
+    let agent_ids = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        update_capabilities(&mut config_map.borrow_mut(), &principal_id, |caps| {
+            if grant { caps.grant(capability) } else { caps.revoke(capability) }
+        })
+    })?;
+    for agent_id in agent_ids {
+        event_log::emit(EventKind::ConfigChanged { principal_id: principal_id.clone(), agent_id, deleted: false });
+    }
+    Ok(())
+}
+
+// Grant capability bits to a principal (controller only)
+pub fn grant_ai_capability(principal_id: String, capability: u64) -> Result<(), String> {
+    require_controller("grant AI capabilities")?;
+    change_capability(principal_id, capability, true)
+}
+
+// Revoke capability bits from a principal (controller only)
+pub fn revoke_ai_capability(principal_id: String, capability: u64) -> Result<(), String> {
+    require_controller("revoke AI capabilities")?;
+    change_capability(principal_id, capability, false)
+}
+
+// Push a principal's expiry back by `extend_ns` on all of its configs (controller only). It
+// does not sweep expired configs first, so an expired principal restored by an admin can be
+// renewed before the next read moves it back.
+pub fn renew_ai_config(principal_id: String, extend_ns: u64) -> Result<(), String> {
+    require_controller("renew AI configs")?;
+    migrate_legacy_config(&principal_id);
+    let agent_ids = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        let mut map = config_map.borrow_mut();
+        let configs = principal_configs(&map, &principal_id);
+        let expires_at = configs.first()
+            .ok_or_else(|| "User AI config not found".to_string())?
+            .expires_at
+            .ok_or_else(|| format!("AI config of {} never expires", principal_id))?;
+        let renewed = Some(expires_at.saturating_add(extend_ns));
+        let mut agent_ids = Vec::new();
+        for config in configs {
+            agent_ids.push(config.agent_id.clone());
+            map.insert(
+                AgentConfigKey { principal_id: principal_id.clone(), agent_id: config.agent_id.clone() },
+                UserAiConfig { expires_at: renewed, ..config },
+            );
+        }
+        Ok::<_, String>(agent_ids)
+    })?;
+    for agent_id in agent_ids {
+        event_log::emit(EventKind::ConfigChanged { principal_id: principal_id.clone(), agent_id, deleted: false });
+    }
+    Ok(())
+}
+
+// Configs expiring at or before `now + within_ns`, soonest first
+fn expiring_configs(
+    map: &StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>,
+    now: u64,
+    within_ns: u64,
+) -> Vec<UserAiConfig> {
+    let until = now.saturating_add(within_ns);
+    let mut expiring: Vec<UserAiConfig> = map.iter()
+        .map(|(_, config)| config)
+        .filter(|config| config.expires_at.is_some_and(|expires_at| expires_at <= until))
+        .collect();
+    expiring.sort_by_key(|config| config.expires_at);
+    expiring
+}
+
+// Configs whose access ends within `within_ns` from now, for renewal reminders; ones already
+// past expiry but not yet moved to the tombstone map are included (controller only)
+pub fn list_expiring_ai_configs(within_ns: u64) -> Result<Vec<UserAiConfig>, String> {
+    require_controller("list expiring AI configs")?;
+    let now = ic_cdk::api::time();
+    Ok(USER_AI_AGENT_CONFIGS.with(|config_map| expiring_configs(&config_map.borrow(), now, within_ns)))
+}
+
+// Whether a principal holds every bit of `capability`; false without a config or for
+// unassigned bits
+pub fn check_ai_capability(principal_id: String, capability: u64) -> bool {
+    AiCapability::validate(capability).is_ok()
+        && get_user_ai_config(principal_id).map_or(false, |config| AiCapability(config.capabilities).has(capability))
+}
+
 // Check if user has AI config
 pub fn has_user_ai_config(principal_id: String) -> bool {
-    USER_AI_CONFIG.with(|config_map| {
-        let key = PrincipalKey { principal_id };
-        config_map.borrow().contains_key(&key)
+    !list_user_ai_configs(principal_id).is_empty()
+}
+
+// A referenced id must exist and be enabled. An empty catalog is not enforced,
+// so configs keep working until the admin has populated it.
+fn check_catalog_ref(
+    catalog: &StableBTreeMap<String, CatalogEntry, Memory>,
+    id: &str,
+    error_prefix: &str,
+) -> Result<(), String> {
+    if catalog.is_empty() {
+        return Ok(());
+    }
+    match catalog.get(&id.to_string()) {
+        Some(entry) if entry.enabled => Ok(()),
+        Some(_) => Err(format!("{}: {} is disabled", error_prefix, id)),
+        None => Err(format!("{}: {} is not in the catalog", error_prefix, id)),
+    }
+}
+
+fn upsert_catalog_entry(
+    catalog: &mut StableBTreeMap<String, CatalogEntry, Memory>,
+    entry: CatalogEntry,
+) -> Result<(), String> {
+    if entry.id.is_empty() {
+        return Err("Catalog id is required".to_string());
+    }
+    catalog.insert(entry.id.clone(), entry);
+    Ok(())
+}
+
+fn disable_catalog_entry(
+    catalog: &mut StableBTreeMap<String, CatalogEntry, Memory>,
+    id: String,
+) -> Result<(), String> {
+    let mut entry = catalog.get(&id).ok_or_else(|| format!("Catalog entry {} not found", id))?;
+    entry.enabled = false;
+    catalog.insert(id, entry);
+    Ok(())
+}
+
+// Add or update an agent catalog entry (controller only)
+pub fn add_agent_catalog_entry(entry: CatalogEntry) -> Result<(), String> {
+    require_controller("manage the agent catalog")?;
+    AGENT_CATALOG.with(|c| upsert_catalog_entry(&mut c.borrow_mut(), entry))
+}
+
+// Disable an agent catalog entry; existing configs are kept (controller only)
+pub fn disable_agent_catalog_entry(id: String) -> Result<(), String> {
+    require_controller("manage the agent catalog")?;
+    AGENT_CATALOG.with(|c| disable_catalog_entry(&mut c.borrow_mut(), id))
+}
+
+pub fn list_agent_catalog() -> Vec<CatalogEntry> {
+    AGENT_CATALOG.with(|c| c.borrow().iter().map(|(_, entry)| entry).collect())
+}
+
+// Add or update a voice catalog entry (controller only)
+pub fn add_voice_catalog_entry(entry: CatalogEntry) -> Result<(), String> {
+    require_controller("manage the voice catalog")?;
+    VOICE_CATALOG.with(|c| upsert_catalog_entry(&mut c.borrow_mut(), entry))
+}
+
+// Disable a voice catalog entry; existing configs are kept (controller only)
+pub fn disable_voice_catalog_entry(id: String) -> Result<(), String> {
+    require_controller("manage the voice catalog")?;
+    VOICE_CATALOG.with(|c| disable_catalog_entry(&mut c.borrow_mut(), id))
+}
+
+pub fn list_voice_catalog() -> Vec<CatalogEntry> {
+    VOICE_CATALOG.with(|c| c.borrow().iter().map(|(_, entry)| entry).collect())
+}
+
+// Check a principal's configs against the catalogs; returns one message per problem,
+// e.g. a config still pointing at a retired voice. Empty means all references are valid.
+pub fn validate_user_ai_config(principal_id: String) -> Vec<String> {
+    let mut issues = Vec::new();
+    for config in list_user_ai_configs(principal_id) {
+        if let Err(e) = AGENT_CATALOG.with(|c| check_catalog_ref(&c.borrow(), &config.agent_id, "UnknownAgent")) {
+            issues.push(e);
+        }
+        if let Err(e) = VOICE_CATALOG.with(|c| check_catalog_ref(&c.borrow(), &config.voice_id, "UnknownVoice")) {
+            issues.push(format!("agent {}: {}", config.agent_id, e));
+        }
+    }
+    issues
+}
+
+// List all AI configs in (principal_id, agent_id) order, followed by the soft-deleted ones
+// if `include_deleted` (controller only)
+pub fn list_ai_configs(offset: u64, limit: u64, include_deleted: bool) -> Result<Vec<UserAiConfig>, String> {
+    require_controller("list AI configs")?;
+    let limit = limit.min(MAX_AI_CONFIG_PAGE_SIZE) as usize;
+    let deleted = if include_deleted { deleted_ai_configs() } else { Vec::new() };
+    Ok(USER_AI_AGENT_CONFIGS.with(|config_map| {
+        config_map.borrow()
+            .iter()
+            .map(|(_, config)| config)
+            .chain(deleted)
+            .skip(offset as usize)
+            .take(limit)
+            .collect()
+    }))
+}
+
+// Count all AI configs (controller only)
+pub fn count_ai_configs() -> Result<u64, String> {
+    require_controller("count AI configs")?;
+    Ok(USER_AI_AGENT_CONFIGS.with(|config_map| config_map.borrow().len()))
+}
+
+// Resolve a page of index entries for `value` into configs. Soft-deleted configs are not
+// indexed; `deleted` are scanned for `field` == `value` and follow the live ones.
+fn find_ai_configs_in_index(
+    index: &StableBTreeMap<AiConfigIndexKey, (), Memory>,
+    value: String,
+    offset: u64,
+    limit: u64,
+    deleted: Vec<UserAiConfig>,
+    field: fn(&UserAiConfig) -> &str,
+) -> Vec<UserAiConfig> {
+    let limit = limit.min(MAX_AI_CONFIG_PAGE_SIZE) as usize;
+    let start = AiConfigIndexKey { value: value.clone(), principal_id: String::new(), agent_id: String::new() };
+    USER_AI_AGENT_CONFIGS.with(|config_map| {
+        let map = config_map.borrow();
+        index.range(start..)
+            .take_while(|(key, _)| key.value == value)
+            .filter_map(|(key, _)| map.get(&AgentConfigKey { principal_id: key.principal_id, agent_id: key.agent_id }))
+            .chain(deleted.into_iter().filter(|config| field(config) == value))
+            .skip(offset as usize)
+            .take(limit)
+            .collect()
     })
 }
+
+// Find AI configs using a voice, soft-deleted ones too if `include_deleted` (controller only)
+pub fn find_ai_configs_by_voice(voice_id: String, offset: u64, limit: u64, include_deleted: bool) -> Result<Vec<UserAiConfig>, String> {
+    require_controller("search AI configs")?;
+    let deleted = if include_deleted { deleted_ai_configs() } else { Vec::new() };
+    Ok(AI_CONFIGS_BY_VOICE.with(|index| {
+        find_ai_configs_in_index(&index.borrow(), voice_id, offset, limit, deleted, |c| &c.voice_id)
+    }))
+}
+
+// Find AI configs of an agent, soft-deleted ones too if `include_deleted` (controller only)
+pub fn find_ai_configs_by_agent(agent_id: String, offset: u64, limit: u64, include_deleted: bool) -> Result<Vec<UserAiConfig>, String> {
+    require_controller("search AI configs")?;
+    let deleted = if include_deleted { deleted_ai_configs() } else { Vec::new() };
+    Ok(AI_CONFIGS_BY_AGENT.with(|index| {
+        find_ai_configs_in_index(&index.borrow(), agent_id, offset, limit, deleted, |c| &c.agent_id)
+    }))
+}
+
+// Rebuild the voice/agent indexes from stored configs (controller only)
+pub fn rebuild_ai_config_indexes() -> Result<u64, String> {
+    require_controller("rebuild AI config indexes")?;
+    AI_CONFIGS_BY_VOICE.with(|index| {
+        let mut index = index.borrow_mut();
+        let keys: Vec<AiConfigIndexKey> = index.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    AI_CONFIGS_BY_AGENT.with(|index| {
+        let mut index = index.borrow_mut();
+        let keys: Vec<AiConfigIndexKey> = index.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+    let configs: Vec<UserAiConfig> = USER_AI_AGENT_CONFIGS.with(|config_map| {
+        config_map.borrow().iter().map(|(_, config)| config).collect()
+    });
+    for config in &configs {
+        index_config(config);
+    }
+    Ok(configs.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "2vxsx-fae";
+
+    fn owner() -> Principal {
+        Principal::from_slice(&[1, 2, 3, 4])
+    }
+
+    fn stranger() -> Principal {
+        Principal::from_slice(&[9, 9, 9, 9])
+    }
+
+    #[test]
+    fn test_owner_can_modify_own_config() {
+        let id = owner().to_text();
+        assert!(authorize_config_write(owner(), &id, false).is_ok());
+    }
+
+    #[test]
+    fn test_admin_can_modify_any_config() {
+        let id = owner().to_text();
+        assert!(authorize_config_write(stranger(), &id, true).is_ok());
+    }
+
+    #[test]
+    fn test_anonymous_is_rejected() {
+        let err = authorize_config_write(Principal::anonymous(), OWNER, false).unwrap_err();
+        assert!(err.starts_with("NotAuthorized"));
+        // Even for its own principal id or with admin rights
+        assert!(authorize_config_write(Principal::anonymous(), OWNER, true).is_err());
+    }
+
+    #[test]
+    fn test_stranger_is_rejected() {
+        let id = owner().to_text();
+        let err = authorize_config_write(stranger(), &id, false).unwrap_err();
+        assert!(err.starts_with("NotAuthorized"));
+    }
+
+    #[derive(CandidType)]
+    struct OriginalUserAiConfig {
+        principal_id: String,
+        agent_id: String,
+        voice_id: String,
+    }
+
+    #[test]
+    fn test_legacy_config_decodes_without_timestamps() {
+        use ic_stable_structures::Storable;
+        let legacy = OriginalUserAiConfig {
+            principal_id: OWNER.to_string(),
+            agent_id: "agent".to_string(),
+            voice_id: "voice".to_string(),
+        };
+        let bytes = Encode!(&legacy).unwrap();
+        let config = UserAiConfig::from_bytes(Cow::Owned(bytes));
+        assert_eq!(config.agent_id, "agent");
+        assert_eq!(config.voice_id, "voice");
+        assert_eq!(config.is_default, None);
+        assert_eq!(config.created_at, None);
+        assert_eq!(config.updated_at, None);
+        assert!(config.settings.is_empty());
+    }
+
+    fn max_settings() -> Vec<(String, String)> {
+        (0..MAX_AI_CONFIG_SETTINGS)
+            .map(|i| (format!("{:0>64}", i), "v".repeat(MAX_AI_CONFIG_SETTING_VALUE_LEN)))
+            .collect()
+    }
+
+    #[test]
+    fn test_settings_at_limits_round_trip() {
+        use ic_stable_structures::Storable;
+        let settings = max_settings();
+        assert!(validate_settings(&settings).is_ok());
+        let config = UserAiConfig {
+            principal_id: OWNER.to_string(),
+            agent_id: "agent".to_string(),
+            voice_id: "voice".to_string(),
+            is_default: Some(true),
+            created_at: Some(1),
+            updated_at: Some(2),
+            settings,
+            capabilities: AiCapability::VOICE_CLONE,
+            deleted_at: None,
+            expires_at: None,
+        };
+        let decoded = UserAiConfig::from_bytes(config.to_bytes());
+        assert_eq!(decoded, config);
+    }
+
+    #[test]
+    fn test_voice_index_lookup_pages() {
+        let voice = "voice-index-test";
+        for i in 0..3 {
+            let config = UserAiConfig {
+                principal_id: format!("p{}", i),
+                agent_id: "agent".to_string(),
+                voice_id: voice.to_string(),
+                is_default: Some(true),
+                created_at: None,
+                updated_at: None,
+                settings: vec![],
+                capabilities: 0,
+                deleted_at: None,
+                expires_at: None,
+            };
+            index_config(&config);
+            USER_AI_AGENT_CONFIGS.with(|m| {
+                m.borrow_mut().insert(AgentConfigKey { principal_id: config.principal_id.clone(), agent_id: config.agent_id.clone() }, config)
+            });
+        }
+
+        let page = AI_CONFIGS_BY_VOICE.with(|index| find_ai_configs_in_index(&index.borrow(), voice.to_string(), 1, 10, Vec::new(), |c| &c.voice_id));
+        let ids: Vec<String> = page.into_iter().map(|c| c.principal_id).collect();
+        assert_eq!(ids, vec!["p1".to_string(), "p2".to_string()]);
+
+        // Dropping p0 from the index hides it even though the config row still exists
+        let stale = USER_AI_AGENT_CONFIGS.with(|m| {
+            m.borrow().get(&AgentConfigKey { principal_id: "p0".to_string(), agent_id: "agent".to_string() })
+        }).unwrap();
+        unindex_config(&stale);
+        let all = AI_CONFIGS_BY_VOICE.with(|index| find_ai_configs_in_index(&index.borrow(), voice.to_string(), 0, u64::MAX, Vec::new(), |c| &c.voice_id));
+        assert_eq!(all.len(), 2);
+        assert!(AI_CONFIGS_BY_VOICE.with(|index| find_ai_configs_in_index(&index.borrow(), "other".to_string(), 0, 10, Vec::new(), |c| &c.voice_id)).is_empty());
+
+        // Deleted configs follow the live ones when included
+        let deleted = vec![UserAiConfig { voice_id: "other".to_string(), ..stale.clone() }, stale];
+        let page = AI_CONFIGS_BY_VOICE.with(|index| find_ai_configs_in_index(&index.borrow(), voice.to_string(), 1, 10, deleted, |c| &c.voice_id));
+        let ids: Vec<String> = page.into_iter().map(|c| c.principal_id).collect();
+        assert_eq!(ids, vec!["p2".to_string(), "p0".to_string()]);
+    }
+
+    #[test]
+    fn test_catalog_ref_checks() {
+        VOICE_CATALOG.with(|c| {
+            let mut catalog = c.borrow_mut();
+            // Not enforced until the catalog has entries
+            assert!(check_catalog_ref(&catalog, "anything", "UnknownVoice").is_ok());
+
+            upsert_catalog_entry(&mut catalog, CatalogEntry {
+                id: "alloy".to_string(),
+                display_name: "Alloy".to_string(),
+                enabled: true,
+            }).unwrap();
+            assert!(check_catalog_ref(&catalog, "alloy", "UnknownVoice").is_ok());
+            let err = check_catalog_ref(&catalog, "missing", "UnknownVoice").unwrap_err();
+            assert!(err.starts_with("UnknownVoice"));
+
+            disable_catalog_entry(&mut catalog, "alloy".to_string()).unwrap();
+            assert!(catalog.get(&"alloy".to_string()).is_some());
+            assert!(check_catalog_ref(&catalog, "alloy", "UnknownVoice").unwrap_err().contains("disabled"));
+            assert!(disable_catalog_entry(&mut catalog, "missing".to_string()).is_err());
+        });
+    }
+
+    #[test]
+    fn test_settings_over_limits_rejected() {
+        let mut settings = max_settings();
+        settings.push(("extra".to_string(), "v".to_string()));
+        assert!(validate_settings(&settings).is_err());
+
+        assert!(validate_setting("model", &"v".repeat(MAX_AI_CONFIG_SETTING_VALUE_LEN + 1)).is_err());
+        assert!(validate_setting(&"k".repeat(MAX_AI_CONFIG_SETTING_KEY_LEN + 1), "v").is_err());
+        assert!(validate_setting("", "v").is_err());
+        assert!(validate_setting("bad key", "v").is_err());
+        assert!(validate_setting("system.prompt_id", "v").is_ok());
+
+        let dup = vec![("a".to_string(), "1".to_string()), ("a".to_string(), "2".to_string())];
+        assert!(validate_settings(&dup).is_err());
+    }
+
+    #[test]
+    fn test_invalid_principal_id_is_rejected() {
+        let err = authorize_config_write(owner(), "not-a-principal", true).unwrap_err();
+        assert!(err.starts_with("Invalid principal_id"));
+    }
+
+    #[test]
+    fn test_batch_apply_and_tombstone_in_one_borrow() {
+        let principal_id = owner().to_text();
+        let config = |agent_id: &str| UserAiConfig {
+            principal_id: principal_id.clone(),
+            agent_id: agent_id.to_string(),
+            voice_id: "voice".to_string(),
+            is_default: None,
+            created_at: None,
+            updated_at: None,
+            settings: Vec::new(),
+            capabilities: 0,
+            deleted_at: None,
+            expires_at: None,
+        };
+        let mut bad = config("bad");
+        bad.settings = vec![("bad key".to_string(), "v".to_string())];
+
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            let results: Vec<Result<(), String>> = vec![config("a"), bad, config("b")]
+                .into_iter()
+                .map(|c| apply_user_ai_config(&mut map, c, 7, 10))
+                .collect();
+            assert!(results[0].is_ok() && results[1].is_err() && results[2].is_ok());
+            assert_eq!(principal_configs(&map, &principal_id).len(), 2);
+
+            let removed = tombstone_user_ai_configs(&mut map, &principal_id, "admin", 9).unwrap();
+            assert_eq!(removed.len(), 2);
+            assert!(principal_configs(&map, &principal_id).is_empty());
+            assert!(tombstone_user_ai_configs(&mut map, &principal_id, "admin", 9).is_err());
+        });
+
+        let tombstone = DELETED_AI_CONFIGS.with(|d| d.borrow().get(&principal_id)).unwrap();
+        assert_eq!((tombstone.agent_id.as_str(), tombstone.deleted_at), ("a", 9));
+        assert_eq!(tombstone.configs.len(), 2);
+        assert!(tombstone.configs.iter().all(|c| c.deleted_at == Some(9)));
+    }
+
+    fn agent_config(principal_id: &str, agent_id: &str, is_default: Option<bool>) -> UserAiConfig {
+        UserAiConfig {
+            principal_id: principal_id.to_string(),
+            agent_id: agent_id.to_string(),
+            voice_id: "voice".to_string(),
+            is_default,
+            created_at: None,
+            updated_at: None,
+            settings: Vec::new(),
+            capabilities: 0,
+            deleted_at: None,
+            expires_at: None,
+        }
+    }
+
+    // (agent_id, is_default) of every config of the principal
+    fn defaults(map: &StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>, principal_id: &str) -> Vec<(String, Option<bool>)> {
+        principal_configs(map, principal_id).into_iter().map(|c| (c.agent_id, c.is_default)).collect()
+    }
+
+    #[test]
+    fn test_legacy_config_moves_to_agent_map_as_default() {
+        let principal_id = owner().to_text();
+        let legacy = agent_config(&principal_id, "legacy", None);
+        USER_AI_CONFIG.with(|m| m.borrow_mut().insert(PrincipalKey { principal_id: principal_id.clone() }, legacy));
+
+        let config = get_user_ai_config(principal_id.clone()).unwrap();
+        assert_eq!((config.agent_id.as_str(), config.is_default), ("legacy", Some(true)));
+        assert!(USER_AI_CONFIG.with(|m| m.borrow().get(&PrincipalKey { principal_id: principal_id.clone() })).is_none());
+        assert_eq!(list_user_ai_configs(principal_id.clone()).len(), 1);
+
+        // A principal that already has a default keeps it; the legacy entry joins as non-default
+        let other = stranger().to_text();
+        USER_AI_AGENT_CONFIGS.with(|m| {
+            apply_user_ai_config(&mut m.borrow_mut(), agent_config(&other, "current", None), 1, 10)
+        }).unwrap();
+        USER_AI_CONFIG.with(|m| m.borrow_mut().insert(PrincipalKey { principal_id: other.clone() }, agent_config(&other, "legacy", None)));
+        assert_eq!(get_user_ai_config(other.clone()).unwrap().agent_id, "current");
+        assert_eq!(USER_AI_AGENT_CONFIGS.with(|m| defaults(&m.borrow(), &other)), vec![
+            ("current".to_string(), Some(true)),
+            ("legacy".to_string(), Some(false)),
+        ]);
+    }
+
+    #[test]
+    fn test_agent_limit_allows_updates_at_the_limit() {
+        let principal_id = owner().to_text();
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            apply_user_ai_config(&mut map, agent_config(&principal_id, "a", None), 1, 2).unwrap();
+            apply_user_ai_config(&mut map, agent_config(&principal_id, "b", None), 2, 2).unwrap();
+            let err = apply_user_ai_config(&mut map, agent_config(&principal_id, "c", None), 3, 2).unwrap_err();
+            assert!(err.contains("Agent limit reached"), "{}", err);
+
+            // Updating an agent the principal already has is not a new agent
+            let mut update = agent_config(&principal_id, "b", None);
+            update.voice_id = "other-voice".to_string();
+            apply_user_ai_config(&mut map, update, 4, 2).unwrap();
+            let configs = principal_configs(&map, &principal_id);
+            assert_eq!(configs.len(), 2);
+            assert_eq!((configs[1].voice_id.as_str(), configs[1].created_at, configs[1].updated_at), ("other-voice", Some(2), Some(4)));
+
+            // The limit is per principal
+            apply_user_ai_config(&mut map, agent_config(&stranger().to_text(), "c", None), 5, 2).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_single_default_and_promotion_on_delete() {
+        let principal_id = owner().to_text();
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            // The first agent becomes the default; later ones only when asked
+            for agent_id in ["a", "b", "c"] {
+                apply_user_ai_config(&mut map, agent_config(&principal_id, agent_id, None), 1, 10).unwrap();
+            }
+            apply_user_ai_config(&mut map, agent_config(&principal_id, "c", Some(true)), 2, 10).unwrap();
+            assert_eq!(defaults(&map, &principal_id), vec![
+                ("a".to_string(), Some(false)),
+                ("b".to_string(), Some(false)),
+                ("c".to_string(), Some(true)),
+            ]);
+
+            // Deleting a non-default agent leaves the default alone
+            remove_agent_config(&mut map, &principal_id, "b".to_string()).unwrap();
+            assert_eq!(defaults(&map, &principal_id), vec![("a".to_string(), Some(false)), ("c".to_string(), Some(true))]);
+
+            // Deleting the default promotes the first remaining agent
+            let removed = remove_agent_config(&mut map, &principal_id, "c".to_string()).unwrap();
+            assert_eq!(removed.is_default, Some(true));
+            assert_eq!(defaults(&map, &principal_id), vec![("a".to_string(), Some(true))]);
+            assert!(remove_agent_config(&mut map, &principal_id, "c".to_string()).is_err());
+        });
+        assert!(AI_CONFIGS_BY_AGENT.with(|index| index.borrow().iter().all(|(key, _)| key.agent_id == "a")));
+    }
+
+    #[test]
+    fn test_config_without_deleted_at_decodes_as_live() {
+        use ic_stable_structures::Storable;
+        #[derive(CandidType)]
+        struct UndeletableUserAiConfig {
+            principal_id: String,
+            agent_id: String,
+            voice_id: String,
+            is_default: Option<bool>,
+            created_at: Option<u64>,
+            updated_at: Option<u64>,
+            settings: Vec<(String, String)>,
+            capabilities: u64,
+        }
+        let old = UndeletableUserAiConfig {
+            principal_id: OWNER.to_string(),
+            agent_id: "agent".to_string(),
+            voice_id: "voice".to_string(),
+            is_default: Some(true),
+            created_at: Some(1),
+            updated_at: Some(2),
+            settings: Vec::new(),
+            capabilities: AiCapability::AGENT_V2,
+        };
+        let config = UserAiConfig::from_bytes(Cow::Owned(Encode!(&old).unwrap()));
+        assert_eq!((config.capabilities, config.deleted_at, config.expires_at), (AiCapability::AGENT_V2, None, None));
+        assert!(!is_expired(&config, u64::MAX));
+    }
+
+    #[test]
+    fn test_soft_deleted_configs_are_absent_until_purged() {
+        let principal_id = owner().to_text();
+        let other = stranger().to_text();
+        let config = |principal_id: &str| UserAiConfig {
+            principal_id: principal_id.to_string(),
+            agent_id: "agent".to_string(),
+            voice_id: "voice".to_string(),
+            is_default: None,
+            created_at: None,
+            updated_at: None,
+            settings: Vec::new(),
+            capabilities: 0,
+            deleted_at: Some(5),
+            expires_at: None,
+        };
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            for (principal_id, deleted_at) in [(&principal_id, 10), (&other, 20)] {
+                apply_user_ai_config(&mut map, config(principal_id), 1, 10).unwrap();
+                assert_eq!(principal_configs(&map, principal_id)[0].deleted_at, None);
+                tombstone_user_ai_configs(&mut map, principal_id, "admin", deleted_at).unwrap();
+            }
+        });
+        assert!(!has_user_ai_config(principal_id.clone()));
+        assert_eq!(get_user_ai_config(principal_id.clone()), None);
+        assert_eq!(deleted_ai_configs().len(), 2);
+
+        assert_eq!(purge_tombstones(10), 0);
+        assert_eq!(purge_tombstones(15), 1);
+        assert!(DELETED_AI_CONFIGS.with(|d| d.borrow().get(&principal_id)).is_none());
+        assert_eq!(deleted_ai_configs().iter().map(|c| c.principal_id.clone()).collect::<Vec<_>>(), vec![other]);
+    }
+
+    #[test]
+    fn test_capability_bits() {
+        let caps = AiCapability::default().grant(AiCapability::VOICE_CLONE | AiCapability::BATCH_INFERENCE);
+        assert!(caps.has(AiCapability::VOICE_CLONE));
+        assert!(caps.has(AiCapability::VOICE_CLONE | AiCapability::BATCH_INFERENCE));
+        assert!(!caps.has(AiCapability::VOICE_CLONE | AiCapability::AGENT_V2));
+        assert!(!caps.has(0));
+
+        let caps = caps.revoke(AiCapability::VOICE_CLONE);
+        assert_eq!(caps, AiCapability(AiCapability::BATCH_INFERENCE));
+
+        assert_eq!(AiCapability::validate(AiCapability::ALL), Ok(()));
+        assert!(AiCapability::validate(0).is_err());
+        assert_eq!(AiCapability::validate(AiCapability::AGENT_V2 | 1 << 3), Err("Unknown capability bits: 0x8".to_string()));
+        assert!(change_capability(owner().to_text(), 1 << 63, true).unwrap_err().starts_with("Unknown capability bits"));
+
+        // A bit stored before it was assigned does not pass a check
+        let principal_id = owner().to_text();
+        let config = UserAiConfig { capabilities: u64::MAX, ..agent_config(&principal_id, "agent", Some(true)) };
+        USER_AI_AGENT_CONFIGS.with(|m| m.borrow_mut().insert(AgentConfigKey { principal_id: principal_id.clone(), agent_id: "agent".to_string() }, config));
+        assert!(check_ai_capability(principal_id.clone(), AiCapability::ALL));
+        assert!(!check_ai_capability(principal_id, 1 << 3));
+    }
+
+    #[test]
+    fn test_config_without_capabilities_decodes_as_none_granted() {
+        use ic_stable_structures::Storable;
+        let old = UncappedUserAiConfig {
+            principal_id: OWNER.to_string(),
+            agent_id: "agent".to_string(),
+            voice_id: "voice".to_string(),
+            is_default: Some(true),
+            created_at: Some(1),
+            updated_at: Some(2),
+            settings: vec![("model".to_string(), "m".to_string())],
+        };
+        let config = UserAiConfig::from_bytes(Cow::Owned(Encode!(&old).unwrap()));
+        assert_eq!((config.capabilities, config.settings.len()), (0, 1));
+
+        let tombstone = UncappedDeletedAiConfig {
+            principal_id: OWNER.to_string(),
+            deleted_at: 3,
+            agent_id: "agent".to_string(),
+            voice_id: "voice".to_string(),
+            deleted_by: "admin".to_string(),
+            configs: vec![old],
+        };
+        let decoded = DeletedAiConfig::from_bytes(Cow::Owned(Encode!(&tombstone).unwrap()));
+        assert_eq!(decoded.configs[0].capabilities, 0);
+    }
+
+    #[test]
+    fn test_capabilities_kept_across_config_writes() {
+        let principal_id = stranger().to_text();
+        let config = |agent_id: &str, capabilities: u64| UserAiConfig {
+            principal_id: principal_id.clone(),
+            agent_id: agent_id.to_string(),
+            voice_id: "voice".to_string(),
+            is_default: None,
+            created_at: None,
+            updated_at: None,
+            settings: Vec::new(),
+            capabilities,
+            deleted_at: None,
+            expires_at: None,
+        };
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            assert!(update_capabilities(&mut map, &principal_id, |c| c.grant(AiCapability::AGENT_V2)).is_err());
+
+            apply_user_ai_config(&mut map, config("a", u64::MAX), 1, 10).unwrap();
+            assert_eq!(principal_configs(&map, &principal_id)[0].capabilities, 0);
+
+            update_capabilities(&mut map, &principal_id, |c| c.grant(AiCapability::AGENT_V2)).unwrap();
+            apply_user_ai_config(&mut map, config("b", 0), 2, 10).unwrap();
+            apply_user_ai_config(&mut map, config("a", 0), 3, 10).unwrap();
+            assert!(principal_configs(&map, &principal_id).iter().all(|c| c.capabilities == AiCapability::AGENT_V2));
+
+            update_capabilities(&mut map, &principal_id, |c| c.revoke(AiCapability::AGENT_V2)).unwrap();
+            assert!(principal_configs(&map, &principal_id).iter().all(|c| c.capabilities == 0));
+        });
+    }
+
+    #[test]
+    fn test_expiry_spans_principal_and_moves_configs_to_tombstone() {
+        let principal_id = owner().to_text();
+        let config = |agent_id: &str, expires_at: Option<u64>| UserAiConfig {
+            principal_id: principal_id.clone(),
+            agent_id: agent_id.to_string(),
+            voice_id: "voice".to_string(),
+            is_default: None,
+            created_at: None,
+            updated_at: None,
+            settings: Vec::new(),
+            capabilities: 0,
+            deleted_at: None,
+            expires_at,
+        };
+        USER_AI_AGENT_CONFIGS.with(|config_map| {
+            let mut map = config_map.borrow_mut();
+            apply_user_ai_config(&mut map, config("a", None), 1, 10).unwrap();
+            apply_user_ai_config(&mut map, config("b", Some(100)), 2, 10).unwrap();
+            assert!(principal_configs(&map, &principal_id).iter().all(|c| c.expires_at == Some(100)));
+            assert_eq!(expiring_configs(&map, 40, 50).len(), 0);
+            assert_eq!(expiring_configs(&map, 50, 50).len(), 2);
+
+            assert!(expire_principal_configs(&mut map, &principal_id, 99).is_empty());
+            assert_eq!(expire_principal_configs(&mut map, &principal_id, 100).len(), 2);
+            assert!(principal_configs(&map, &principal_id).is_empty());
+        });
+        let tombstone = DELETED_AI_CONFIGS.with(|d| d.borrow().get(&principal_id)).unwrap();
+        assert_eq!((tombstone.deleted_by.as_str(), tombstone.deleted_at), (EXPIRED_DELETER, 100));
+        assert_eq!(get_user_ai_config(principal_id), None);
+    }
+}
@@ -19,7 +19,7 @@ fn headers() -> Vec<HttpHeader> {
     ]
 }
 
-#[ic_cdk::query]
+#[ic_cdk::query(hidden = true)]
 fn transform(resp: TransformArgs) -> ic_cdk::api::management_canister::http_request::HttpResponse {
     ic_cdk::api::management_canister::http_request::HttpResponse {
         status: resp.response.status, headers: vec![], body: resp.response.body,
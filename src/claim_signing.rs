@@ -0,0 +1,291 @@
+// Claim Signing Module - threshold ECDSA signatures over claim tickets
+//
+// The Solana distributor can require a canister signature in addition to the Merkle proof,
+// so a proof leaked from a compromised RPC cannot be replayed into a cloned distributor.
+//
+// Signed message (CRITICAL - Must match Solana program):
+// message      = "AIO_CLAIM_TICKET_V1" || epoch_u64_le || index_u32_le || wallet_pubkey_32bytes || amount_u64_le
+// message_hash = SHA256(message)
+// signature    = secp256k1 ECDSA over message_hash, 64 bytes (r || s)
+//
+// get_signed_claim_ticket additionally signs the whole ticket, so a frontend can check the
+// ticket came from this canister without trusting the replica that answered. Same key; every
+// variable-length field is prefixed with its u32 little-endian length:
+// message = "AIO_SIGNED_CLAIM_TICKET_V1" || epoch_u64_le || index_u32_le || len || wallet_utf8
+//           || amount_u64_le || len || root || proof_count_u32_le || (len || hash)*
+//           || target_u8 (0 = Solana, 1 = EVM)
+// The ticket's own signature field is not covered.
+//
+// ECDSA signatures are randomized: a ticket re-issued before its claim is marked repeats
+// the signed message but not the signature bytes. Both signatures verify.
+
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+
+use crate::stable_mem_storage::CLAIM_SIGNING_CONFIG;
+use crate::task_rewards::{decode_wallet_base58, ChainTarget, ClaimTicket};
+
+/// Domain separator prefixed to every signed claim message
+pub const CLAIM_MESSAGE_DOMAIN: &[u8] = b"AIO_CLAIM_TICKET_V1";
+
+/// Domain separator prefixed to every signed whole-ticket message
+pub const SIGNED_TICKET_DOMAIN: &[u8] = b"AIO_SIGNED_CLAIM_TICKET_V1";
+
+/// Threshold ECDSA signing configuration
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ClaimSigningConfig {
+    pub enabled: bool,                  // When false, tickets are returned unsigned
+    pub key_name: String,               // "dfx_test_key" locally, "key_1" / "test_key_1" on mainnet
+    pub derivation_path: Vec<Vec<u8>>,
+}
+
+impl Default for ClaimSigningConfig {
+    fn default() -> Self {
+        ClaimSigningConfig {
+            enabled: false,
+            key_name: "dfx_test_key".to_string(),
+            derivation_path: vec![b"aio-claim-ticket".to_vec()],
+        }
+    }
+}
+
+impl Storable for ClaimSigningConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize ClaimSigningConfig");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize ClaimSigningConfig")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Build the exact byte message that is signed for a claim
+pub fn claim_message(epoch: u64, index: u32, wallet_bytes: &[u8; 32], amount: u64) -> Vec<u8> {
+    let mut message = Vec::with_capacity(CLAIM_MESSAGE_DOMAIN.len() + 8 + 4 + 32 + 8);
+    message.extend_from_slice(CLAIM_MESSAGE_DOMAIN);
+    message.extend_from_slice(&epoch.to_le_bytes());
+    message.extend_from_slice(&index.to_le_bytes());
+    message.extend_from_slice(wallet_bytes);
+    message.extend_from_slice(&amount.to_le_bytes());
+    message
+}
+
+/// SHA256 of the claim message, as passed to sign_with_ecdsa
+pub fn claim_message_hash(epoch: u64, index: u32, wallet_bytes: &[u8; 32], amount: u64) -> [u8; 32] {
+    Sha256::digest(claim_message(epoch, index, wallet_bytes, amount)).into()
+}
+
+/// A claim ticket with a threshold ECDSA signature over all of its fields
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SignedClaimTicket {
+    pub ticket: ClaimTicket,
+    pub ic_signature: Vec<u8>,  // secp256k1 ECDSA (r || s) over SHA256(signed_ticket_message)
+    pub canister_id: String,    // Signer; its key comes from get_claim_signing_pubkey
+}
+
+fn push_with_len(message: &mut Vec<u8>, bytes: &[u8]) {
+    message.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    message.extend_from_slice(bytes);
+}
+
+/// Build the canonical byte message signed for a whole ticket
+pub fn signed_ticket_message(ticket: &ClaimTicket) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(SIGNED_TICKET_DOMAIN);
+    message.extend_from_slice(&ticket.epoch.to_le_bytes());
+    message.extend_from_slice(&ticket.index.to_le_bytes());
+    push_with_len(&mut message, ticket.wallet.as_bytes());
+    message.extend_from_slice(&ticket.amount.to_le_bytes());
+    push_with_len(&mut message, &ticket.root);
+    message.extend_from_slice(&(ticket.proof.len() as u32).to_le_bytes());
+    for hash in &ticket.proof {
+        push_with_len(&mut message, hash);
+    }
+    message.push(match ticket.target {
+        ChainTarget::Solana => 0,
+        ChainTarget::Evm => 1,
+    });
+    message
+}
+
+/// Get current signing configuration
+pub fn get_claim_signing_config() -> ClaimSigningConfig {
+    CLAIM_SIGNING_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+/// Update signing configuration (controller only)
+pub fn set_claim_signing_config(config: ClaimSigningConfig) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can set claim signing config".to_string());
+    }
+
+    if config.key_name.is_empty() {
+        return Err("Signing key name must not be empty".to_string());
+    }
+
+    CLAIM_SIGNING_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store claim signing config: {:?}", e))
+    })
+}
+
+fn key_id(config: &ClaimSigningConfig) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: config.key_name.clone(),
+    }
+}
+
+/// Sign a claim ticket in place. No-op when signing is disabled or the ticket is for an
+/// EVM epoch (the message format above is specific to the Solana distributor).
+pub async fn sign_claim_ticket(ticket: &mut ClaimTicket) -> Result<(), String> {
+    let config = get_claim_signing_config();
+    if !config.enabled || ticket.target != ChainTarget::Solana {
+        return Ok(());
+    }
+
+    let wallet_bytes = decode_wallet_base58(&ticket.wallet)?;
+    let message_hash = claim_message_hash(ticket.epoch, ticket.index, &wallet_bytes, ticket.amount);
+
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: message_hash.to_vec(),
+        derivation_path: config.derivation_path.clone(),
+        key_id: key_id(&config),
+    })
+    .await
+    .map_err(|(code, msg)| format!("sign_with_ecdsa failed: {:?} {}", code, msg))?;
+
+    ticket.signature = Some(response.signature);
+    Ok(())
+}
+
+/// Sign a whole ticket with the configured key, whether or not per-claim signing is enabled
+pub async fn sign_whole_ticket(ticket: ClaimTicket) -> Result<SignedClaimTicket, String> {
+    let config = get_claim_signing_config();
+    let message_hash: [u8; 32] = Sha256::digest(signed_ticket_message(&ticket)).into();
+
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: message_hash.to_vec(),
+        derivation_path: config.derivation_path.clone(),
+        key_id: key_id(&config),
+    })
+    .await
+    .map_err(|(code, msg)| format!("sign_with_ecdsa failed: {:?} {}", code, msg))?;
+
+    Ok(SignedClaimTicket {
+        ticket,
+        ic_signature: response.signature,
+        canister_id: ic_cdk::id().to_text(),
+    })
+}
+
+/// SEC1 compressed public key that verifies claim signatures
+pub async fn get_claim_signing_pubkey() -> Result<Vec<u8>, String> {
+    let config = get_claim_signing_config();
+
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: config.derivation_path.clone(),
+        key_id: key_id(&config),
+    })
+    .await
+    .map_err(|(code, msg)| format!("ecdsa_public_key failed: {:?} {}", code, msg))?;
+
+    Ok(response.public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequential_wallet() -> [u8; 32] {
+        let mut wallet = [0u8; 32];
+        for (i, b) in wallet.iter_mut().enumerate() {
+            *b = i as u8 + 1;
+        }
+        wallet
+    }
+
+    #[test]
+    fn test_claim_message_layout() {
+        let message = claim_message(1, 2, &sequential_wallet(), 1_000_000);
+        assert_eq!(message.len(), 71);
+        assert_eq!(
+            hex::encode(&message),
+            "41494f5f434c41494d5f5449434b45545f5631\
+             0100000000000000\
+             02000000\
+             0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20\
+             40420f0000000000"
+        );
+    }
+
+    #[test]
+    fn test_claim_message_hash_vectors() {
+        assert_eq!(
+            hex::encode(claim_message_hash(1, 2, &sequential_wallet(), 1_000_000)),
+            "ff6e247a51e3899aac8cdbb76087c8f9ee31bf8c61e31ad6bfa14f47724c1ff2"
+        );
+        assert_eq!(
+            hex::encode(claim_message_hash(u64::MAX, u32::MAX, &[0xff; 32], u64::MAX)),
+            "21de7d6327328d346513a6becce8f8b069f33d0e557bceef277e9b65a17dace2"
+        );
+    }
+
+    #[test]
+    fn test_signed_ticket_message_layout() {
+        let ticket = ClaimTicket {
+            epoch: 1,
+            index: 2,
+            wallet: "ab".to_string(),
+            amount: 3,
+            proof: vec![vec![0xaa], vec![0xbb, 0xcc]],
+            root: vec![0x11; 2],
+            signature: Some(vec![0xff; 64]),
+            target: ChainTarget::Evm,
+        };
+        let message = signed_ticket_message(&ticket);
+        assert_eq!(
+            hex::encode(&message[SIGNED_TICKET_DOMAIN.len()..]),
+            "0100000000000000\
+             02000000\
+             02000000 6162\
+             0300000000000000\
+             02000000 1111\
+             02000000 01000000 aa 02000000 bbcc\
+             01".replace(' ', "")
+        );
+
+        // The ticket's own signature is not covered
+        let unsigned = ClaimTicket { signature: None, ..ticket };
+        assert_eq!(signed_ticket_message(&unsigned), message);
+    }
+
+    #[test]
+    fn test_only_controller_sets_signing_config() {
+        let admin = candid::Principal::from_slice(&[0xad]);
+        let env = crate::env::TestEnvironment::install(admin, 1_000);
+        let config = ClaimSigningConfig { enabled: true, ..ClaimSigningConfig::default() };
+
+        env.set_caller(candid::Principal::from_slice(&[1]));
+        assert!(set_claim_signing_config(config.clone()).is_err());
+        assert!(!get_claim_signing_config().enabled);
+
+        env.set_caller(admin);
+        assert_eq!(set_claim_signing_config(config), Ok(()));
+        assert!(get_claim_signing_config().enabled);
+    }
+}
@@ -0,0 +1,410 @@
+// Claim Sync Module - mirror of the Solana distributor's claimed bitmap
+//
+// Once an epoch root is published the claim itself happens on Solana, and we only hear about
+// it if the frontend calls mark_claim_result. Each epoch keeps a local claimed bitmap: it is set
+// by mark_claim_result and by sync_epoch_claims, which reads the distributor's bitmap account
+// through an HTTPS outcall to a Solana RPC (getAccountInfo) and marks every index claimed there
+// but not here.
+//
+// Bitmap layout (must match the Solana program): `bitmap_offset` bytes of account header, then
+// one bit per leaf index, index i at byte i / 8, bit i % 8 (least significant bit first).
+//
+// The account is read in dataSlice chunks of at most `max_bytes_per_call` bytes so a large epoch
+// never exceeds the outcall response limit. Chunks are reconciled as they arrive: a failed call
+// or an account shorter than the epoch keeps what was already read and reports the sync as
+// incomplete. Replicas read the account independently, so a claim landing mid-sync can make the
+// outcall fail consensus; the next sync picks it up.
+
+use candid::{CandidType, Deserialize};
+use base64::{engine::general_purpose, Engine as _};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::stable_mem_storage::{CLAIM_BITMAPS, CLAIM_SYNC_CONFIG, EPOCH_META, EPOCH_VESTED_TRANCHES, EPOCH_WALLET_INDEX};
+use crate::task_rewards::{self, ChainTarget};
+
+/// Largest bitmap slice one outcall may request; keeps the base64 response under 2 MB
+pub const MAX_SYNC_BYTES_PER_CALL: u32 = 1_000_000;
+
+/// Cycles attached to each getAccountInfo outcall
+const OUTCALL_CYCLES: u128 = 2_000_000_000;
+
+/// Where and how to read the distributor's claimed bitmaps
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ClaimSyncConfig {
+    pub rpc_url: Option<String>,    // Solana JSON-RPC endpoint; None disables syncing
+    pub bitmap_offset: u32,         // Account bytes before the bitmap (e.g. 8-byte discriminator)
+    pub max_bytes_per_call: u32,    // Bitmap bytes requested per outcall
+    pub sync_interval_secs: u64,    // Timer period for syncing every registered epoch; 0 = off
+}
+
+impl Default for ClaimSyncConfig {
+    fn default() -> Self {
+        ClaimSyncConfig {
+            rpc_url: None,
+            bitmap_offset: 8,
+            max_bytes_per_call: 8_192,
+            sync_interval_secs: 0,
+        }
+    }
+}
+
+impl Storable for ClaimSyncConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize ClaimSyncConfig");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize ClaimSyncConfig")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Local claimed bitmap of an epoch and the Solana account it mirrors
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct EpochClaimBitmap {
+    pub account: Option<String>,  // Distributor bitmap account (base58); None until registered
+    pub bits: Vec<u8>,            // Same layout as the on-chain bitmap
+    pub last_synced_at: u64,      // Canister time (ns) of the last sync; 0 if never synced
+}
+
+impl Storable for EpochClaimBitmap {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize EpochClaimBitmap");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize EpochClaimBitmap")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Outcome of one sync_epoch_claims run
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ClaimSyncReport {
+    pub epoch: u64,
+    pub bytes_read: u64,
+    pub newly_claimed: Vec<u32>,  // Indices claimed on-chain that were not marked here
+    pub complete: bool,           // false if the account could not be read to the end
+    pub error: Option<String>,    // Why the read stopped early
+}
+
+thread_local! {
+    static SYNC_TIMER_ID: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+fn bit_is_set(bits: &[u8], index: u32) -> bool {
+    bits.get((index / 8) as usize).is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+}
+
+fn set_bit(bits: &mut Vec<u8>, index: u32) {
+    let byte = (index / 8) as usize;
+    if bits.len() <= byte {
+        bits.resize(byte + 1, 0);
+    }
+    bits[byte] |= 1 << (index % 8);
+}
+
+/// Indices set in `chunk` (bitmap bytes starting at `first_byte`) but not in `local`,
+/// ignoring padding bits at or beyond `leaves_count`
+fn unmarked_claims(chunk: &[u8], first_byte: u64, local: &[u8], leaves_count: u64) -> Vec<u32> {
+    let mut indices = Vec::new();
+    for (offset, byte) in chunk.iter().enumerate() {
+        if *byte == 0 {
+            continue;
+        }
+        for bit in 0..8u64 {
+            let index = (first_byte + offset as u64) * 8 + bit;
+            if index >= leaves_count {
+                return indices;
+            }
+            if byte & (1 << bit) != 0 && !bit_is_set(local, index as u32) {
+                indices.push(index as u32);
+            }
+        }
+    }
+    indices
+}
+
+/// Account data bytes from a getAccountInfo response (base64 encoding); None if the account
+/// does not exist
+fn parse_account_data(body: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let json: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| format!("Invalid RPC response: {}", e))?;
+    if let Some(error) = json.get("error") {
+        return Err(format!("RPC error: {}", error));
+    }
+    let value = &json["result"]["value"];
+    if value.is_null() {
+        return Ok(None);
+    }
+    let encoded = value["data"][0].as_str()
+        .ok_or_else(|| "RPC response has no account data".to_string())?;
+    general_purpose::STANDARD.decode(encoded)
+        .map(Some)
+        .map_err(|e| format!("Invalid base64 account data: {}", e))
+}
+
+/// Keep only the account data so replicas at different slots agree on the response
+#[ic_cdk::query(hidden = true)]
+fn transform_solana_account(args: TransformArgs) -> HttpResponse {
+    let body = match parse_account_data(&args.response.body) {
+        Ok(data) => serde_json::json!({ "result": { "value": data.map(|d| serde_json::json!({
+            "data": [general_purpose::STANDARD.encode(d), "base64"]
+        })) } }),
+        Err(e) => serde_json::json!({ "error": e }),
+    };
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: body.to_string().into_bytes(),
+    }
+}
+
+/// Read `length` bytes of `account` starting at `offset`
+async fn fetch_account_slice(rpc_url: &str, account: &str, offset: u64, length: u64) -> Result<Option<Vec<u8>>, String> {
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "getAccountInfo",
+        "params": [account, { "encoding": "base64", "dataSlice": { "offset": offset, "length": length } }],
+    });
+    let arg = CanisterHttpRequestArgument {
+        url: rpc_url.to_string(),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader { name: "Content-Type".into(), value: "application/json".into() }],
+        body: Some(request.to_string().into_bytes()),
+        max_response_bytes: Some(length * 4 / 3 + 4_096),
+        transform: Some(TransformContext::from_name("transform_solana_account".to_string(), vec![])),
+    };
+    let (response,) = http_request(arg, OUTCALL_CYCLES)
+        .await
+        .map_err(|(code, msg)| format!("RPC outcall failed ({:?}): {}", code, msg))?;
+    parse_account_data(&response.body)
+}
+
+/// Get the claim sync configuration
+pub fn get_claim_sync_config() -> ClaimSyncConfig {
+    CLAIM_SYNC_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+/// Update the claim sync configuration and reschedule the sync timer (admin only)
+pub fn set_claim_sync_config(config: ClaimSyncConfig) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can set claim sync config".to_string());
+    }
+    if config.max_bytes_per_call == 0 || config.max_bytes_per_call > MAX_SYNC_BYTES_PER_CALL {
+        return Err(format!("max_bytes_per_call must be between 1 and {}", MAX_SYNC_BYTES_PER_CALL));
+    }
+    CLAIM_SYNC_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store claim sync config: {:?}", e))
+    })?;
+    schedule_claim_sync();
+    Ok(())
+}
+
+/// Register the distributor bitmap account of an epoch (admin only)
+pub fn set_epoch_claim_account(epoch: u64, account: String) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can set epoch claim accounts".to_string());
+    }
+    let meta = EPOCH_META.with(|store| store.borrow().get(&epoch))
+        .ok_or_else(|| format!("Epoch {} metadata not found", epoch))?;
+    if meta.target != ChainTarget::Solana {
+        return Err(format!("Epoch {} does not target Solana", epoch));
+    }
+    task_rewards::decode_wallet_base58(&account)?;
+    CLAIM_BITMAPS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut bitmap = map.get(&epoch).unwrap_or_default();
+        bitmap.account = Some(account);
+        map.insert(epoch, bitmap);
+    });
+    Ok(())
+}
+
+/// Local claimed bitmap of an epoch
+pub fn get_epoch_claim_bitmap(epoch: u64) -> Option<EpochClaimBitmap> {
+    CLAIM_BITMAPS.with(|store| store.borrow().get(&epoch))
+}
+
+/// Whether leaf `index` of `epoch` is known to be claimed
+pub fn is_index_claimed(epoch: u64, index: u32) -> bool {
+    CLAIM_BITMAPS.with(|store| {
+        store.borrow().get(&epoch).is_some_and(|bitmap| bit_is_set(&bitmap.bits, index))
+    })
+}
+
+/// Set the local claimed bit of a leaf
+pub(crate) fn mark_index_claimed(epoch: u64, index: u32) {
+    CLAIM_BITMAPS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut bitmap = map.get(&epoch).unwrap_or_default();
+        set_bit(&mut bitmap.bits, index);
+        map.insert(epoch, bitmap);
+    });
+}
+
+/// Sync the claimed bitmap of an epoch from Solana (admin only)
+pub async fn sync_epoch_claims(epoch: u64) -> Result<ClaimSyncReport, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can sync epoch claims".to_string());
+    }
+    sync_epoch(epoch).await
+}
+
+async fn sync_epoch(epoch: u64) -> Result<ClaimSyncReport, String> {
+    let config = get_claim_sync_config();
+    let rpc_url = config.rpc_url.clone().ok_or_else(|| "Claim sync is not configured".to_string())?;
+    let meta = EPOCH_META.with(|store| store.borrow().get(&epoch))
+        .ok_or_else(|| format!("Epoch {} metadata not found", epoch))?;
+    if !meta.locked {
+        return Err(format!("Epoch {} is not locked; its root is not published", epoch));
+    }
+    let account = get_epoch_claim_bitmap(epoch)
+        .and_then(|bitmap| bitmap.account)
+        .ok_or_else(|| format!("Epoch {} has no claim bitmap account", epoch))?;
+
+    let mut wallets: BTreeMap<u32, String> = EPOCH_WALLET_INDEX.with(|store| {
+        store.borrow()
+            .iter()
+            .filter(|(key, _)| key.epoch == epoch)
+            .map(|(key, entry)| (entry.index, key.wallet))
+            .collect()
+    });
+    EPOCH_VESTED_TRANCHES.with(|store| {
+        for (key, tranche) in store.borrow().iter().filter(|(key, _)| key.epoch == epoch) {
+            wallets.insert(tranche.index, key.wallet);
+        }
+    });
+
+    let total_bytes = meta.leaves_count.div_ceil(8);
+    let chunk_len = config.max_bytes_per_call as u64;
+    let mut report = ClaimSyncReport { epoch, bytes_read: 0, newly_claimed: Vec::new(), complete: false, error: None };
+
+    while report.bytes_read < total_bytes {
+        let length = chunk_len.min(total_bytes - report.bytes_read);
+        let offset = config.bitmap_offset as u64 + report.bytes_read;
+        let chunk = match fetch_account_slice(&rpc_url, &account, offset, length).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                report.error = Some(format!("Account {} not found", account));
+                break;
+            }
+            Err(e) => {
+                report.error = Some(e);
+                break;
+            }
+        };
+
+        let local = get_epoch_claim_bitmap(epoch).unwrap_or_default().bits;
+        for index in unmarked_claims(&chunk, report.bytes_read, &local, meta.leaves_count) {
+            match wallets.get(&index) {
+                Some(wallet) => {
+                    task_rewards::apply_synced_claim(wallet, epoch, index);
+                    mark_index_claimed(epoch, index);
+                    report.newly_claimed.push(index);
+                }
+                None => crate::env::println!("Claimed index {} of epoch {} has no wallet here", index, epoch),
+            }
+        }
+        report.bytes_read += chunk.len() as u64;
+
+        if (chunk.len() as u64) < length {
+            report.error = Some(format!(
+                "Account {} ended after {} of {} bitmap bytes", account, report.bytes_read, total_bytes
+            ));
+            break;
+        }
+    }
+
+    report.complete = report.error.is_none();
+    CLAIM_BITMAPS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut bitmap = map.get(&epoch).unwrap_or_default();
+        bitmap.last_synced_at = crate::env::time();
+        map.insert(epoch, bitmap);
+    });
+    crate::env::println!(
+        "Synced epoch {} claims: {} bytes read, {} newly claimed, complete={}",
+        epoch, report.bytes_read, report.newly_claimed.len(), report.complete
+    );
+    Ok(report)
+}
+
+/// (Re)start the periodic sync of every epoch with a registered account, per the config
+pub fn schedule_claim_sync() {
+    SYNC_TIMER_ID.with(|id| {
+        if let Some(timer) = id.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer);
+        }
+    });
+    let config = get_claim_sync_config();
+    if config.rpc_url.is_none() || config.sync_interval_secs == 0 {
+        return;
+    }
+    let timer = ic_cdk_timers::set_timer_interval(Duration::from_secs(config.sync_interval_secs), || {
+        ic_cdk::spawn(async {
+            let epochs: Vec<u64> = CLAIM_BITMAPS.with(|store| {
+                store.borrow()
+                    .iter()
+                    .filter(|(_, bitmap)| bitmap.account.is_some())
+                    .map(|(epoch, _)| epoch)
+                    .collect()
+            });
+            for epoch in epochs {
+                if let Err(e) = sync_epoch(epoch).await {
+                    crate::env::println!("Claim sync of epoch {} failed: {}", epoch, e);
+                }
+            }
+        });
+    });
+    SYNC_TIMER_ID.with(|id| *id.borrow_mut() = Some(timer));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unmarked_claims_skips_local_and_padding() {
+        // Remote: indices 0, 3, 9 and padding bit 12; local already has 3
+        let remote = [0b0000_1001, 0b0001_0010];
+        let mut local = Vec::new();
+        set_bit(&mut local, 3);
+        assert_eq!(unmarked_claims(&remote, 0, &local, 12), vec![0, 9]);
+
+        // A later chunk is offset by the bytes already read
+        assert_eq!(unmarked_claims(&[0b1000_0000], 2, &[], 24), vec![23]);
+        assert!(bit_is_set(&local, 3));
+        assert!(!bit_is_set(&local, 200));
+    }
+
+    #[test]
+    fn test_parse_account_data() {
+        let body = br#"{"jsonrpc":"2.0","result":{"context":{"slot":7},"value":{"data":["CQAB","base64"],"lamports":1}},"id":1}"#;
+        assert_eq!(parse_account_data(body).unwrap(), Some(vec![9, 0, 1]));
+
+        let missing = br#"{"jsonrpc":"2.0","result":{"context":{"slot":7},"value":null},"id":1}"#;
+        assert_eq!(parse_account_data(missing).unwrap(), None);
+
+        let error = br#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"bad"},"id":1}"#;
+        assert!(parse_account_data(error).unwrap_err().starts_with("RPC error"));
+    }
+}
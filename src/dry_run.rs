@@ -0,0 +1,173 @@
+// Dry Run Module - run a controller operation and undo its stable writes before the call ends
+//
+// with_dry_run sets DRY_RUN_MODE and runs the operation. While it is set, write sites call a
+// shadow_* helper before writing, which saves the old value and queues a restore. The
+// restores run in reverse order once the operation returns, so each entry gets back the
+// value it had before the first write. A trap rolls back the whole message anyway.
+//
+// Only the writes of build_epoch_snapshot are shadowed. Effects that cannot be undone are
+// skipped instead: event log entries, certified data and the epoch webhook.
+
+use ic_stable_structures::{StableBTreeMap, StableCell, StableVec, Storable};
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+use crate::stable_mem_storage::Memory;
+
+type Restore = Box<dyn FnOnce()>;
+
+thread_local! {
+    static DRY_RUN_MODE: RefCell<bool> = const { RefCell::new(false) };
+    static RESTORES: RefCell<Vec<Restore>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Whether writes are being shadowed
+pub fn is_active() -> bool {
+    DRY_RUN_MODE.with(|mode| *mode.borrow())
+}
+
+fn push_restore(restore: Restore) {
+    RESTORES.with(|restores| restores.borrow_mut().push(restore));
+}
+
+/// Run `f` and undo every shadowed write it made, returning its result
+pub fn with_dry_run<T, E>(f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    DRY_RUN_MODE.with(|mode| {
+        let mut mode = mode.borrow_mut();
+        assert!(!*mode, "dry runs cannot be nested");
+        *mode = true;
+    });
+    let result = f();
+    DRY_RUN_MODE.with(|mode| *mode.borrow_mut() = false);
+
+    let restores = RESTORES.with(|restores| std::mem::take(&mut *restores.borrow_mut()));
+    for restore in restores.into_iter().rev() {
+        restore();
+    }
+    result
+}
+
+/// Save the entry of `key` in `map` (the borrowed contents of `store`) before it is written
+pub(crate) fn shadow_entry<K, V>(
+    store: &'static LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>,
+    map: &StableBTreeMap<K, V, Memory>,
+    key: &K,
+) where
+    K: Storable + Ord + Clone + 'static,
+    V: Storable + 'static,
+{
+    if !is_active() {
+        return;
+    }
+    let key = key.clone();
+    let original = map.get(&key);
+    push_restore(Box::new(move || {
+        store.with(|store| {
+            let mut map = store.borrow_mut();
+            match original {
+                Some(value) => map.insert(key, value),
+                None => map.remove(&key),
+            };
+        })
+    }));
+}
+
+/// Save the value of the cell `store` before it is written
+pub(crate) fn shadow_cell<T>(store: &'static LocalKey<RefCell<StableCell<T, Memory>>>, cell: &StableCell<T, Memory>)
+where
+    T: Storable + Clone + 'static,
+{
+    if !is_active() {
+        return;
+    }
+    let original = cell.get().clone();
+    push_restore(Box::new(move || {
+        store.with(|store| {
+            store.borrow_mut().set(original).expect("Failed to restore cell after dry run");
+        })
+    }));
+}
+
+/// Save every element of the vector `store` before it is written
+pub(crate) fn shadow_vec<T>(store: &'static LocalKey<RefCell<StableVec<T, Memory>>>, vec: &StableVec<T, Memory>)
+where
+    T: Storable + 'static,
+{
+    if !is_active() {
+        return;
+    }
+    let original: Vec<T> = vec.iter().collect();
+    push_restore(Box::new(move || {
+        store.with(|store| {
+            let vec = store.borrow_mut();
+            while vec.pop().is_some() {}
+            for item in &original {
+                vec.push(item).expect("Failed to restore vector after dry run");
+            }
+        })
+    }));
+}
+
+/// Save the element at `index` of the vector `store` before it is overwritten with set
+pub(crate) fn shadow_vec_element<T>(store: &'static LocalKey<RefCell<StableVec<T, Memory>>>, vec: &StableVec<T, Memory>, index: u64)
+where
+    T: Storable + 'static,
+{
+    if !is_active() {
+        return;
+    }
+    let Some(original) = vec.get(index) else {
+        return;
+    };
+    push_restore(Box::new(move || {
+        store.with(|store| store.borrow().set(index, &original))
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stable_mem_storage::{EPOCH_CREATION_TIMES, LIABILITY_SUMMARY, TIER_MULTIPLIERS};
+    use crate::task_rewards::liability::LiabilitySummary;
+
+    #[test]
+    fn test_dry_run_restores_shadowed_writes() {
+        TIER_MULTIPLIERS.with(|store| store.borrow_mut().insert("pro".to_string(), 2));
+
+        let result: Result<u64, String> = with_dry_run(|| {
+            assert!(is_active());
+            TIER_MULTIPLIERS.with(|store| {
+                let mut map = store.borrow_mut();
+                for (tier, multiplier) in [("pro", 3), ("max", 5), ("pro", 4)] {
+                    shadow_entry(&TIER_MULTIPLIERS, &map, &tier.to_string());
+                    map.insert(tier.to_string(), multiplier);
+                }
+            });
+            LIABILITY_SUMMARY.with(|store| {
+                let mut cell = store.borrow_mut();
+                shadow_cell(&LIABILITY_SUMMARY, &cell);
+                cell.set(LiabilitySummary { pending: 9, ..Default::default() }).unwrap();
+            });
+            EPOCH_CREATION_TIMES.with(|store| {
+                let vec = store.borrow_mut();
+                shadow_vec(&EPOCH_CREATION_TIMES, &vec);
+                vec.push(&7).unwrap();
+            });
+            Ok(TIER_MULTIPLIERS.with(|store| store.borrow().get(&"pro".to_string()).unwrap()))
+        });
+
+        assert_eq!(result, Ok(4));
+        assert!(!is_active());
+        assert_eq!(TIER_MULTIPLIERS.with(|store| store.borrow().iter().collect::<Vec<_>>()), vec![("pro".to_string(), 2)]);
+        assert_eq!(LIABILITY_SUMMARY.with(|store| *store.borrow().get()), LiabilitySummary::default());
+        assert_eq!(EPOCH_CREATION_TIMES.with(|store| store.borrow().len()), 0);
+
+        // Outside a dry run nothing is saved
+        TIER_MULTIPLIERS.with(|store| {
+            let mut map = store.borrow_mut();
+            shadow_entry(&TIER_MULTIPLIERS, &map, &"max".to_string());
+            map.insert("max".to_string(), 5);
+        });
+        assert_eq!(RESTORES.with(|restores| restores.borrow().len()), 0);
+    }
+}
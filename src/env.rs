@@ -0,0 +1,143 @@
+// Environment Module - the system API the reward code reads: caller, time, controllers,
+// logging and certified data
+//
+// task_rewards and the modules it calls go through these functions instead of ic_cdk, so a
+// cargo test can drive whole flows by installing a TestEnvironment with set_environment. In
+// a canister the installed environment is CanisterEnvironment, which delegates to ic_cdk.
+
+use candid::Principal;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub trait Environment {
+    fn caller(&self) -> Principal;
+    fn time(&self) -> u64;
+    fn is_controller(&self, principal: &Principal) -> bool;
+    fn print(&self, message: &str);
+    fn set_certified_data(&self, data: &[u8]);
+}
+
+/// The real system API
+pub struct CanisterEnvironment;
+
+impl Environment for CanisterEnvironment {
+    fn caller(&self) -> Principal {
+        ic_cdk::caller()
+    }
+
+    fn time(&self) -> u64 {
+        ic_cdk::api::time()
+    }
+
+    fn is_controller(&self, principal: &Principal) -> bool {
+        ic_cdk::api::is_controller(principal)
+    }
+
+    fn print(&self, message: &str) {
+        // The macro falls back to stdout outside a canister
+        ic_cdk::println!("{}", message)
+    }
+
+    fn set_certified_data(&self, data: &[u8]) {
+        ic_cdk::api::set_certified_data(data)
+    }
+}
+
+thread_local! {
+    static ENVIRONMENT: RefCell<Rc<dyn Environment>> = RefCell::new(Rc::new(CanisterEnvironment));
+}
+
+fn current() -> Rc<dyn Environment> {
+    ENVIRONMENT.with(|env| env.borrow().clone())
+}
+
+/// Replace the environment for the rest of this thread (tests)
+#[cfg(test)]
+pub fn set_environment(env: Rc<dyn Environment>) {
+    ENVIRONMENT.with(|current| *current.borrow_mut() = env);
+}
+
+pub fn caller() -> Principal {
+    current().caller()
+}
+
+/// Current time in nanoseconds since the epoch
+pub fn time() -> u64 {
+    current().time()
+}
+
+pub fn is_controller(principal: &Principal) -> bool {
+    current().is_controller(principal)
+}
+
+pub fn print(message: &str) {
+    current().print(message)
+}
+
+pub fn set_certified_data(data: &[u8]) {
+    current().set_certified_data(data)
+}
+
+/// ic_cdk::println through the installed environment
+macro_rules! env_println {
+    ($($arg:tt)*) => {
+        $crate::env::print(&format!($($arg)*))
+    };
+}
+pub(crate) use env_println as println;
+
+/// Environment a test sets up and moves along: caller, clock and controllers are plain
+/// fields; printed lines and certified data are kept for inspection
+#[cfg(test)]
+#[derive(Default)]
+pub struct TestEnvironment {
+    pub caller: std::cell::Cell<Option<Principal>>,  // None = anonymous
+    pub now: std::cell::Cell<u64>,
+    pub controllers: RefCell<Vec<Principal>>,
+    pub printed: RefCell<Vec<String>>,
+    pub certified_data: RefCell<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl TestEnvironment {
+    /// Install a fresh environment whose only controller is `controller`, calling as it
+    pub fn install(controller: Principal, now: u64) -> Rc<TestEnvironment> {
+        let env = Rc::new(TestEnvironment::default());
+        env.controllers.borrow_mut().push(controller);
+        env.caller.set(Some(controller));
+        env.now.set(now);
+        set_environment(env.clone());
+        env
+    }
+
+    pub fn set_caller(&self, caller: Principal) {
+        self.caller.set(Some(caller));
+    }
+
+    pub fn advance(&self, ns: u64) {
+        self.now.set(self.now.get() + ns);
+    }
+}
+
+#[cfg(test)]
+impl Environment for TestEnvironment {
+    fn caller(&self) -> Principal {
+        self.caller.get().unwrap_or_else(Principal::anonymous)
+    }
+
+    fn time(&self) -> u64 {
+        self.now.get()
+    }
+
+    fn is_controller(&self, principal: &Principal) -> bool {
+        self.controllers.borrow().contains(principal)
+    }
+
+    fn print(&self, message: &str) {
+        self.printed.borrow_mut().push(message.to_string());
+    }
+
+    fn set_certified_data(&self, data: &[u8]) {
+        *self.certified_data.borrow_mut() = data.to_vec();
+    }
+}
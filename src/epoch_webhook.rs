@@ -0,0 +1,168 @@
+// Epoch Webhook Module - POST a notice to an external URL when an epoch snapshot is built
+//
+// After build_epoch_snapshot stores an epoch, notify_epoch_built sends
+//   { "epoch": N, "root": "<hex>", "leaves_count": N, "canister_id": "..." }
+// to the configured HTTPS URL. The call runs after the build has returned, so a failed outcall
+// is only logged and recorded in the last webhook status; the snapshot stays built.
+//
+// Every replica of the subnet sends the request, so the receiver sees the same POST several
+// times and should deduplicate on epoch. The transform drops the response headers; a receiver
+// whose response body differs between requests makes the outcall fail consensus.
+
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::stable_mem_storage::EPOCH_WEBHOOK_URL;
+
+/// Longest webhook URL accepted
+pub const MAX_WEBHOOK_URL_LEN: usize = 512;
+
+/// Largest response the webhook may return; longer responses fail the outcall
+const WEBHOOK_MAX_RESPONSE_BYTES: u64 = 4_096;
+
+/// Cycles attached to each webhook outcall
+const OUTCALL_CYCLES: u128 = 1_000_000_000;
+
+/// Outcome of the last webhook call
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct WebhookStatus {
+    pub epoch: u64,
+    pub status_code: u16,         // HTTP status; 0 if no response was received
+    pub body: String,             // Response body (lossy UTF-8)
+    pub error: Option<String>,    // Why the outcall failed
+    pub sent_at: u64,             // Canister time (ns); 0 if no call was made yet
+}
+
+thread_local! {
+    // Last webhook outcome (heap only; reset on upgrade)
+    static LAST_WEBHOOK_STATUS: RefCell<WebhookStatus> = RefCell::new(WebhookStatus::default());
+}
+
+fn validate_webhook_url(url: &str) -> Result<(), String> {
+    if url.len() > MAX_WEBHOOK_URL_LEN {
+        return Err(format!("Webhook URL must be at most {} bytes", MAX_WEBHOOK_URL_LEN));
+    }
+    let host = url.strip_prefix("https://")
+        .ok_or_else(|| "Webhook URL must start with https://".to_string())?;
+    if host.is_empty() || host.starts_with('/') || url.chars().any(char::is_whitespace) {
+        return Err(format!("Invalid webhook URL: {}", url));
+    }
+    Ok(())
+}
+
+/// JSON body announcing a built epoch
+fn webhook_payload(epoch: u64, root: &[u8], leaves_count: u64, canister_id: &str) -> Vec<u8> {
+    serde_json::json!({
+        "epoch": epoch,
+        "root": hex::encode(root),
+        "leaves_count": leaves_count,
+        "canister_id": canister_id,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Webhook URL; empty if none is set
+pub fn get_epoch_webhook_url() -> String {
+    EPOCH_WEBHOOK_URL.with(|cell| cell.borrow().get().clone())
+}
+
+/// Set the URL notified when an epoch is built; an empty URL turns the webhook off (admin only)
+pub fn set_epoch_webhook_url(url: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only controller can set the epoch webhook URL".to_string());
+    }
+    let url = url.trim().to_string();
+    if !url.is_empty() {
+        validate_webhook_url(&url)?;
+    }
+    EPOCH_WEBHOOK_URL.with(|cell| {
+        cell.borrow_mut()
+            .set(url)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store epoch webhook URL: {:?}", e))
+    })
+}
+
+/// Outcome of the last webhook call
+pub fn get_last_webhook_status() -> WebhookStatus {
+    LAST_WEBHOOK_STATUS.with(|status| status.borrow().clone())
+}
+
+/// Drop the headers so replicas agree on the response
+#[ic_cdk::query(hidden = true)]
+fn transform_epoch_webhook(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: args.response.body,
+    }
+}
+
+/// Notify the webhook, if one is set, that `epoch` was built. The outcall is spawned so the
+/// caller never waits on it or sees its failure.
+pub fn notify_epoch_built(epoch: u64, root: [u8; 32], leaves_count: u64) {
+    let url = get_epoch_webhook_url();
+    if url.is_empty() {
+        return;
+    }
+    ic_cdk::spawn(async move {
+        let status = post_epoch_built(&url, epoch, &root, leaves_count).await;
+        if let Some(error) = &status.error {
+            ic_cdk::println!("Epoch {} webhook failed: {}", epoch, error);
+        } else {
+            ic_cdk::println!("Epoch {} webhook returned {}", epoch, status.status_code);
+        }
+        LAST_WEBHOOK_STATUS.with(|last| *last.borrow_mut() = status);
+    });
+}
+
+async fn post_epoch_built(url: &str, epoch: u64, root: &[u8], leaves_count: u64) -> WebhookStatus {
+    let canister_id = ic_cdk::id().to_text();
+    let arg = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader { name: "Content-Type".into(), value: "application/json".into() }],
+        body: Some(webhook_payload(epoch, root, leaves_count, &canister_id)),
+        max_response_bytes: Some(WEBHOOK_MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_epoch_webhook".to_string(), vec![])),
+    };
+    let mut status = WebhookStatus { epoch, sent_at: ic_cdk::api::time(), ..Default::default() };
+    match http_request(arg, OUTCALL_CYCLES).await {
+        Ok((response,)) => {
+            status.status_code = u16::try_from(response.status.0).unwrap_or(0);
+            status.body = String::from_utf8_lossy(&response.body).into_owned();
+        }
+        Err((code, msg)) => status.error = Some(format!("Webhook outcall failed ({:?}): {}", code, msg)),
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_webhook_url() {
+        assert!(validate_webhook_url("https://hooks.example.com/epochs").is_ok());
+        assert!(validate_webhook_url("http://hooks.example.com").is_err());
+        assert!(validate_webhook_url("https://").is_err());
+        assert!(validate_webhook_url("https://a b.com").is_err());
+        assert!(validate_webhook_url(&format!("https://{}", "a".repeat(MAX_WEBHOOK_URL_LEN))).is_err());
+    }
+
+    #[test]
+    fn test_webhook_payload() {
+        let body = webhook_payload(7, &[0xab, 0x01], 42, "aaaaa-aa");
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["epoch"], 7);
+        assert_eq!(json["root"], "ab01");
+        assert_eq!(json["leaves_count"], 42);
+        assert_eq!(json["canister_id"], "aaaaa-aa");
+    }
+}
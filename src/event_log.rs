@@ -0,0 +1,139 @@
+// Event Log Module - append-only log of state transitions for off-chain indexers
+//
+// Every event gets a sequence number one higher than the previous one (starting at 1),
+// so an indexer can catch up with get_events_since(last_seen_seq, limit).
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use crate::stable_mem_storage::{EVENTS, NEXT_EVENT_SEQ};
+use crate::task_rewards::TaskStatus;
+
+/// Maximum events kept; the oldest are dropped on append beyond this
+pub const MAX_EVENTS: u64 = 100_000;
+
+/// Maximum events returned by one get_events_since call
+pub const MAX_EVENTS_PAGE: u64 = 500;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum EventKind {
+    TaskCompleted { wallet: String, taskid: String },
+    PaymentRecorded { wallet: String, amount_paid: u64, payfor: Option<String> },
+    EpochBuilt { epoch: u64, leaves_count: u64, total_reward_amount: u64 },
+    TicketIssued { wallet: String, epoch: u64 },
+    ClaimMarked { wallet: String, epoch: u64, success: bool },
+    ConfigChanged { principal_id: String, agent_id: String, deleted: bool },
+    ConfigBatchApplied { operation: String, total: u64, succeeded: u64, failed: u64 },
+    PaidOut { wallet: String, amount: u64, tx_sig: String },
+    ClaimSynced { wallet: String, epoch: u64, index: u32 },  // Claim found on-chain by sync_epoch_claims
+    EpochDescriptionUpdated { epoch: u64, description: String, updated_by: String },
+    ReferralRegistered { referrer: String, referee: String },
+    ReferralConverted { referrer: String, referee: String, taskid: String },
+    ClaimDisputed { wallet: String, taskid: String },
+    DisputeResolved { wallet: String, taskid: String, approved: bool },
+    WalletFlagged { wallet: String, reason: String, flagged_by: String },
+    WalletUnflagged { wallet: String, unflagged_by: String },
+    TaskRolledBack { wallet: String, taskid: String, to_status: TaskStatus, reason: String, rolled_back_by: String },
+    WalletSuspended { wallet: String, reason: String, expires_at: Option<u64>, suspended_by: String },
+    WalletUnsuspended { wallet: String, unsuspended_by: String },
+    EpochRootOverrideProposed { epoch: u64, new_root: Vec<u8>, justification: String, proposed_by: String },
+    EpochRootOverridden { epoch: u64, old_root: Vec<u8>, new_root: Vec<u8>, justification: String, proposed_by: String, approved_by: String, tickets_invalidated: u64 },
+    TaskCompletionBatchApplied { total: u64, succeeded: u64, failed: u64, applied_by: String },
+    TaskPauseChanged { taskid: String, paused: bool, changed_by: String },
+    EvidenceSubmitted { wallet: String, taskid: String },
+    TaskAttested { wallet: String, taskid: String, approved: bool, attested_by: String },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub kind: EventKind,
+}
+
+impl Storable for Event {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize Event");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize Event")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Append an event stamped with the current time
+pub fn emit(kind: EventKind) {
+    append(kind, crate::env::time());
+}
+
+fn append(kind: EventKind, timestamp: u64) -> u64 {
+    let seq = NEXT_EVENT_SEQ.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let seq = (*cell.get()).max(1);
+        cell.set(seq + 1).expect("Failed to store next event sequence");
+        seq
+    });
+
+    EVENTS.with(|store| {
+        let mut map = store.borrow_mut();
+        map.insert(seq, Event { seq, timestamp, kind });
+        while map.len() > MAX_EVENTS {
+            map.pop_first();
+        }
+    });
+    seq
+}
+
+/// Events with a sequence number greater than `seq`, oldest first
+pub fn get_events_since(seq: u64, limit: u64) -> Vec<Event> {
+    let limit = limit.min(MAX_EVENTS_PAGE) as usize;
+    EVENTS.with(|store| {
+        store.borrow()
+            .range(seq.saturating_add(1)..)
+            .take(limit)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+/// Delete events with a sequence number below `seq` (admin only); returns how many were removed
+pub fn prune_events_before(seq: u64) -> Result<u64, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can prune events".to_string());
+    }
+    Ok(EVENTS.with(|store| {
+        let mut map = store.borrow_mut();
+        let keys: Vec<u64> = map.range(..seq).map(|(key, _)| key).collect();
+        for key in &keys {
+            map.remove(key);
+        }
+        keys.len() as u64
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_cursor_catch_up() {
+        let first = append(EventKind::TicketIssued { wallet: "w".to_string(), epoch: 1 }, 10);
+        let second = append(EventKind::ClaimMarked { wallet: "w".to_string(), epoch: 1, success: true }, 20);
+        assert_eq!(second, first + 1);
+
+        let events = get_events_since(first - 1, 10);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].seq, first);
+        assert_eq!(events[1].timestamp, 20);
+
+        let rest = get_events_since(first, 10);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].seq, second);
+        assert!(get_events_since(second, 10).is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+// ICRC Payments Module - payments verified against an ICRC-1 ledger before they are recorded
+//
+// A controller registers each accepted ledger with the account payments must go to; the
+// ledger symbol is fetched once at registration. record_icrc_payment then looks the block up
+// on the ledger (or its archive), checks it, and records it through the normal payment path.
+// Errors are prefixed so clients can tell them apart:
+//   LedgerUnavailable: the ledger call failed, retry later
+//   PaymentVerificationFailed: the block does not prove the payment
+//   DuplicatePayment: the block was already recorded
+//   WalletSuspended: the wallet is suspended (checked before the ledger is called)
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_stable_structures::{Storable, storable::Bound};
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc3::transactions::{
+    GetTransactionsRequest, GetTransactionsResponse, Transaction, TransactionRange, TRANSACTION_TRANSFER,
+};
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use std::borrow::Cow;
+
+use crate::stable_mem_storage::{ICRC_LEDGERS, ICRC_PAYMENT_BLOCKS};
+use crate::task_rewards::{self, PaymentCurrency, PaymentRecord};
+
+/// An accepted ledger and where payments to it must be sent
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct IcrcLedgerConfig {
+    pub symbol: String,
+    pub receiving_account: Account,
+}
+
+impl Storable for IcrcLedgerConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize IcrcLedgerConfig");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize IcrcLedgerConfig")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Accept payments on `ledger` sent to `receiving_account` (admin only)
+pub async fn set_icrc_ledger(ledger: Principal, receiving_account: Account) -> Result<IcrcLedgerConfig, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can configure ICRC ledgers".to_string());
+    }
+    let (symbol,): (String,) = ic_cdk::call(ledger, "icrc1_symbol", ())
+        .await
+        .map_err(|(code, msg)| format!("LedgerUnavailable: icrc1_symbol failed ({:?}): {}", code, msg))?;
+    let config = IcrcLedgerConfig { symbol, receiving_account };
+    ICRC_LEDGERS.with(|store| store.borrow_mut().insert(ledger, config.clone()));
+    Ok(config)
+}
+
+/// Stop accepting payments on a ledger (admin only); recorded blocks stay deduplicated
+pub fn remove_icrc_ledger(ledger: Principal) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can configure ICRC ledgers".to_string());
+    }
+    ICRC_LEDGERS.with(|store| store.borrow_mut().remove(&ledger))
+        .map(|_| ())
+        .ok_or_else(|| format!("Ledger {} is not configured", ledger))
+}
+
+/// List accepted ledgers
+pub fn list_icrc_ledgers() -> Vec<(Principal, IcrcLedgerConfig)> {
+    ICRC_LEDGERS.with(|store| store.borrow().iter().collect())
+}
+
+/// Fetch one transaction from the ledger, following the archive callback for old blocks
+async fn fetch_transaction(ledger: Principal, block_index: u64) -> Result<Option<Transaction>, String> {
+    let request = GetTransactionsRequest { start: Nat::from(block_index), length: Nat::from(1u64) };
+    let (response,): (GetTransactionsResponse,) = ic_cdk::call(ledger, "get_transactions", (request.clone(),))
+        .await
+        .map_err(|(code, msg)| format!("LedgerUnavailable: get_transactions failed ({:?}): {}", code, msg))?;
+
+    if response.first_index == block_index {
+        return Ok(response.transactions.into_iter().next());
+    }
+
+    let archived = response.archived_transactions.into_iter().find(|range| {
+        range.start <= block_index && range.start.clone() + range.length.clone() > block_index
+    });
+    match archived {
+        Some(range) => {
+            let (archive_range,): (TransactionRange,) =
+                ic_cdk::call(range.callback.canister_id, &range.callback.method, (request,))
+                    .await
+                    .map_err(|(code, msg)| format!("LedgerUnavailable: archive call failed ({:?}): {}", code, msg))?;
+            Ok(archive_range.transactions.into_iter().next())
+        }
+        None => Ok(None),
+    }
+}
+
+/// Check that a transaction is a transfer of at least `expected_amount` to `receiving_account`.
+/// Returns (payer, amount, timestamp).
+fn verify_transfer(
+    tx: &Transaction,
+    receiving_account: &Account,
+    expected_amount: u64,
+) -> Result<(Account, u64, u64), String> {
+    if tx.kind != TRANSACTION_TRANSFER {
+        return Err(format!("PaymentVerificationFailed: block is a {}, not a transfer", tx.kind));
+    }
+    let transfer = tx.transfer.as_ref()
+        .ok_or_else(|| "PaymentVerificationFailed: transfer details missing".to_string())?;
+    if transfer.to != *receiving_account {
+        return Err(format!("PaymentVerificationFailed: transfer goes to {}, not {}", transfer.to, receiving_account));
+    }
+    let amount = transfer.amount.0.to_u64()
+        .ok_or_else(|| "PaymentVerificationFailed: amount exceeds u64".to_string())?;
+    if amount < expected_amount {
+        return Err(format!("PaymentVerificationFailed: transferred {} but expected at least {}", amount, expected_amount));
+    }
+    Ok((transfer.from, amount, tx.timestamp))
+}
+
+fn is_recorded(ledger: Principal, block_index: u64) -> bool {
+    ICRC_PAYMENT_BLOCKS.with(|store| store.borrow().contains_key(&(ledger, block_index)))
+}
+
+/// Record a payment after verifying block `block_index` on `ledger`. Callable by the payer
+/// of the block, a payment operator or a controller; like record_payment, it refuses a
+/// suspended wallet.
+pub async fn record_icrc_payment(
+    wallet: String,
+    ledger: Principal,
+    block_index: u64,
+    expected_amount: u64,
+    payfor: Option<String>,
+) -> Result<(), String> {
+    let wallet = task_rewards::normalize_wallet(&wallet)?;
+    task_rewards::suspensions::require_wallet_active(&wallet)?;
+    let caller = crate::env::caller();
+    let config = ICRC_LEDGERS.with(|store| store.borrow().get(&ledger))
+        .ok_or_else(|| format!("Ledger {} is not accepted for payments", ledger))?;
+    if is_recorded(ledger, block_index) {
+        return Err(format!("DuplicatePayment: block {} of ledger {} already recorded", block_index, ledger));
+    }
+
+    let tx = fetch_transaction(ledger, block_index).await?
+        .ok_or_else(|| format!("PaymentVerificationFailed: block {} not found on ledger {}", block_index, ledger))?;
+    let (payer, amount, ledger_ts) = verify_transfer(&tx, &config.receiving_account, expected_amount)?;
+    if payer.owner != caller && !task_rewards::is_payment_operator(&caller) && !crate::env::is_controller(&caller) {
+        return Err(format!("NotAuthorized: {} is not the payer of block {}", caller, block_index));
+    }
+
+    // Another call for the same block may have completed while we awaited the ledger
+    if is_recorded(ledger, block_index) {
+        return Err(format!("DuplicatePayment: block {} of ledger {} already recorded", block_index, ledger));
+    }
+    let payment_id = task_rewards::apply_payment(PaymentRecord {
+        wallet,
+        amount_paid: amount,
+        tx_ref: format!("icrc:{}:{}", ledger, block_index),
+        ts: crate::env::time(),
+        payfor,
+        recorded_by: Some(caller),
+        currency: PaymentCurrency::from_symbol(&config.symbol),
+        exchange_rate: None,
+        token: Some(config.symbol),
+        client_ts: Some(ledger_ts),
+    })?;
+    ICRC_PAYMENT_BLOCKS.with(|store| store.borrow_mut().insert((ledger, block_index), payment_id));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icrc_ledger_types::icrc3::transactions::Transfer;
+
+    fn account(id: u8) -> Account {
+        Account { owner: Principal::from_slice(&[id]), subaccount: None }
+    }
+
+    fn transfer(to: Account, amount: u64) -> Transaction {
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(amount),
+                from: account(1),
+                to,
+                spender: None,
+                memo: None,
+                fee: None,
+                created_at_time: None,
+            },
+            42,
+        )
+    }
+
+    #[test]
+    fn test_verify_transfer() {
+        let receiving = account(2);
+        let (payer, amount, ts) = verify_transfer(&transfer(receiving, 100), &receiving, 100).unwrap();
+        assert_eq!((payer, amount, ts), (account(1), 100, 42));
+        // A zero subaccount is the default subaccount
+        let explicit = Account { owner: receiving.owner, subaccount: Some([0; 32]) };
+        assert!(verify_transfer(&transfer(explicit, 100), &receiving, 100).is_ok());
+
+        let short = verify_transfer(&transfer(receiving, 99), &receiving, 100).unwrap_err();
+        assert!(short.starts_with("PaymentVerificationFailed"));
+        let elsewhere = verify_transfer(&transfer(account(3), 100), &receiving, 100).unwrap_err();
+        assert!(elsewhere.starts_with("PaymentVerificationFailed"));
+    }
+
+    #[test]
+    fn test_suspended_wallet_payment_is_refused_before_the_ledger_call() {
+        let _env = crate::env::TestEnvironment::install(Principal::from_slice(&[0xad]), 1_000);
+        let wallet = bs58::encode([7u8; 32]).into_string();
+        task_rewards::suspensions::suspend_wallet(wallet.clone(), "fraud".to_string(), None).unwrap();
+
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        let payment = std::pin::pin!(record_icrc_payment(wallet, Principal::from_slice(&[9]), 5, 100, None));
+        match std::future::Future::poll(payment, &mut context) {
+            std::task::Poll::Ready(Err(err)) => assert!(err.starts_with("WalletSuspended")),
+            other => panic!("expected an immediate error, got {:?}", other),
+        }
+        assert!(!is_recorded(Principal::from_slice(&[9]), 5));
+    }
+}
@@ -0,0 +1,311 @@
+// ICRC Payouts Module - pay task rewards to an ICRC-1 account instead of a Solana Merkle claim
+//
+// For users with an Internet Identity but no Solana wallet. The principal bound to a wallet
+// can have its Completed (not yet snapshotted) rewards transferred from the canister's account
+// on the configured ledger. Those tasks become Claimed and so never reach a Merkle snapshot.
+//
+// While the transfer is awaited the wallet is marked in flight: a second payout for it is
+// refused, build_epoch_snapshot skips it, and add_wallet_to_epoch, migrate_wallet and task
+// rollbacks refuse it. Task statuses only change after the ledger
+// confirms, so a failed transfer leaves the rewards where they were.
+// Errors are prefixed so clients can tell them apart:
+//   PayoutInFlight: another payout for the wallet has not finished
+//   LedgerUnavailable: the ledger call failed, retry later
+//   PayoutFailed: the ledger rejected the transfer
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_stable_structures::{Storable, storable::Bound};
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc1::transfer::{TransferArg, TransferError};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::{ICRC_PAYOUT_CONFIG, ICRC_PAYOUTS};
+use crate::task_rewards;
+
+/// Ledger paid out from and the largest amount one call may transfer
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct IcrcPayoutConfig {
+    pub ledger: Option<Principal>,  // None disables ICRC payouts
+    pub max_payout_per_call: u64,   // Tasks beyond this are left for a later call
+}
+
+impl Storable for IcrcPayoutConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize IcrcPayoutConfig");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize IcrcPayoutConfig")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A completed payout; the claim history of the ICRC settlement path
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct IcrcPayoutRecord {
+    pub wallet: String,
+    pub account: Account,
+    pub amount: u64,
+    pub taskids: Vec<String>,
+    pub tx_sig: String,  // "icrc:{ledger}:{block index}"
+    pub ts: u64,
+}
+
+impl Storable for IcrcPayoutRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize IcrcPayoutRecord");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize IcrcPayoutRecord")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Wallets with a transfer awaiting the ledger (heap only: no call is in flight across an upgrade)
+    static PAYOUTS_IN_FLIGHT: RefCell<BTreeSet<String>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// Marks a wallet in flight until dropped, including on early returns and failed transfers
+struct PayoutGuard {
+    wallet: String,
+}
+
+impl PayoutGuard {
+    fn acquire(wallet: &str) -> Result<Self, String> {
+        let inserted = PAYOUTS_IN_FLIGHT.with(|set| set.borrow_mut().insert(wallet.to_string()));
+        if !inserted {
+            return Err(format!("PayoutInFlight: a payout for wallet {} is already in progress", wallet));
+        }
+        Ok(PayoutGuard { wallet: wallet.to_string() })
+    }
+}
+
+impl Drop for PayoutGuard {
+    fn drop(&mut self) {
+        PAYOUTS_IN_FLIGHT.with(|set| set.borrow_mut().remove(&self.wallet));
+    }
+}
+
+/// Whether a payout for the wallet is awaiting the ledger
+pub fn is_payout_in_flight(wallet: &str) -> bool {
+    PAYOUTS_IN_FLIGHT.with(|set| set.borrow().contains(wallet))
+}
+
+/// Refuse a change to a wallet's tasks or leaves while a payout for it is awaiting the ledger:
+/// the payout marks the tasks it selected Claimed once the transfer lands
+pub fn check_no_payout_in_flight(wallet: &str) -> Result<(), String> {
+    if is_payout_in_flight(wallet) {
+        return Err(format!("PayoutInFlight: a payout for wallet {} has not finished", wallet));
+    }
+    Ok(())
+}
+
+/// Get the payout configuration
+pub fn get_icrc_payout_config() -> IcrcPayoutConfig {
+    ICRC_PAYOUT_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+/// Update the payout configuration (admin only)
+pub fn set_icrc_payout_config(config: IcrcPayoutConfig) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can set ICRC payout config".to_string());
+    }
+    if config.ledger.is_some() && config.max_payout_per_call == 0 {
+        return Err("max_payout_per_call must be non-zero when a ledger is set".to_string());
+    }
+    ICRC_PAYOUT_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store ICRC payout config: {:?}", e))
+    })
+}
+
+/// Payouts made to a wallet, oldest first
+pub fn list_icrc_payouts(wallet: String) -> Vec<IcrcPayoutRecord> {
+    let Ok(wallet) = task_rewards::normalize_wallet(&wallet) else {
+        return Vec::new();
+    };
+    ICRC_PAYOUTS.with(|store| {
+        store.borrow()
+            .iter()
+            .filter(|(_, record)| record.wallet == wallet)
+            .map(|(_, record)| record)
+            .collect()
+    })
+}
+
+/// Transfer the wallet's unsnapshotted rewards (up to the per-call cap) to `account` on the
+/// configured ledger. Only the principal bound to the wallet may call. Returns the block index.
+pub async fn claim_to_icp_account(wallet: String, account: Account) -> Result<Nat, String> {
+    let wallet = require_payout_caller(&wallet)?;
+    task_rewards::suspensions::require_wallet_active(&wallet)?;
+    task_rewards::wallet_flags::check_not_flagged(&wallet)?;
+    let config = get_icrc_payout_config();
+    let ledger = config.ledger.ok_or_else(|| "ICRC payouts are not configured".to_string())?;
+
+    let _guard = PayoutGuard::acquire(&wallet)?;
+    let (taskids, amount) = task_rewards::payout_tasks(&wallet, config.max_payout_per_call)?;
+
+    let now = crate::env::time();
+    let arg = TransferArg {
+        from_subaccount: None,
+        to: account,
+        fee: None,
+        created_at_time: Some(now),
+        memo: None,
+        amount: Nat::from(amount),
+    };
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+        .await
+        .map_err(|(code, msg)| format!("LedgerUnavailable: icrc1_transfer failed ({:?}): {}", code, msg))?;
+    let block_index = result.map_err(|e| format!("PayoutFailed: {:?}", e))?;
+
+    let tx_sig = format!("icrc:{}:{}", ledger, block_index);
+    task_rewards::mark_tasks_paid_out(&wallet, &taskids, amount, &tx_sig);
+    ICRC_PAYOUTS.with(|store| {
+        let mut map = store.borrow_mut();
+        let id = map.len();
+        map.insert(id, IcrcPayoutRecord {
+            wallet: wallet.clone(),
+            account,
+            amount,
+            taskids,
+            tx_sig: tx_sig.clone(),
+            ts: now,
+        });
+    });
+    event_log::emit(EventKind::PaidOut { wallet, amount, tx_sig });
+    Ok(block_index)
+}
+
+/// Normalized `wallet` if the caller is its bound principal. The anonymous principal never
+/// is, even if a binding to it was stored before binding it was refused.
+fn require_payout_caller(wallet: &str) -> Result<String, String> {
+    let caller = crate::env::caller();
+    let wallet = task_rewards::normalize_wallet(wallet)?;
+    if caller == Principal::anonymous()
+        || crate::ai_sub_service::get_wallet_principal(&wallet) != Some(caller.to_text())
+    {
+        return Err(format!("NotAuthorized: wallet {} is not bound to {}", wallet, caller));
+    }
+    Ok(wallet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::TestEnvironment;
+    use crate::stable_mem_storage::WALLET_PRINCIPALS;
+
+    #[test]
+    fn test_anonymous_caller_cannot_claim() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use sha2::{Digest, Sha256};
+
+        let env = TestEnvironment::install(Principal::from_slice(&[0xad]), 1_000);
+        let key = SigningKey::from_bytes(&[9u8; 32]);
+        let wallet = bs58::encode(key.verifying_key().to_bytes()).into_string();
+        let anonymous = Principal::anonymous();
+        let message = crate::ai_sub_service::get_binding_message(anonymous.to_text(), wallet.clone()).into_bytes();
+        let signature = key.sign(&Sha256::digest(&message)).to_bytes().to_vec();
+
+        env.caller.set(None);
+        assert!(crate::ai_sub_service::bind_wallet_with_proof(wallet.clone(), signature, message).unwrap_err().contains("anonymous"));
+        assert!(require_payout_caller(&wallet).unwrap_err().starts_with("NotAuthorized"));
+
+        // A binding to the anonymous principal stored earlier still grants nothing
+        WALLET_PRINCIPALS.with(|store| store.borrow_mut().insert(wallet.clone(), anonymous.to_text()));
+        assert!(require_payout_caller(&wallet).unwrap_err().starts_with("NotAuthorized"));
+        let owner = Principal::from_slice(&[1]);
+        WALLET_PRINCIPALS.with(|store| store.borrow_mut().insert(wallet.clone(), owner.to_text()));
+        env.set_caller(owner);
+        assert_eq!(require_payout_caller(&wallet), Ok(wallet));
+    }
+
+    fn poll_once<F: std::future::Future>(future: F) -> std::task::Poll<F::Output> {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        std::future::Future::poll(std::pin::pin!(future), &mut context)
+    }
+
+    #[test]
+    fn test_suspended_wallet_cannot_claim() {
+        let env = TestEnvironment::install(Principal::from_slice(&[0xad]), 1_000);
+        let wallet = bs58::encode([5u8; 32]).into_string();
+        let owner = Principal::from_slice(&[2]);
+        WALLET_PRINCIPALS.with(|store| store.borrow_mut().insert(wallet.clone(), owner.to_text()));
+        task_rewards::suspensions::suspend_wallet(wallet.clone(), "abuse".to_string(), None).unwrap();
+
+        // The claim fails before its first await, so the ledger is never called
+        env.set_caller(owner);
+        let account = Account { owner, subaccount: None };
+        match poll_once(claim_to_icp_account(wallet.clone(), account)) {
+            std::task::Poll::Ready(Err(err)) => assert!(err.starts_with("WalletSuspended")),
+            other => panic!("expected an immediate error, got {:?}", other),
+        }
+        assert!(!is_payout_in_flight(&wallet));
+    }
+
+    #[test]
+    fn test_flagged_wallet_cannot_claim() {
+        let env = TestEnvironment::install(Principal::from_slice(&[0xad]), 1_000);
+        let wallet = bs58::encode([6u8; 32]).into_string();
+        let owner = Principal::from_slice(&[3]);
+        WALLET_PRINCIPALS.with(|store| store.borrow_mut().insert(wallet.clone(), owner.to_text()));
+        task_rewards::wallet_flags::flag_wallet(wallet.clone(), "sybil".to_string()).unwrap();
+
+        env.set_caller(owner);
+        let account = Account { owner, subaccount: None };
+        match poll_once(claim_to_icp_account(wallet.clone(), account)) {
+            std::task::Poll::Ready(Err(err)) => assert!(err.starts_with("WalletFlagged")),
+            other => panic!("expected an immediate error, got {:?}", other),
+        }
+        assert!(!is_payout_in_flight(&wallet));
+    }
+
+    #[test]
+    fn test_wallet_changes_wait_for_payout_in_flight() {
+        let _env = TestEnvironment::install(Principal::from_slice(&[0xad]), 1_000);
+        let wallet = bs58::encode([3u8; 32]).into_string();
+        let guard = PayoutGuard::acquire(&wallet).unwrap();
+
+        let rollbacks = [
+            task_rewards::rollbacks::admin_rollback_task_to_in_progress,
+            task_rewards::rollbacks::admin_rollback_task_to_not_started,
+        ];
+        let mut refused = vec![
+            task_rewards::add_wallet_to_epoch(1, wallet.clone()).map(|_| ()),
+            task_rewards::migrate_wallet(wallet.clone(), bs58::encode([4u8; 32]).into_string(), "lost key".to_string()).map(|_| ()),
+        ];
+        refused.extend(rollbacks.map(|rollback| rollback(wallet.clone(), "follow".to_string(), "forged".to_string())));
+        for result in refused {
+            assert!(result.unwrap_err().starts_with("PayoutInFlight"));
+        }
+
+        drop(guard);
+        assert_eq!(check_no_payout_in_flight(&wallet), Ok(()));
+        assert!(!task_rewards::add_wallet_to_epoch(1, wallet).unwrap_err().starts_with("PayoutInFlight"));
+    }
+
+    #[test]
+    fn test_payout_guard_blocks_reentry_until_dropped() {
+        let guard = PayoutGuard::acquire("w").unwrap();
+        assert!(is_payout_in_flight("w"));
+        assert!(PayoutGuard::acquire("w").err().unwrap().starts_with("PayoutInFlight"));
+        assert!(PayoutGuard::acquire("other").is_ok());
+
+        drop(guard);
+        assert!(!is_payout_in_flight("w"));
+        assert!(PayoutGuard::acquire("w").is_ok());
+    }
+}
@@ -20,6 +20,17 @@ mod ai_types;
 mod ai_subscription_types;
 mod ai_sub_service;
 pub mod task_rewards;
+mod claim_signing;
+mod rate_limit;
+mod event_log;
+mod icrc_payments;
+mod icrc_payouts;
+mod claim_sync;
+mod epoch_webhook;
+mod storage_utils;
+mod dry_run;
+mod env;
+mod schema_versions;
 
 use candid::candid_method;
 use candid::{CandidType, Deserialize};
@@ -52,7 +63,7 @@ use std::cell::RefCell;
 use candid::Principal;
 use crate::bitpay::{create_invoice as bp_create_invoice, get_invoice as bp_get_invoice, set_pos_token as bp_set_pos_token, token as bp_token};
 use crate::hmac::verify_webhook_sig;
-use ai_types::UserAiConfig;
+use ai_types::{UserAiConfig, CatalogEntry, DeletedAiConfig};
 
 pub use account_storage::*;
 pub use trace_storage::*;
@@ -95,6 +106,46 @@ fn dispatch_mining_rewards() -> Result<(), String> {
     Ok(())
 }
 
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    schema_versions::log_before_upgrade();
+}
+
+// Check the stored schema versions, restore the certified epoch root and log task contract
+// problems after every upgrade; the health check runs in its own message so a large user set
+// cannot make the upgrade fail
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    if let Err(e) = schema_versions::check_on_upgrade() {
+        ic_cdk::trap(&format!("Schema version check failed: {}", e));
+    }
+    task_rewards::restore_certified_epoch_root();
+    claim_sync::schedule_claim_sync();
+    task_rewards::funnel::rebuild_if_empty();
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        let added = task_rewards::backfill_wallet_epoch_index();
+        if added > 0 {
+            ic_cdk::println!("Backfilled wallet epoch index with {} entries", added);
+        }
+        let added = task_rewards::backfill_payment_currency_index();
+        if added > 0 {
+            ic_cdk::println!("Backfilled payment currency index with {} entries", added);
+        }
+        let added = task_rewards::backfill_payment_wallet_index();
+        if added > 0 {
+            ic_cdk::println!("Backfilled payment wallet index with {} entries", added);
+        }
+    });
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        let report = task_rewards::task_contract_health();
+        if report.healthy {
+            ic_cdk::println!("Task contract health check passed ({} tasks)", report.total_tasks);
+        } else {
+            ic_cdk::println!("Task contract health check FAILED: {:?}", report);
+        }
+    });
+}
+
 // add stop mining rewards function
 #[ic_cdk::update]
 fn stop_mining_rewards() -> Result<(), String> {
@@ -1964,6 +2015,62 @@ fn delete_user_ai_config(principal_id: String) -> Result<(), String> {
     result
 }
 
+#[ic_cdk::update]
+fn soft_delete_user_ai_config(principal_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[soft_delete_user_ai_config] Input: principal_id={}", principal_id);
+    let result = ai_types::soft_delete_user_ai_config(principal_id);
+    ic_cdk::println!("CALL[soft_delete_user_ai_config] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::update]
+fn set_user_ai_configs_batch(configs: Vec<UserAiConfig>) -> Result<Vec<Result<(), String>>, String> {
+    ic_cdk::println!("CALL[set_user_ai_configs_batch] Input: {} configs", configs.len());
+    let result = ai_types::set_user_ai_configs_batch(configs);
+    ic_cdk::println!("CALL[set_user_ai_configs_batch] Output: ok={}", result.is_ok());
+    result
+}
+
+#[ic_cdk::update]
+fn delete_user_ai_configs_batch(principal_ids: Vec<String>) -> Result<Vec<Result<(), String>>, String> {
+    ic_cdk::println!("CALL[delete_user_ai_configs_batch] Input: {} principals", principal_ids.len());
+    let result = ai_types::delete_user_ai_configs_batch(principal_ids);
+    ic_cdk::println!("CALL[delete_user_ai_configs_batch] Output: ok={}", result.is_ok());
+    result
+}
+
+#[ic_cdk::update]
+fn restore_user_ai_config(principal_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[restore_user_ai_config] Input: principal_id={}", principal_id);
+    let result = ai_types::restore_user_ai_config(principal_id);
+    ic_cdk::println!("CALL[restore_user_ai_config] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn list_deleted_ai_configs() -> Result<Vec<DeletedAiConfig>, String> {
+    ic_cdk::println!("CALL[list_deleted_ai_configs] Input: none");
+    let result = ai_types::list_deleted_ai_configs();
+    ic_cdk::println!("CALL[list_deleted_ai_configs] Output: {:?}", result.as_ref().map(|v| v.len()));
+    result
+}
+
+#[ic_cdk::query]
+fn list_expiring_ai_configs(within_ns: u64) -> Result<Vec<UserAiConfig>, String> {
+    ic_cdk::println!("CALL[list_expiring_ai_configs] Input: within_ns={}", within_ns);
+    let result = ai_types::list_expiring_ai_configs(within_ns);
+    ic_cdk::println!("CALL[list_expiring_ai_configs] Output: {:?}", result.as_ref().map(|v| v.len()));
+    result
+}
+
+#[ic_cdk::update]
+fn purge_deleted_ai_configs(older_than_ts: u64) -> Result<u64, String> {
+    ic_cdk::println!("CALL[purge_deleted_ai_configs] Input: older_than_ts={}", older_than_ts);
+    let result = ai_types::purge_deleted_ai_configs(older_than_ts);
+    ic_cdk::println!("CALL[purge_deleted_ai_configs] Output: {:?}", result);
+    result
+}
+
 #[ic_cdk::query]
 fn has_user_ai_config(principal_id: String) -> bool {
     ic_cdk::println!("CALL[has_user_ai_config] Input: principal_id={}", principal_id);
@@ -1972,233 +2079,1800 @@ fn has_user_ai_config(principal_id: String) -> bool {
     result
 }
 
-// ==== Task Rewards API ====
+#[ic_cdk::query]
+fn list_user_ai_configs(principal_id: String) -> Vec<UserAiConfig> {
+    ic_cdk::println!("CALL[list_user_ai_configs] Input: principal_id={}", principal_id);
+    let result = ai_types::list_user_ai_configs(principal_id);
+    ic_cdk::println!("CALL[list_user_ai_configs] Output: count={}", result.len());
+    result
+}
 
-use task_rewards::{TaskContractItem, UserTaskState, ClaimTicket, ClaimResultStatus, MerkleSnapshotMeta};
+#[ic_cdk::query]
+fn get_user_ai_config_by_agent(principal_id: String, agent_id: String) -> Option<UserAiConfig> {
+    ic_cdk::println!("CALL[get_user_ai_config_by_agent] Input: principal_id={}, agent_id={}", principal_id, agent_id);
+    let result = ai_types::get_user_ai_config_by_agent(principal_id, agent_id);
+    ic_cdk::println!("CALL[get_user_ai_config_by_agent] Output: exists={}", result.is_some());
+    result
+}
 
-/// Initialize task contract (admin only)
 #[ic_cdk::update]
-fn init_task_contract(tasks: Vec<TaskContractItem>) -> Result<(), String> {
-    ic_cdk::println!("CALL[init_task_contract] Input: {} tasks", tasks.len());
-    let result = task_rewards::init_task_contract(tasks);
-    ic_cdk::println!("CALL[init_task_contract] Output: {:?}", result);
+fn delete_user_ai_config_by_agent(principal_id: String, agent_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[delete_user_ai_config_by_agent] Input: principal_id={}, agent_id={}", principal_id, agent_id);
+    let result = ai_types::delete_user_ai_config_by_agent(principal_id, agent_id);
+    ic_cdk::println!("CALL[delete_user_ai_config_by_agent] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::update]
+fn set_ai_config_setting(principal_id: String, key: String, value: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_ai_config_setting] Input: principal_id={}, key={}", principal_id, key);
+    let result = ai_types::set_ai_config_setting(principal_id, key, value);
+    ic_cdk::println!("CALL[set_ai_config_setting] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::update]
+fn remove_ai_config_setting(principal_id: String, key: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[remove_ai_config_setting] Input: principal_id={}, key={}", principal_id, key);
+    let result = ai_types::remove_ai_config_setting(principal_id, key);
+    ic_cdk::println!("CALL[remove_ai_config_setting] Output: {:?}", result);
     result
 }
 
-/// Get task contract
 #[ic_cdk::query]
-fn get_task_contract() -> Vec<TaskContractItem> {
-    ic_cdk::println!("CALL[get_task_contract] Input: none");
-    let result = task_rewards::get_task_contract();
-    ic_cdk::println!("CALL[get_task_contract] Output: {} tasks", result.len());
+fn get_user_ai_config_history(principal_id: String, limit: u64) -> Vec<UserAiConfig> {
+    ic_cdk::println!("CALL[get_user_ai_config_history] Input: principal_id={}, limit={}", principal_id, limit);
+    let result = ai_types::get_user_ai_config_history(principal_id, limit);
+    ic_cdk::println!("CALL[get_user_ai_config_history] Output: count={}", result.len());
     result
 }
 
-/// Get or initialize user tasks (user login)
 #[ic_cdk::query]
-fn get_or_init_user_tasks(wallet: String) -> UserTaskState {
-    ic_cdk::println!("CALL[get_or_init_user_tasks] Input: wallet={}", wallet);
-    let result = task_rewards::get_or_init_user_tasks(wallet);
-    ic_cdk::println!("CALL[get_or_init_user_tasks] Output: {} tasks", result.tasks.len());
+fn get_max_agents_per_principal() -> u64 {
+    ai_types::get_max_agents_per_principal()
+}
+
+#[ic_cdk::update]
+fn set_max_agents_per_principal(limit: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_max_agents_per_principal] Input: limit={}", limit);
+    let result = ai_types::set_max_agents_per_principal(limit);
+    ic_cdk::println!("CALL[set_max_agents_per_principal] Output: {:?}", result);
     result
 }
 
-/// Record payment and trigger AI subscription task completion
 #[ic_cdk::update]
-fn record_payment(
-    wallet: String,
-    amount_paid: u64,
-    tx_ref: String,
-    ts: u64,
-    payfor: Option<String>,
-) -> Result<(), String> {
-    ic_cdk::println!("CALL[record_payment] Input: wallet={}, amount={}, tx_ref={}, payfor={:?}", 
-                     wallet, amount_paid, tx_ref, payfor);
-    let result = task_rewards::record_payment(wallet, amount_paid, tx_ref, ts, payfor);
-    ic_cdk::println!("CALL[record_payment] Output: {:?}", result);
+fn backfill_user_ai_configs() -> Result<u64, String> {
+    ic_cdk::println!("CALL[backfill_user_ai_configs] Input: none");
+    let result = ai_types::backfill_user_ai_configs();
+    ic_cdk::println!("CALL[backfill_user_ai_configs] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn list_ai_configs(offset: u64, limit: u64, include_deleted: Option<bool>) -> Result<Vec<UserAiConfig>, String> {
+    ic_cdk::println!("CALL[list_ai_configs] Input: offset={}, limit={}, include_deleted={:?}", offset, limit, include_deleted);
+    let result = ai_types::list_ai_configs(offset, limit, include_deleted.unwrap_or(false));
+    ic_cdk::println!("CALL[list_ai_configs] Output: {:?}", result.as_ref().map(|c| c.len()));
+    result
+}
+
+#[ic_cdk::query]
+fn count_ai_configs() -> Result<u64, String> {
+    ic_cdk::println!("CALL[count_ai_configs] Input: none");
+    let result = ai_types::count_ai_configs();
+    ic_cdk::println!("CALL[count_ai_configs] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn find_ai_configs_by_voice(voice_id: String, offset: u64, limit: u64, include_deleted: Option<bool>) -> Result<Vec<UserAiConfig>, String> {
+    ic_cdk::println!("CALL[find_ai_configs_by_voice] Input: voice_id={}, offset={}, limit={}, include_deleted={:?}", voice_id, offset, limit, include_deleted);
+    let result = ai_types::find_ai_configs_by_voice(voice_id, offset, limit, include_deleted.unwrap_or(false));
+    ic_cdk::println!("CALL[find_ai_configs_by_voice] Output: {:?}", result.as_ref().map(|c| c.len()));
+    result
+}
+
+#[ic_cdk::query]
+fn find_ai_configs_by_agent(agent_id: String, offset: u64, limit: u64, include_deleted: Option<bool>) -> Result<Vec<UserAiConfig>, String> {
+    ic_cdk::println!("CALL[find_ai_configs_by_agent] Input: agent_id={}, offset={}, limit={}, include_deleted={:?}", agent_id, offset, limit, include_deleted);
+    let result = ai_types::find_ai_configs_by_agent(agent_id, offset, limit, include_deleted.unwrap_or(false));
+    ic_cdk::println!("CALL[find_ai_configs_by_agent] Output: {:?}", result.as_ref().map(|c| c.len()));
     result
 }
 
-/// Complete a task (register device, voice clone, etc.)
 #[ic_cdk::update]
-fn complete_task(
-    wallet: String,
-    taskid: String,
-    evidence: Option<String>,
-    ts: u64,
-) -> Result<(), String> {
-    ic_cdk::println!("CALL[complete_task] Input: wallet={}, taskid={}, evidence={:?}", 
-                     wallet, taskid, evidence);
-    let result = task_rewards::complete_task(wallet, taskid, evidence, ts);
-    ic_cdk::println!("CALL[complete_task] Output: {:?}", result);
+fn rebuild_ai_config_indexes() -> Result<u64, String> {
+    ic_cdk::println!("CALL[rebuild_ai_config_indexes] Input: none");
+    let result = ai_types::rebuild_ai_config_indexes();
+    ic_cdk::println!("CALL[rebuild_ai_config_indexes] Output: {:?}", result);
     result
 }
 
-/// Build epoch snapshot - generates Merkle tree (admin/scheduled)
 #[ic_cdk::update]
-fn build_epoch_snapshot(epoch: u64) -> Result<MerkleSnapshotMeta, String> {
-    ic_cdk::println!("CALL[build_epoch_snapshot] Input: epoch={}", epoch);
-    let result = task_rewards::build_epoch_snapshot(epoch);
-    match &result {
-        Ok(meta) => ic_cdk::println!("CALL[build_epoch_snapshot] Output: Success - {} leaves, root={:?}", 
-                                    meta.leaves_count, meta.root),
-        Err(e) => ic_cdk::println!("CALL[build_epoch_snapshot] Output: Error - {}", e),
-    }
+fn add_agent_catalog_entry(entry: CatalogEntry) -> Result<(), String> {
+    ic_cdk::println!("CALL[add_agent_catalog_entry] Input: id={}, enabled={}", entry.id, entry.enabled);
+    let result = ai_types::add_agent_catalog_entry(entry);
+    ic_cdk::println!("CALL[add_agent_catalog_entry] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::update]
+fn disable_agent_catalog_entry(id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[disable_agent_catalog_entry] Input: id={}", id);
+    let result = ai_types::disable_agent_catalog_entry(id);
+    ic_cdk::println!("CALL[disable_agent_catalog_entry] Output: {:?}", result);
     result
 }
 
-/// Get claim ticket for frontend to submit on-chain
 #[ic_cdk::query]
-fn get_claim_ticket(wallet: String) -> Result<ClaimTicket, String> {
-    ic_cdk::println!("CALL[get_claim_ticket] Input: wallet={}", wallet);
-    let result = task_rewards::get_claim_ticket(wallet);
-    match &result {
-        Ok(ticket) => ic_cdk::println!("CALL[get_claim_ticket] Output: Success - epoch={}, index={}, amount={}", 
-                                      ticket.epoch, ticket.index, ticket.amount),
-        Err(e) => ic_cdk::println!("CALL[get_claim_ticket] Output: Error - {}", e),
-    }
+fn list_agent_catalog() -> Vec<CatalogEntry> {
+    ai_types::list_agent_catalog()
+}
+
+#[ic_cdk::update]
+fn add_voice_catalog_entry(entry: CatalogEntry) -> Result<(), String> {
+    ic_cdk::println!("CALL[add_voice_catalog_entry] Input: id={}, enabled={}", entry.id, entry.enabled);
+    let result = ai_types::add_voice_catalog_entry(entry);
+    ic_cdk::println!("CALL[add_voice_catalog_entry] Output: {:?}", result);
     result
 }
 
-/// Mark claim result after on-chain transaction
 #[ic_cdk::update]
-fn mark_claim_result(
-    wallet: String,
-    epoch: u64,
-    status: ClaimResultStatus,
-    tx_sig: Option<String>,
-) -> Result<(), String> {
-    ic_cdk::println!("CALL[mark_claim_result] Input: wallet={}, epoch={}, status={:?}, tx={:?}", 
-                     wallet, epoch, status, tx_sig);
-    let result = task_rewards::mark_claim_result(wallet, epoch, status, tx_sig);
-    ic_cdk::println!("CALL[mark_claim_result] Output: {:?}", result);
+fn disable_voice_catalog_entry(id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[disable_voice_catalog_entry] Input: id={}", id);
+    let result = ai_types::disable_voice_catalog_entry(id);
+    ic_cdk::println!("CALL[disable_voice_catalog_entry] Output: {:?}", result);
     result
 }
 
-/// Get epoch metadata
 #[ic_cdk::query]
-fn get_epoch_meta(epoch: u64) -> Option<MerkleSnapshotMeta> {
-    ic_cdk::println!("CALL[get_epoch_meta] Input: epoch={}", epoch);
-    let result = task_rewards::get_epoch_meta(epoch);
-    ic_cdk::println!("CALL[get_epoch_meta] Output: exists={}", result.is_some());
-    result
+fn list_voice_catalog() -> Vec<CatalogEntry> {
+    ai_types::list_voice_catalog()
 }
 
-/// List all epoch metadata
 #[ic_cdk::query]
-fn list_all_epochs() -> Vec<MerkleSnapshotMeta> {
-    ic_cdk::println!("CALL[list_all_epochs] Input: none");
-    let result = task_rewards::list_all_epochs();
-    ic_cdk::println!("CALL[list_all_epochs] Output: {} epochs", result.len());
+fn validate_user_ai_config(principal_id: String) -> Vec<String> {
+    ic_cdk::println!("CALL[validate_user_ai_config] Input: principal_id={}", principal_id);
+    let result = ai_types::validate_user_ai_config(principal_id);
+    ic_cdk::println!("CALL[validate_user_ai_config] Output: {:?}", result);
     result
 }
 
-// ==== AI Subscription API ====
+#[ic_cdk::update]
+fn grant_ai_capability(principal_id: String, capability: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[grant_ai_capability] Input: principal_id={}, capability={:#x}", principal_id, capability);
+    let result = ai_types::grant_ai_capability(principal_id, capability);
+    ic_cdk::println!("CALL[grant_ai_capability] Output: {:?}", result);
+    result
+}
 
-use ai_subscription_types::{ServiceType, SubscriptionRecord, SubscriptionStatus};
+#[ic_cdk::update]
+fn revoke_ai_capability(principal_id: String, capability: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[revoke_ai_capability] Input: principal_id={}, capability={:#x}", principal_id, capability);
+    let result = ai_types::revoke_ai_capability(principal_id, capability);
+    ic_cdk::println!("CALL[revoke_ai_capability] Output: {:?}", result);
+    result
+}
 
 #[ic_cdk::update]
-fn ai_sub_create_service(service: ServiceType) -> Result<(), String> {
-    ic_cdk::println!("CALL[ai_sub_create_service] Input: svr_id={}", service.svr_id);
-    let result = ai_sub_service::create_service(service);
-    ic_cdk::println!("CALL[ai_sub_create_service] Output: {:?}", result);
+fn renew_ai_config(principal_id: String, extend_ns: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[renew_ai_config] Input: principal_id={}, extend_ns={}", principal_id, extend_ns);
+    let result = ai_types::renew_ai_config(principal_id, extend_ns);
+    ic_cdk::println!("CALL[renew_ai_config] Output: {:?}", result);
     result
 }
 
 #[ic_cdk::query]
-fn ai_sub_get_service(svr_id: String) -> Option<ServiceType> {
-    ic_cdk::println!("CALL[ai_sub_get_service] Input: svr_id={}", svr_id);
-    let result = ai_sub_service::get_service(&svr_id);
-    ic_cdk::println!("CALL[ai_sub_get_service] Output: exists={}", result.is_some());
+fn check_ai_capability(principal_id: String, capability: u64) -> bool {
+    ai_types::check_ai_capability(principal_id, capability)
+}
+
+// ==== Task Rewards API ====
+
+use task_rewards::{TaskContractItem, UserTaskState, UserTaskDetail, ClaimTicket, LegacyClaimTicket, ClaimResultStatus, MerkleSnapshotMeta, BuildEpochOptions, VestingPolicy, TicketIssuance, LeafHashTestVector, MerkleNodeCounts, EpochTreeExport, PaymentRecord, IndexedPayment, PaymentReceipt, PaymentCurrency, WalletMigration, WalletMigrationReport, PayforStats, LeaderboardEntry, TaskContractHealthReport, CertifiedResult, EpochPreview, ChainTarget, VestingEntry, EpochFilter, EpochPage, EpochContinuityReport, AdminTaskCompletion, AdminBatchResult, WalletClaimSummary, UserTaskStatePage, EpochWalletPage, EpochEntriesChunk};
+use task_rewards::payment_archive::ArchivedPaymentBatch;
+use task_rewards::ticket_encoding::{ClaimTicketEncoded, ProofEncoding};
+use task_rewards::referrals::ReferralStats;
+use task_rewards::notifications::TaskNotification;
+use task_rewards::wallet_flags::WalletFlag;
+use task_rewards::suspensions::WalletSuspension;
+use task_rewards::liability::LiabilitySummary;
+use task_rewards::funnel::TaskFunnel;
+use task_rewards::root_overrides::EpochRootOverride;
+use task_rewards::wallet_listing::WalletFilter;
+use task_rewards::checkins::{CheckinReceipt, StreakView};
+use task_rewards::attestations::EvidenceSubmission;
+use task_rewards::reward_logs::RewardCalculationLog;
+use schema_versions::SchemaVersionReport;
+use task_rewards::wallet_meta::WalletMeta;
+use claim_signing::{ClaimSigningConfig, SignedClaimTicket};
+use rate_limit::RateLimitConfig;
+use event_log::Event;
+use icrc_payments::IcrcLedgerConfig;
+use icrc_payouts::{IcrcPayoutConfig, IcrcPayoutRecord};
+use claim_sync::{ClaimSyncConfig, ClaimSyncReport, EpochClaimBitmap};
+use epoch_webhook::WebhookStatus;
+
+/// Initialize task contract (admin only)
+#[ic_cdk::update]
+fn init_task_contract(tasks: Vec<TaskContractItem>) -> Result<(), String> {
+    ic_cdk::println!("CALL[init_task_contract] Input: {} tasks", tasks.len());
+    let result = task_rewards::init_task_contract(tasks);
+    ic_cdk::println!("CALL[init_task_contract] Output: {:?}", result);
     result
 }
 
+/// Add a task to the contract, optionally syncing it into existing users (admin only)
 #[ic_cdk::update]
-fn ai_sub_update_service(svr_id: String, service: ServiceType) -> Result<(), String> {
-    ic_cdk::println!("CALL[ai_sub_update_service] Input: svr_id={}", svr_id);
-    let result = ai_sub_service::update_service(&svr_id, service);
-    ic_cdk::println!("CALL[ai_sub_update_service] Output: {:?}", result);
+fn add_task_to_contract(task: TaskContractItem, auto_sync: bool) -> Result<u64, String> {
+    ic_cdk::println!("CALL[add_task_to_contract] Input: taskid={}, auto_sync={}", task.taskid, auto_sync);
+    let result = task_rewards::add_task_to_contract(task, auto_sync);
+    ic_cdk::println!("CALL[add_task_to_contract] Output: {:?}", result);
     result
 }
 
+/// Append a contract task to all existing user states (admin only, O(n) over users)
 #[ic_cdk::update]
-fn ai_sub_delete_service(svr_id: String) -> Result<(), String> {
-    ic_cdk::println!("CALL[ai_sub_delete_service] Input: svr_id={}", svr_id);
-    let result = ai_sub_service::delete_service(&svr_id);
-    ic_cdk::println!("CALL[ai_sub_delete_service] Output: {:?}", result);
+fn sync_user_tasks_for_new_task(taskid: String) -> Result<u64, String> {
+    ic_cdk::println!("CALL[sync_user_tasks_for_new_task] Input: taskid={}", taskid);
+    let result = task_rewards::sync_user_tasks_for_new_task(taskid);
+    ic_cdk::println!("CALL[sync_user_tasks_for_new_task] Output: {:?}", result);
     result
 }
 
-#[ic_cdk::query]
-fn ai_sub_list_services() -> Vec<ServiceType> {
-    ic_cdk::println!("CALL[ai_sub_list_services] Input: none");
-    let result = ai_sub_service::list_services();
-    ic_cdk::println!("CALL[ai_sub_list_services] Output: count={}", result.len());
+/// Permanently lock the task contract (admin only, requires "CONFIRM_LOCK")
+#[ic_cdk::update]
+fn lock_task_contract(confirm: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[lock_task_contract] Input: confirm={}", confirm);
+    let result = task_rewards::lock_task_contract(confirm);
+    ic_cdk::println!("CALL[lock_task_contract] Output: {:?}", result);
     result
 }
 
+/// Check whether the task contract is locked
 #[ic_cdk::query]
-fn ai_sub_list_services_paginated(offset: u64, limit: usize) -> Vec<ServiceType> {
-    ic_cdk::println!("CALL[ai_sub_list_services_paginated] Input: offset={}, limit={}", offset, limit);
-    let result = ai_sub_service::list_services_paginated(offset, limit);
-    ic_cdk::println!("CALL[ai_sub_list_services_paginated] Output: count={}", result.len());
+fn is_task_contract_locked() -> bool {
+    task_rewards::is_task_contract_locked()
+}
+
+/// Get task contract in display order
+#[ic_cdk::query]
+fn get_task_contract() -> Vec<TaskContractItem> {
+    ic_cdk::println!("CALL[get_task_contract] Input: none");
+    let result = task_rewards::get_task_contract();
+    ic_cdk::println!("CALL[get_task_contract] Output: {} tasks", result.len());
     result
 }
 
+/// Contract tasks whose activation window contains `ts` (nanoseconds), in display order
 #[ic_cdk::query]
-fn ai_sub_service_count() -> u64 {
-    ai_sub_service::service_count()
+fn list_active_tasks(ts: u64) -> Vec<TaskContractItem> {
+    task_rewards::list_active_tasks(ts)
+}
+
+/// Contract tasks of a campaign, in display order
+#[ic_cdk::query]
+fn list_tasks_by_campaign(campaign_id: String) -> Vec<TaskContractItem> {
+    task_rewards::list_tasks_by_campaign(campaign_id)
+}
+
+/// Epochs built for a campaign, in ascending epoch order
+#[ic_cdk::query]
+fn list_epochs_by_campaign(campaign_id: String) -> Vec<MerkleSnapshotMeta> {
+    task_rewards::list_epochs_by_campaign(campaign_id)
 }
 
+/// Set the reward expression tier_mul of a subscription tier (admin only)
 #[ic_cdk::update]
-fn ai_sub_create_subscription_record(record: SubscriptionRecord) -> Result<u64, String> {
-    ic_cdk::println!("CALL[ai_sub_create_subscription_record] Input: principal={}, svr_id={}", record.principal_id, record.svr_id);
-    let result = ai_sub_service::create_subscription_record(record);
-    ic_cdk::println!("CALL[ai_sub_create_subscription_record] Output: {:?}", result);
+fn set_tier_multiplier(tier: String, multiplier: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_tier_multiplier] Input: tier={}, multiplier={}", tier, multiplier);
+    let result = task_rewards::set_tier_multiplier(tier, multiplier);
+    ic_cdk::println!("CALL[set_tier_multiplier] Output: {:?}", result);
     result
 }
 
+/// Reward expression tier_mul of every subscription tier that has one
 #[ic_cdk::query]
-fn ai_sub_get_subscription_record(index: u64) -> Option<SubscriptionRecord> {
-    ai_sub_service::get_subscription_record(index)
+fn list_tier_multipliers() -> Vec<(String, u64)> {
+    task_rewards::list_tier_multipliers()
+}
+
+/// Label of the reward tier a completion of a task with `evidence` would be booked at
+#[ic_cdk::query]
+fn get_task_tier_for_evidence(taskid: String, evidence: String) -> Option<String> {
+    task_rewards::reward_tiers::get_task_tier_for_evidence(taskid, evidence)
 }
 
+/// Set a task's display order (admin only)
 #[ic_cdk::update]
-fn ai_sub_update_subscription_status(index: u64, status: SubscriptionStatus) -> Result<(), String> {
-    ai_sub_service::update_subscription_status(index, status)
+fn set_task_display_order(taskid: String, order: u32) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_task_display_order] Input: taskid={}, order={}", taskid, order);
+    let result = task_rewards::set_task_display_order(taskid, order);
+    ic_cdk::println!("CALL[set_task_display_order] Output: {:?}", result);
+    result
 }
 
+/// Stop new completions of a task without removing it (admin only)
 #[ic_cdk::update]
-fn ai_sub_resolve_subscription(index: u64) -> Result<(), String> {
-    ai_sub_service::resolve_subscription(index)
+fn pause_task(taskid: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[pause_task] Input: taskid={}", taskid);
+    let result = task_rewards::pause_task(taskid);
+    ic_cdk::println!("CALL[pause_task] Output: {:?}", result);
+    result
+}
+
+/// Accept completions of a paused task again (admin only)
+#[ic_cdk::update]
+fn resume_task(taskid: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[resume_task] Input: taskid={}", taskid);
+    let result = task_rewards::resume_task(taskid);
+    ic_cdk::println!("CALL[resume_task] Output: {:?}", result);
+    result
 }
 
+/// Taskids of paused tasks
 #[ic_cdk::query]
-fn ai_sub_list_subscriptions_by_principal(principal_id: String) -> Vec<SubscriptionRecord> {
-    ic_cdk::println!("CALL[ai_sub_list_subscriptions_by_principal] Input: principal_id={}", principal_id);
-    let result = ai_sub_service::list_subscriptions_by_principal(&principal_id);
-    ic_cdk::println!("CALL[ai_sub_list_subscriptions_by_principal] Output: count={}", result.len());
+fn list_paused_tasks() -> Vec<String> {
+    ic_cdk::println!("CALL[list_paused_tasks] Input: none");
+    let result = task_rewards::list_paused_tasks();
+    ic_cdk::println!("CALL[list_paused_tasks] Output: {} tasks", result.len());
     result
 }
 
+/// When each completed task of a wallet vests into a snapshot
 #[ic_cdk::query]
-fn ai_sub_list_subscriptions_by_principal_paginated(principal_id: String, offset: u64, limit: usize) -> Vec<SubscriptionRecord> {
-    ai_sub_service::list_subscriptions_by_principal_paginated(&principal_id, offset, limit)
+fn get_vesting_schedule(wallet: String) -> Vec<VestingEntry> {
+    ic_cdk::println!("CALL[get_vesting_schedule] Input: wallet={}", wallet);
+    let result = task_rewards::get_vesting_schedule(wallet);
+    ic_cdk::println!("CALL[get_vesting_schedule] Output: {} entries", result.len());
+    result
 }
 
+/// How each reward of a wallet was calculated and which epoch included it, oldest first
 #[ic_cdk::query]
-fn ai_sub_subscription_record_count() -> u64 {
-    ai_sub_service::subscription_record_count()
+fn get_reward_calculation_logs(wallet: String, offset: u64, limit: u64) -> (Vec<RewardCalculationLog>, u64) {
+    ic_cdk::println!("CALL[get_reward_calculation_logs] Input: wallet={}, offset={}, limit={}", wallet, offset, limit);
+    let result = task_rewards::reward_logs::get_reward_calculation_logs(wallet, offset, limit);
+    ic_cdk::println!("CALL[get_reward_calculation_logs] Output: {} of {} logs", result.0.len(), result.1);
+    result
 }
 
+/// Get user tasks in contract display order
 #[ic_cdk::query]
-fn ai_sub_is_subscribed(principal_id: String, svr_id: String) -> bool {
-    ai_sub_service::is_subscribed(&principal_id, &svr_id)
+fn get_user_task_state_ordered(wallet: String) -> UserTaskState {
+    ic_cdk::println!("CALL[get_user_task_state_ordered] Input: wallet={}", wallet);
+    let result = task_rewards::get_user_task_state_ordered(wallet);
+    ic_cdk::println!("CALL[get_user_task_state_ordered] Output: {} tasks", result.tasks.len());
+    result
 }
 
+/// Page through stored user task states by wallet cursor (admin only)
 #[ic_cdk::query]
-fn ai_sub_get_active_subscriptions(principal_id: String) -> Vec<SubscriptionRecord> {
-    ai_sub_service::get_active_subscriptions(&principal_id)
+fn list_user_task_states(cursor: Option<String>, limit: u64) -> Result<UserTaskStatePage, String> {
+    ic_cdk::println!("CALL[list_user_task_states] Input: cursor={:?}, limit={}", cursor, limit);
+    let result = task_rewards::list_user_task_states(cursor, limit);
+    ic_cdk::println!("CALL[list_user_task_states] Output: {:?}", result.as_ref().map(|p| p.states.len()));
+    result
+}
+
+/// Stored task states of up to 100 wallets, in input order; unknown wallets are None
+#[ic_cdk::query]
+fn get_user_task_states_batch(wallets: Vec<String>) -> Result<Vec<Option<UserTaskState>>, String> {
+    ic_cdk::println!("CALL[get_user_task_states_batch] Input: {} wallets", wallets.len());
+    let result = task_rewards::get_user_task_states_batch(wallets);
+    ic_cdk::println!("CALL[get_user_task_states_batch] Output: {:?}", result.as_ref().map(|states| states.iter().flatten().count()));
+    result
+}
+
+/// (wallet, total_unclaimed) of up to 100 wallets, in input order
+#[ic_cdk::query]
+fn get_unclaimed_totals_batch(wallets: Vec<String>) -> Result<Vec<(String, u64)>, String> {
+    ic_cdk::println!("CALL[get_unclaimed_totals_batch] Input: {} wallets", wallets.len());
+    let result = task_rewards::get_unclaimed_totals_batch(wallets);
+    ic_cdk::println!("CALL[get_unclaimed_totals_batch] Output: {:?}", result.as_ref().map(|totals| totals.len()));
+    result
+}
+
+/// Page through the wallets of an epoch by wallet cursor
+#[ic_cdk::query]
+fn list_epoch_wallets(epoch: u64, cursor: Option<String>, limit: u64) -> EpochWalletPage {
+    task_rewards::list_epoch_wallets(epoch, cursor, limit)
 }
 
+/// Page through the canonical entry bytes of an epoch in leaf index order
+#[ic_cdk::query]
+fn export_epoch_entries(epoch: u64, start_index: u32, limit: u64) -> Result<EpochEntriesChunk, String> {
+    task_rewards::export_epoch_entries(epoch, start_index, limit)
+}
 
+/// Get or initialize user tasks (user login)
+#[ic_cdk::update]
+fn get_or_init_user_tasks(wallet: String) -> UserTaskState {
+    ic_cdk::println!("CALL[get_or_init_user_tasks] Input: wallet={}", wallet);
+    let result = task_rewards::get_or_init_user_tasks(wallet);
+    ic_cdk::println!("CALL[get_or_init_user_tasks] Output: {} tasks", result.tasks.len());
+    result
+}
+
+/// Record payment and trigger AI subscription task completion
+#[ic_cdk::update]
+fn record_payment(
+    wallet: String,
+    amount_paid: u64,
+    tx_ref: String,
+    client_ts: Option<u64>,
+    payfor: Option<String>,
+    currency: PaymentCurrency,
+    exchange_rate: Option<u64>,
+) -> Result<(), String> {
+    ic_cdk::println!("CALL[record_payment] Input: wallet={}, amount={}, tx_ref={}, payfor={:?}, currency={:?}, exchange_rate={:?}", 
+                     wallet, amount_paid, tx_ref, payfor, currency, exchange_rate);
+    let result = task_rewards::record_payment(wallet, amount_paid, tx_ref, client_ts, payfor, currency, exchange_rate);
+    ic_cdk::println!("CALL[record_payment] Output: {:?}", result);
+    result
+}
+
+/// Record a refund; shortens the AI subscription bought with it (admin only)
+#[ic_cdk::update]
+fn record_refund(wallet: String, amount_refunded: u64, tx_ref: String, payfor: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[record_refund] Input: wallet={}, amount={}, tx_ref={}, payfor={}",
+                     wallet, amount_refunded, tx_ref, payfor);
+    let result = task_rewards::record_refund(wallet, amount_refunded, tx_ref, payfor);
+    ic_cdk::println!("CALL[record_refund] Output: {:?}", result);
+    result
+}
+
+/// Payments of a wallet, including wallets migrated into it
+#[ic_cdk::query]
+fn get_payments_by_wallet(wallet: String) -> Result<Vec<PaymentRecord>, String> {
+    ic_cdk::println!("CALL[get_payments_by_wallet] Input: wallet={}", wallet);
+    let result = task_rewards::get_payments_by_wallet(wallet);
+    ic_cdk::println!("CALL[get_payments_by_wallet] Output: {:?}", result.as_ref().map(|p| p.len()));
+    result
+}
+
+/// Page of payments after a payment index, optionally of one wallet; returns the next cursor
+#[ic_cdk::query]
+fn list_payments_cursor(wallet: Option<String>, after_index: Option<u64>, limit: u64) -> (Vec<PaymentRecord>, Option<u64>) {
+    ic_cdk::println!("CALL[list_payments_cursor] Input: wallet={:?}, after_index={:?}, limit={}", wallet, after_index, limit);
+    let result = task_rewards::list_payments_cursor(wallet, after_index, limit);
+    ic_cdk::println!("CALL[list_payments_cursor] Output: {} payments, next={:?}", result.0.len(), result.1);
+    result
+}
+
+/// Payments of a wallet in [from_ts, to_ts), oldest first, with their payment log positions
+#[ic_cdk::query]
+fn get_payments_by_wallet_range(wallet: String, from_ts: u64, to_ts: u64, offset: u64, limit: u64) -> Result<Vec<IndexedPayment>, String> {
+    ic_cdk::println!("CALL[get_payments_by_wallet_range] Input: wallet={}, from_ts={}, to_ts={}, offset={}, limit={}", wallet, from_ts, to_ts, offset, limit);
+    let result = task_rewards::get_payments_by_wallet_range(wallet, from_ts, to_ts, offset, limit);
+    ic_cdk::println!("CALL[get_payments_by_wallet_range] Output: {:?}", result.as_ref().map(|p| p.len()));
+    result
+}
+
+/// Index a batch of historical payments in the payment timeline; returns the next cursor (admin only)
+#[ic_cdk::update]
+fn backfill_payment_time_index(after_index: Option<u64>, limit: u64) -> Result<Option<u64>, String> {
+    ic_cdk::println!("CALL[backfill_payment_time_index] Input: after_index={:?}, limit={}", after_index, limit);
+    let result = task_rewards::backfill_payment_time_index(after_index, limit);
+    ic_cdk::println!("CALL[backfill_payment_time_index] Output: {:?}", result);
+    result
+}
+
+/// Move payments recorded before `before_ts` to the archive queue; returns how many moved (admin only)
+#[ic_cdk::update]
+fn archive_payments(before_ts: u64, max_records: u64) -> Result<u64, String> {
+    ic_cdk::println!("CALL[archive_payments] Input: before_ts={}, max_records={}", before_ts, max_records);
+    let result = task_rewards::payment_archive::archive_payments(before_ts, max_records);
+    ic_cdk::println!("CALL[archive_payments] Output: {:?}", result);
+    result
+}
+
+/// Acknowledge archived payments up to `cursor` and pull the next batch (admin only)
+#[ic_cdk::update]
+fn get_archived_batch(cursor: Option<u64>) -> Result<ArchivedPaymentBatch, String> {
+    ic_cdk::println!("CALL[get_archived_batch] Input: cursor={:?}", cursor);
+    let result = task_rewards::payment_archive::get_archived_batch(cursor);
+    ic_cdk::println!("CALL[get_archived_batch] Output: {:?}", result.as_ref().map(|b| (b.payments.len(), b.next_cursor)));
+    result
+}
+
+/// Payments made in a currency, oldest first
+#[ic_cdk::query]
+fn get_payments_by_currency(currency: PaymentCurrency) -> Vec<PaymentRecord> {
+    ic_cdk::println!("CALL[get_payments_by_currency] Input: currency={:?}", currency);
+    let result = task_rewards::get_payments_by_currency(currency);
+    ic_cdk::println!("CALL[get_payments_by_currency] Output: {} payments", result.len());
+    result
+}
+
+/// Receipt with a content hash for the payment at `payment_index`
+#[ic_cdk::query]
+fn generate_payment_receipt(payment_index: u64) -> Result<PaymentReceipt, String> {
+    ic_cdk::println!("CALL[generate_payment_receipt] Input: payment_index={}", payment_index);
+    let result = task_rewards::generate_payment_receipt(payment_index);
+    ic_cdk::println!("CALL[generate_payment_receipt] Output: {:?}", result.as_ref().map(|r| hex::encode(&r.receipt_hash)));
+    result
+}
+
+/// Check that a payment receipt's hash matches its fields
+#[ic_cdk::query]
+fn verify_payment_receipt(receipt: PaymentReceipt) -> bool {
+    task_rewards::verify_payment_receipt(receipt)
+}
+
+/// Move a user's reward state to a new wallet (admin only)
+#[ic_cdk::update]
+fn migrate_wallet(old_wallet: String, new_wallet: String, reason: String) -> Result<WalletMigrationReport, String> {
+    ic_cdk::println!("CALL[migrate_wallet] Input: old_wallet={}, new_wallet={}, reason={}", old_wallet, new_wallet, reason);
+    let result = task_rewards::migrate_wallet(old_wallet, new_wallet, reason);
+    ic_cdk::println!("CALL[migrate_wallet] Output: {:?}", result);
+    result
+}
+
+/// List wallet migration audit records
+#[ic_cdk::query]
+fn list_wallet_migrations() -> Vec<WalletMigration> {
+    task_rewards::list_wallet_migrations()
+}
+
+/// Revenue and usage per payfor category, highest revenue first
+#[ic_cdk::query]
+fn get_payfor_stats() -> Vec<PayforStats> {
+    ic_cdk::println!("CALL[get_payfor_stats] Input: none");
+    let result = task_rewards::get_payfor_stats();
+    ic_cdk::println!("CALL[get_payfor_stats] Output: {} categories", result.len());
+    result
+}
+
+/// Revenue and usage of a single payfor category
+#[ic_cdk::query]
+fn get_payfor_stat(payfor: String) -> Option<PayforStats> {
+    task_rewards::get_payfor_stat(payfor)
+}
+
+/// Set the per-caller/per-wallet call limit of a method (admin only)
+#[ic_cdk::update]
+fn set_rate_limit(method: String, max_per_window: u32, window_secs: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_rate_limit] Input: method={}, max_per_window={}, window_secs={}", method, max_per_window, window_secs);
+    let result = rate_limit::set_rate_limit(method, max_per_window, window_secs);
+    ic_cdk::println!("CALL[set_rate_limit] Output: {:?}", result);
+    result
+}
+
+/// List configured rate limits
+#[ic_cdk::query]
+fn get_rate_limits() -> Vec<(String, RateLimitConfig)> {
+    rate_limit::get_rate_limits()
+}
+
+/// Check the task contract for suspicious or inconsistent entries (admin only)
+#[ic_cdk::query]
+fn check_task_contract_health() -> Result<TaskContractHealthReport, String> {
+    ic_cdk::println!("CALL[check_task_contract_health] Input: none");
+    let result = task_rewards::check_task_contract_health();
+    ic_cdk::println!("CALL[check_task_contract_health] Output: {:?}", result);
+    result
+}
+
+/// Set minimum payment amount for a payfor category (admin only)
+#[ic_cdk::update]
+fn set_payment_floor(payfor: Option<String>, min_amount: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_payment_floor] Input: payfor={:?}, min_amount={}", payfor, min_amount);
+    let result = task_rewards::set_payment_floor(payfor, min_amount);
+    ic_cdk::println!("CALL[set_payment_floor] Output: {:?}", result);
+    result
+}
+
+/// Get effective payment floor for a payfor category
+#[ic_cdk::query]
+fn get_payment_floor(payfor: Option<String>) -> u64 {
+    ic_cdk::println!("CALL[get_payment_floor] Input: payfor={:?}", payfor);
+    let result = task_rewards::get_payment_floor(payfor);
+    ic_cdk::println!("CALL[get_payment_floor] Output: {}", result);
+    result
+}
+
+/// List configured payment floors
+#[ic_cdk::query]
+fn list_payment_floors() -> Vec<(Option<String>, u64)> {
+    ic_cdk::println!("CALL[list_payment_floors] Input: none");
+    let result = task_rewards::list_payment_floors();
+    ic_cdk::println!("CALL[list_payment_floors] Output: {} floors", result.len());
+    result
+}
+
+/// Allow a principal to record payments (admin only)
+#[ic_cdk::update]
+fn add_payment_operator(operator: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[add_payment_operator] Input: operator={}", operator);
+    let result = task_rewards::add_payment_operator(operator);
+    ic_cdk::println!("CALL[add_payment_operator] Output: {:?}", result);
+    result
+}
+
+/// Revoke a payment operator (admin only)
+#[ic_cdk::update]
+fn remove_payment_operator(operator: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[remove_payment_operator] Input: operator={}", operator);
+    let result = task_rewards::remove_payment_operator(operator);
+    ic_cdk::println!("CALL[remove_payment_operator] Output: {:?}", result);
+    result
+}
+
+/// List principals allowed to record payments (admin only)
+#[ic_cdk::query]
+fn list_payment_operators() -> Result<Vec<Principal>, String> {
+    ic_cdk::println!("CALL[list_payment_operators] Input: none");
+    let result = task_rewards::list_payment_operators();
+    ic_cdk::println!("CALL[list_payment_operators] Output: {:?}", result);
+    result
+}
+
+/// Accept payments on an ICRC-1 ledger sent to the given account (admin only)
+#[ic_cdk::update]
+async fn set_icrc_ledger(ledger: Principal, receiving_account: Account) -> Result<IcrcLedgerConfig, String> {
+    ic_cdk::println!("CALL[set_icrc_ledger] Input: ledger={}, receiving_account={}", ledger, receiving_account);
+    let result = icrc_payments::set_icrc_ledger(ledger, receiving_account).await;
+    ic_cdk::println!("CALL[set_icrc_ledger] Output: {:?}", result);
+    result
+}
+
+/// Stop accepting payments on an ICRC-1 ledger (admin only)
+#[ic_cdk::update]
+fn remove_icrc_ledger(ledger: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[remove_icrc_ledger] Input: ledger={}", ledger);
+    let result = icrc_payments::remove_icrc_ledger(ledger);
+    ic_cdk::println!("CALL[remove_icrc_ledger] Output: {:?}", result);
+    result
+}
+
+/// List ICRC-1 ledgers accepted for payments
+#[ic_cdk::query]
+fn list_icrc_ledgers() -> Vec<(Principal, IcrcLedgerConfig)> {
+    icrc_payments::list_icrc_ledgers()
+}
+
+/// Record a payment after verifying the transfer block on an ICRC-1 ledger
+#[ic_cdk::update]
+async fn record_icrc_payment(
+    wallet: String,
+    ledger_canister: Principal,
+    block_index: u64,
+    expected_amount: u64,
+    payfor: Option<String>,
+) -> Result<(), String> {
+    ic_cdk::println!("CALL[record_icrc_payment] Input: wallet={}, ledger={}, block_index={}, expected_amount={}, payfor={:?}",
+                     wallet, ledger_canister, block_index, expected_amount, payfor);
+    let result = icrc_payments::record_icrc_payment(wallet, ledger_canister, block_index, expected_amount, payfor).await;
+    ic_cdk::println!("CALL[record_icrc_payment] Output: {:?}", result);
+    result
+}
+
+/// Get the ledger and cap used for ICRC-1 reward payouts
+#[ic_cdk::query]
+fn get_icrc_payout_config() -> IcrcPayoutConfig {
+    icrc_payouts::get_icrc_payout_config()
+}
+
+/// Set the ledger and cap used for ICRC-1 reward payouts (admin only)
+#[ic_cdk::update]
+fn set_icrc_payout_config(config: IcrcPayoutConfig) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_icrc_payout_config] Input: {:?}", config);
+    let result = icrc_payouts::set_icrc_payout_config(config);
+    ic_cdk::println!("CALL[set_icrc_payout_config] Output: {:?}", result);
+    result
+}
+
+/// Pay a bound wallet's unsnapshotted rewards to an ICRC-1 account instead of a Solana claim
+#[ic_cdk::update]
+async fn claim_to_icp_account(wallet: String, account: Account) -> Result<candid::Nat, String> {
+    ic_cdk::println!("CALL[claim_to_icp_account] Input: wallet={}, account={}", wallet, account);
+    let result = icrc_payouts::claim_to_icp_account(wallet, account).await;
+    ic_cdk::println!("CALL[claim_to_icp_account] Output: {:?}", result);
+    result
+}
+
+/// ICRC-1 payouts made to a wallet
+#[ic_cdk::query]
+fn list_icrc_payouts(wallet: String) -> Vec<IcrcPayoutRecord> {
+    icrc_payouts::list_icrc_payouts(wallet)
+}
+
+/// Get the Solana RPC and bitmap layout used to sync on-chain claims
+#[ic_cdk::query]
+fn get_claim_sync_config() -> ClaimSyncConfig {
+    claim_sync::get_claim_sync_config()
+}
+
+/// Set the Solana RPC, bitmap layout and sync interval for on-chain claims (admin only)
+#[ic_cdk::update]
+fn set_claim_sync_config(config: ClaimSyncConfig) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_claim_sync_config] Input: {:?}", config);
+    let result = claim_sync::set_claim_sync_config(config);
+    ic_cdk::println!("CALL[set_claim_sync_config] Output: {:?}", result);
+    result
+}
+
+/// Register the distributor claimed-bitmap account of an epoch (admin only)
+#[ic_cdk::update]
+fn set_epoch_claim_account(epoch: u64, account: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_epoch_claim_account] Input: epoch={}, account={}", epoch, account);
+    let result = claim_sync::set_epoch_claim_account(epoch, account);
+    ic_cdk::println!("CALL[set_epoch_claim_account] Output: {:?}", result);
+    result
+}
+
+/// Read an epoch's claimed bitmap from Solana and mark claims not yet recorded here (admin only)
+#[ic_cdk::update]
+async fn sync_epoch_claims(epoch: u64) -> Result<ClaimSyncReport, String> {
+    ic_cdk::println!("CALL[sync_epoch_claims] Input: epoch={}", epoch);
+    let result = claim_sync::sync_epoch_claims(epoch).await;
+    ic_cdk::println!("CALL[sync_epoch_claims] Output: {:?}", result);
+    result
+}
+
+/// Local claimed bitmap of an epoch
+#[ic_cdk::query]
+fn get_epoch_claim_bitmap(epoch: u64) -> Option<EpochClaimBitmap> {
+    claim_sync::get_epoch_claim_bitmap(epoch)
+}
+
+/// Whether leaf `index` of an epoch is known to be claimed
+#[ic_cdk::query]
+fn is_index_claimed(epoch: u64, index: u32) -> bool {
+    claim_sync::is_index_claimed(epoch, index)
+}
+
+/// Set the HTTPS URL notified when an epoch snapshot is built; empty turns it off (admin only)
+#[ic_cdk::update]
+fn set_epoch_webhook_url(url: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_epoch_webhook_url] Input: {}", url);
+    let result = epoch_webhook::set_epoch_webhook_url(url);
+    ic_cdk::println!("CALL[set_epoch_webhook_url] Output: {:?}", result);
+    result
+}
+
+/// HTTP status and response body of the last epoch webhook call
+#[ic_cdk::query]
+fn get_last_webhook_status() -> WebhookStatus {
+    epoch_webhook::get_last_webhook_status()
+}
+
+/// Complete a task (register device, voice clone, etc.), stamped with canister time.
+/// Older clients that still pass a trailing timestamp are accepted; it is ignored.
+/// Gated tasks first query the gate's canister for the wallet's bound principal.
+#[ic_cdk::update]
+async fn complete_task(
+    wallet: String,
+    taskid: String,
+    evidence: Option<String>,
+) -> Result<(), String> {
+    ic_cdk::println!("CALL[complete_task] Input: wallet={}, taskid={}, evidence={:?}", 
+                     wallet, taskid, evidence);
+    let result = task_rewards::complete_task(wallet, taskid, evidence).await;
+    ic_cdk::println!("CALL[complete_task] Output: {:?}", result);
+    result
+}
+
+/// Complete tasks for up to 200 wallets at once, audited as one batch (admin only)
+#[ic_cdk::update]
+fn admin_complete_tasks_for_wallets(completions: Vec<AdminTaskCompletion>) -> Result<AdminBatchResult, String> {
+    ic_cdk::println!("CALL[admin_complete_tasks_for_wallets] Input: {} completions", completions.len());
+    let result = task_rewards::admin_complete_tasks_for_wallets(completions);
+    ic_cdk::println!("CALL[admin_complete_tasks_for_wallets] Output: {:?}", result.as_ref().map(|r| (r.succeeded, r.failed)));
+    result
+}
+
+/// Register a referee wallet under its referrer (controller or the referee's bound principal)
+#[ic_cdk::update]
+fn register_referral(referrer_wallet: String, referee_wallet: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[register_referral] Input: referrer={}, referee={}", referrer_wallet, referee_wallet);
+    let result = task_rewards::referrals::register_referral(referrer_wallet, referee_wallet);
+    ic_cdk::println!("CALL[register_referral] Output: {:?}", result);
+    result
+}
+
+/// How many wallets a referrer referred and how many converted
+#[ic_cdk::query]
+fn get_referral_stats(wallet: String) -> Result<ReferralStats, String> {
+    task_rewards::referrals::get_referral_stats(wallet)
+}
+
+/// Unread task status notifications of a wallet, oldest first
+#[ic_cdk::query]
+fn get_pending_notifications(wallet: String) -> Vec<TaskNotification> {
+    task_rewards::notifications::get_pending_notifications(wallet)
+}
+
+/// Mark task notifications as read (controller or the wallet's bound principal); returns the count marked
+#[ic_cdk::update]
+fn mark_notifications_read(wallet: String, notification_ids: Vec<u64>) -> Result<u64, String> {
+    ic_cdk::println!("CALL[mark_notifications_read] Input: wallet={}, ids={:?}", wallet, notification_ids);
+    let result = task_rewards::notifications::mark_notifications_read(wallet, notification_ids);
+    ic_cdk::println!("CALL[mark_notifications_read] Output: {:?}", result);
+    result
+}
+
+/// Contest a prepared, issued or claimed task reward (controller or the wallet's bound principal)
+#[ic_cdk::update]
+fn dispute_claim(wallet: String, taskid: String, reason: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[dispute_claim] Input: wallet={}, taskid={}, reason={}", wallet, taskid, reason);
+    let result = task_rewards::disputes::dispute_claim(wallet, taskid, reason);
+    ic_cdk::println!("CALL[dispute_claim] Output: {:?}", result);
+    result
+}
+
+/// Resolve a task dispute: approved clears it, otherwise the task is reset to NotStarted (admin only)
+#[ic_cdk::update]
+fn resolve_dispute(wallet: String, taskid: String, approved: bool) -> Result<(), String> {
+    ic_cdk::println!("CALL[resolve_dispute] Input: wallet={}, taskid={}, approved={}", wallet, taskid, approved);
+    let result = task_rewards::disputes::resolve_dispute(wallet, taskid, approved);
+    ic_cdk::println!("CALL[resolve_dispute] Output: {:?}", result);
+    result
+}
+
+/// Roll a Completed task back to InProgress before it is snapshotted, with a logged reason (admin only)
+#[ic_cdk::update]
+fn admin_rollback_task_to_in_progress(wallet: String, taskid: String, reason: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[admin_rollback_task_to_in_progress] Input: wallet={}, taskid={}, reason={}", wallet, taskid, reason);
+    let result = task_rewards::rollbacks::admin_rollback_task_to_in_progress(wallet, taskid, reason);
+    ic_cdk::println!("CALL[admin_rollback_task_to_in_progress] Output: {:?}", result);
+    result
+}
+
+/// Reset a Completed or InProgress task to NotStarted before it is snapshotted, with a logged reason (admin only)
+#[ic_cdk::update]
+fn admin_rollback_task_to_not_started(wallet: String, taskid: String, reason: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[admin_rollback_task_to_not_started] Input: wallet={}, taskid={}, reason={}", wallet, taskid, reason);
+    let result = task_rewards::rollbacks::admin_rollback_task_to_not_started(wallet, taskid, reason);
+    ic_cdk::println!("CALL[admin_rollback_task_to_not_started] Output: {:?}", result);
+    result
+}
+
+/// Tasks with an open dispute as (wallet, task) pairs (admin only)
+#[ic_cdk::query]
+fn list_disputed_tasks() -> Result<Vec<(String, UserTaskDetail)>, String> {
+    ic_cdk::println!("CALL[list_disputed_tasks] Input: none");
+    let result = task_rewards::disputes::list_disputed_tasks();
+    ic_cdk::println!("CALL[list_disputed_tasks] Output: {:?}", result.as_ref().map(|tasks| tasks.len()));
+    result
+}
+
+/// Exclude a wallet from epoch snapshots and claim tickets, keeping its tasks (admin only)
+#[ic_cdk::update]
+fn flag_wallet(wallet: String, reason: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[flag_wallet] Input: wallet={}, reason={}", wallet, reason);
+    let result = task_rewards::wallet_flags::flag_wallet(wallet, reason);
+    ic_cdk::println!("CALL[flag_wallet] Output: {:?}", result);
+    result
+}
+
+/// Return a flagged wallet to the normal reward flow (admin only)
+#[ic_cdk::update]
+fn unflag_wallet(wallet: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[unflag_wallet] Input: wallet={}", wallet);
+    let result = task_rewards::wallet_flags::unflag_wallet(wallet);
+    ic_cdk::println!("CALL[unflag_wallet] Output: {:?}", result);
+    result
+}
+
+/// Flagged wallets in wallet order (admin only)
+#[ic_cdk::query]
+fn list_flagged_wallets(offset: u64, limit: u64) -> Result<Vec<WalletFlag>, String> {
+    ic_cdk::println!("CALL[list_flagged_wallets] Input: offset={}, limit={}", offset, limit);
+    let result = task_rewards::wallet_flags::list_flagged_wallets(offset, limit);
+    ic_cdk::println!("CALL[list_flagged_wallets] Output: {:?}", result.as_ref().map(|flags| flags.len()));
+    result
+}
+
+/// Block a wallet's task completions, payments and claim tickets (admin only)
+#[ic_cdk::update]
+fn suspend_wallet(wallet: String, reason: String, duration_ns: Option<u64>) -> Result<(), String> {
+    ic_cdk::println!("CALL[suspend_wallet] Input: wallet={}, reason={}, duration_ns={:?}", wallet, reason, duration_ns);
+    let result = task_rewards::suspensions::suspend_wallet(wallet, reason, duration_ns);
+    ic_cdk::println!("CALL[suspend_wallet] Output: {:?}", result);
+    result
+}
+
+/// Lift a wallet's suspension (admin only)
+#[ic_cdk::update]
+fn unsuspend_wallet(wallet: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[unsuspend_wallet] Input: wallet={}", wallet);
+    let result = task_rewards::suspensions::unsuspend_wallet(wallet);
+    ic_cdk::println!("CALL[unsuspend_wallet] Output: {:?}", result);
+    result
+}
+
+/// Suspensions still in effect, in wallet order
+#[ic_cdk::query]
+fn list_suspended_wallets() -> Vec<WalletSuspension> {
+    ic_cdk::println!("CALL[list_suspended_wallets] Input: none");
+    let result = task_rewards::suspensions::list_suspended_wallets();
+    ic_cdk::println!("CALL[list_suspended_wallets] Output: {} wallets", result.len());
+    result
+}
+
+/// Whether a wallet is suspended right now
+#[ic_cdk::query]
+fn is_wallet_suspended(wallet: String) -> bool {
+    ic_cdk::println!("CALL[is_wallet_suspended] Input: wallet={}", wallet);
+    let result = task_rewards::suspensions::is_wallet_suspended(wallet);
+    ic_cdk::println!("CALL[is_wallet_suspended] Output: {}", result);
+    result
+}
+
+/// When a wallet first appeared and last interacted
+#[ic_cdk::query]
+fn get_wallet_meta(wallet: String) -> Option<WalletMeta> {
+    task_rewards::wallet_meta::get_wallet_meta(wallet)
+}
+
+/// Most recently first-seen wallets, newest first (admin only)
+#[ic_cdk::query]
+fn list_newest_wallets(limit: u32) -> Result<Vec<(String, WalletMeta)>, String> {
+    ic_cdk::println!("CALL[list_newest_wallets] Input: limit={}", limit);
+    let result = task_rewards::wallet_meta::list_newest_wallets(limit);
+    ic_cdk::println!("CALL[list_newest_wallets] Output: {:?}", result.as_ref().map(|wallets| wallets.len()));
+    result
+}
+
+/// Preview what build_epoch_snapshot would commit without writing anything (admin only)
+#[ic_cdk::query]
+fn preview_epoch_snapshot(
+    epoch: u64,
+    options: BuildEpochOptions,
+    target: ChainTarget,
+    campaign_id: Option<String>,
+) -> Result<EpochPreview, String> {
+    ic_cdk::println!("CALL[preview_epoch_snapshot] Input: epoch={}, options={:?}, target={:?}, campaign_id={:?}", epoch, options, target, campaign_id);
+    let result = task_rewards::preview_epoch_snapshot(epoch, options, target, campaign_id);
+    match &result {
+        Ok(preview) => ic_cdk::println!("CALL[preview_epoch_snapshot] Output: {} wallets, total {}",
+                                       preview.wallets, preview.total_amount),
+        Err(e) => ic_cdk::println!("CALL[preview_epoch_snapshot] Output: Error - {}", e),
+    }
+    result
+}
+
+/// Build epoch snapshot - generates Merkle tree over the wallets of `target` (admin/scheduled)
+#[ic_cdk::update]
+fn build_epoch_snapshot(
+    epoch: u64,
+    options: BuildEpochOptions,
+    target: ChainTarget,
+    description: String,
+    token_mint: String,
+    vesting: Option<VestingPolicy>,
+    campaign_id: Option<String>,
+) -> Result<MerkleSnapshotMeta, String> {
+    ic_cdk::println!("CALL[build_epoch_snapshot] Input: epoch={}, options={:?}, target={:?}, description={}, token_mint={}, vesting={:?}, campaign_id={:?}",
+                     epoch, options, target, description, token_mint, vesting, campaign_id);
+    let result = task_rewards::build_epoch_snapshot(epoch, options, target, description, token_mint, vesting, campaign_id);
+    match &result {
+        Ok(meta) => ic_cdk::println!("CALL[build_epoch_snapshot] Output: Success - {} leaves, root={:?}", 
+                                    meta.leaves_count, meta.root),
+        Err(e) => ic_cdk::println!("CALL[build_epoch_snapshot] Output: Error - {}", e),
+    }
+    result
+}
+
+/// Run build_epoch_snapshot and undo all of its writes, returning the leaves it built (admin only)
+#[ic_cdk::update]
+fn build_epoch_snapshot_dry_run(
+    epoch: u64,
+    options: BuildEpochOptions,
+    target: ChainTarget,
+    description: String,
+    token_mint: String,
+    vesting: Option<VestingPolicy>,
+    campaign_id: Option<String>,
+) -> Result<EpochPreview, String> {
+    ic_cdk::println!("CALL[build_epoch_snapshot_dry_run] Input: epoch={}, options={:?}, target={:?}, vesting={:?}, campaign_id={:?}",
+                     epoch, options, target, vesting, campaign_id);
+    let result = task_rewards::build_epoch_snapshot_dry_run(epoch, options, target, description, token_mint, vesting, campaign_id);
+    match &result {
+        Ok(preview) => ic_cdk::println!("CALL[build_epoch_snapshot_dry_run] Output: {} leaves, total {}",
+                                       preview.wallets, preview.total_amount),
+        Err(e) => ic_cdk::println!("CALL[build_epoch_snapshot_dry_run] Output: Error - {}", e),
+    }
+    result
+}
+
+/// Get claim ticket (deprecated nat64 index; use get_claim_ticket_v2)
+#[ic_cdk::update]
+fn get_claim_ticket(wallet: String) -> Result<LegacyClaimTicket, String> {
+    ic_cdk::println!("CALL[get_claim_ticket] Input: wallet={}", wallet);
+    let result = task_rewards::get_claim_ticket(wallet).map(LegacyClaimTicket::from);
+    match &result {
+        Ok(ticket) => ic_cdk::println!("CALL[get_claim_ticket] Output: Success - epoch={}, index={}, amount={}", 
+                                      ticket.epoch, ticket.index, ticket.amount),
+        Err(e) => ic_cdk::println!("CALL[get_claim_ticket] Output: Error - {}", e),
+    }
+    result
+}
+
+/// Get claim ticket for frontend to submit on-chain (u32 leaf index, optionally signed).
+/// Only the wallet's bound principal or an admin may issue it.
+#[ic_cdk::update]
+async fn get_claim_ticket_v2(wallet: String) -> Result<ClaimTicket, String> {
+    ic_cdk::println!("CALL[get_claim_ticket_v2] Input: wallet={}", wallet);
+    let result = match task_rewards::get_claim_ticket(wallet) {
+        Ok(mut ticket) => claim_signing::sign_claim_ticket(&mut ticket).await.map(|_| ticket),
+        Err(e) => Err(e),
+    };
+    match &result {
+        Ok(ticket) => ic_cdk::println!("CALL[get_claim_ticket_v2] Output: Success - epoch={}, index={}, amount={}", 
+                                      ticket.epoch, ticket.index, ticket.amount),
+        Err(e) => ic_cdk::println!("CALL[get_claim_ticket_v2] Output: Error - {}", e),
+    }
+    result
+}
+
+/// Get claim ticket with a canister signature over the whole ticket, verifiable with
+/// get_claim_signing_pubkey
+#[ic_cdk::update]
+async fn get_signed_claim_ticket(wallet: String) -> Result<SignedClaimTicket, String> {
+    ic_cdk::println!("CALL[get_signed_claim_ticket] Input: wallet={}", wallet);
+    let result = match task_rewards::get_claim_ticket(wallet) {
+        Ok(mut ticket) => match claim_signing::sign_claim_ticket(&mut ticket).await {
+            Ok(()) => claim_signing::sign_whole_ticket(ticket).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    match &result {
+        Ok(signed) => ic_cdk::println!("CALL[get_signed_claim_ticket] Output: Success - epoch={}, index={}, amount={}",
+                                      signed.ticket.epoch, signed.ticket.index, signed.ticket.amount),
+        Err(e) => ic_cdk::println!("CALL[get_signed_claim_ticket] Output: Error - {}", e),
+    }
+    result
+}
+
+/// Re-encode a claim ticket's proof, root and signature as strings
+#[ic_cdk::query]
+fn encode_claim_ticket(ticket: ClaimTicket, encoding: ProofEncoding) -> ClaimTicketEncoded {
+    task_rewards::ticket_encoding::encode_claim_ticket(ticket, encoding)
+}
+
+/// Decode a claim ticket produced by encode_claim_ticket
+#[ic_cdk::query]
+fn decode_claim_ticket(encoded: ClaimTicketEncoded, encoding: ProofEncoding) -> Result<ClaimTicket, String> {
+    task_rewards::ticket_encoding::decode_claim_ticket(encoded, encoding)
+}
+
+/// Per-epoch allocation, issuance and claim state of a wallet, with lifetime totals
+#[ic_cdk::query]
+fn get_wallet_claim_summary(wallet: String) -> Result<WalletClaimSummary, String> {
+    ic_cdk::println!("CALL[get_wallet_claim_summary] Input: wallet={}", wallet);
+    let result = task_rewards::get_wallet_claim_summary(wallet);
+    ic_cdk::println!("CALL[get_wallet_claim_summary] Output: {:?}", result.as_ref().map(|s| s.epochs.len()));
+    result
+}
+
+/// Check that a claim ticket's proof links its leaf to its root
+#[ic_cdk::query]
+fn verify_claim_ticket(ticket: ClaimTicket) -> Result<bool, String> {
+    task_rewards::verify_claim_ticket(&ticket)
+}
+
+/// Get the SEC1 public key that verifies claim ticket signatures
+#[ic_cdk::update]
+async fn get_claim_signing_pubkey() -> Result<Vec<u8>, String> {
+    ic_cdk::println!("CALL[get_claim_signing_pubkey] Input: none");
+    let result = claim_signing::get_claim_signing_pubkey().await;
+    ic_cdk::println!("CALL[get_claim_signing_pubkey] Output: ok={}", result.is_ok());
+    result
+}
+
+/// Get claim signing configuration
+#[ic_cdk::query]
+fn get_claim_signing_config() -> ClaimSigningConfig {
+    claim_signing::get_claim_signing_config()
+}
+
+/// Set claim signing configuration: enable flag, key name, derivation path (admin only)
+#[ic_cdk::update]
+fn set_claim_signing_config(config: ClaimSigningConfig) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_claim_signing_config] Input: enabled={}, key_name={}", config.enabled, config.key_name);
+    let result = claim_signing::set_claim_signing_config(config);
+    ic_cdk::println!("CALL[set_claim_signing_config] Output: {:?}", result);
+    result
+}
+
+/// Get ticket issuance record for a wallet in an epoch
+#[ic_cdk::query]
+fn get_ticket_issuance(wallet: String, epoch: u64) -> Option<TicketIssuance> {
+    ic_cdk::println!("CALL[get_ticket_issuance] Input: wallet={}, epoch={}", wallet, epoch);
+    let result = task_rewards::get_ticket_issuance(wallet, epoch);
+    ic_cdk::println!("CALL[get_ticket_issuance] Output: exists={}", result.is_some());
+    result
+}
+
+/// Mark claim result after on-chain transaction (bound principal or admin)
+#[ic_cdk::update]
+fn mark_claim_result(
+    wallet: String,
+    epoch: u64,
+    status: ClaimResultStatus,
+    tx_sig: Option<String>,
+) -> Result<(), String> {
+    ic_cdk::println!("CALL[mark_claim_result] Input: wallet={}, epoch={}, status={:?}, tx={:?}", 
+                     wallet, epoch, status, tx_sig);
+    let result = task_rewards::mark_claim_result(wallet, epoch, status, tx_sig);
+    ic_cdk::println!("CALL[mark_claim_result] Output: {:?}", result);
+    result
+}
+
+/// Append a wallet missed by an unlocked epoch's snapshot and return its ticket (admin only)
+#[ic_cdk::update]
+fn add_wallet_to_epoch(epoch: u64, wallet: String) -> Result<ClaimTicket, String> {
+    ic_cdk::println!("CALL[add_wallet_to_epoch] Input: epoch={}, wallet={}", epoch, wallet);
+    let result = task_rewards::add_wallet_to_epoch(epoch, wallet);
+    ic_cdk::println!("CALL[add_wallet_to_epoch] Output: {:?}", result);
+    result
+}
+
+/// Lock a reviewed epoch so claim tickets can be issued (admin only)
+#[ic_cdk::update]
+fn lock_epoch(epoch: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[lock_epoch] Input: epoch={}", epoch);
+    let result = task_rewards::lock_epoch(epoch);
+    ic_cdk::println!("CALL[lock_epoch] Output: {:?}", result);
+    result
+}
+
+/// Unlock an epoch with no claims yet (admin only)
+#[ic_cdk::update]
+fn unlock_epoch(epoch: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[unlock_epoch] Input: epoch={}", epoch);
+    let result = task_rewards::unlock_epoch(epoch);
+    ic_cdk::println!("CALL[unlock_epoch] Output: {:?}", result);
+    result
+}
+
+/// Prune Merkle hash data of a fully claimed or expired epoch (admin only)
+#[ic_cdk::update]
+fn prune_epoch_layers(epoch: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[prune_epoch_layers] Input: epoch={}", epoch);
+    let result = task_rewards::prune_epoch_layers(epoch);
+    ic_cdk::println!("CALL[prune_epoch_layers] Output: {:?}", result);
+    result
+}
+
+/// Maximum number of epoch snapshots per 24h
+#[ic_cdk::query]
+fn get_epoch_rate_limit() -> u32 {
+    task_rewards::get_epoch_rate_limit()
+}
+
+/// Set the maximum number of epoch snapshots per 24h (admin only)
+#[ic_cdk::update]
+fn set_epoch_rate_limit(limit: u32) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_epoch_rate_limit] Input: limit={}", limit);
+    let result = task_rewards::set_epoch_rate_limit(limit);
+    ic_cdk::println!("CALL[set_epoch_rate_limit] Output: {:?}", result);
+    result
+}
+
+/// Length of an epoch in nanoseconds
+#[ic_cdk::query]
+fn get_epoch_duration_ns() -> u64 {
+    task_rewards::get_epoch_duration_ns()
+}
+
+/// Set the length of an epoch in nanoseconds (admin only)
+#[ic_cdk::update]
+fn set_epoch_duration_ns(duration_ns: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_epoch_duration_ns] Input: duration_ns={}", duration_ns);
+    let result = task_rewards::set_epoch_duration_ns(duration_ns);
+    ic_cdk::println!("CALL[set_epoch_duration_ns] Output: {:?}", result);
+    result
+}
+
+/// Time epoch 0 started in nanoseconds, if set
+#[ic_cdk::query]
+fn get_epoch_zero_ts() -> Option<u64> {
+    task_rewards::get_epoch_zero_ts()
+}
+
+/// Set the time epoch 0 started in nanoseconds, enabling the epoch clock check (admin only)
+#[ic_cdk::update]
+fn set_epoch_zero_ts(ts: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_epoch_zero_ts] Input: ts={}", ts);
+    let result = task_rewards::set_epoch_zero_ts(ts);
+    ic_cdk::println!("CALL[set_epoch_zero_ts] Output: {:?}", result);
+    result
+}
+
+/// Epoch whose time window contains the current time
+#[ic_cdk::query]
+fn epoch_for_current_time() -> u64 {
+    task_rewards::epoch_for_current_time()
+}
+
+/// Top wallets by total claimed reward (limit capped at 100)
+#[ic_cdk::query]
+fn get_reward_leaderboard(limit: u32) -> Vec<LeaderboardEntry> {
+    ic_cdk::println!("CALL[get_reward_leaderboard] Input: limit={}", limit);
+    let result = task_rewards::get_reward_leaderboard(limit);
+    ic_cdk::println!("CALL[get_reward_leaderboard] Output: {} entries", result.len());
+    result
+}
+
+/// Rewards owed but not yet claimed (pending, locked_unclaimed) and claimed so far
+#[ic_cdk::query]
+fn get_liability_summary() -> LiabilitySummary {
+    task_rewards::liability::get_liability_summary()
+}
+
+/// Rebuild the liability summary from all user tasks in the background (admin only)
+#[ic_cdk::update]
+fn recompute_liability_summary() -> Result<(), String> {
+    ic_cdk::println!("CALL[recompute_liability_summary] Input: none");
+    let result = task_rewards::liability::recompute_liability_summary();
+    ic_cdk::println!("CALL[recompute_liability_summary] Output: {:?}", result);
+    result
+}
+
+/// How many wallets have a task in each status
+#[ic_cdk::query]
+fn get_task_funnel(taskid: String) -> TaskFunnel {
+    task_rewards::funnel::get_task_funnel(taskid)
+}
+
+/// Status funnel of every task, in taskid order
+#[ic_cdk::query]
+fn get_all_task_funnels() -> Vec<TaskFunnel> {
+    task_rewards::funnel::get_all_task_funnels()
+}
+
+/// Rebuild the task funnel counters from all user tasks in the background (admin only)
+#[ic_cdk::update]
+fn rebuild_task_funnels() -> Result<(), String> {
+    ic_cdk::println!("CALL[rebuild_task_funnels] Input: none");
+    let result = task_rewards::funnel::rebuild_task_funnels();
+    ic_cdk::println!("CALL[rebuild_task_funnels] Output: {:?}", result);
+    result
+}
+
+/// Check in for today: books the daily check-in task's reward with the streak bonus
+#[ic_cdk::update]
+fn check_in(wallet: String) -> Result<CheckinReceipt, String> {
+    ic_cdk::println!("CALL[check_in] Input: wallet={}", wallet);
+    let result = task_rewards::checkins::check_in(wallet);
+    ic_cdk::println!("CALL[check_in] Output: {:?}", result);
+    result
+}
+
+/// A wallet's daily check-in streak
+#[ic_cdk::query]
+fn get_streak(wallet: String) -> StreakView {
+    task_rewards::checkins::get_streak(wallet)
+}
+
+/// Stored and expected schema version of each structure and the migration running (admin only)
+#[ic_cdk::query]
+fn get_schema_versions() -> Result<SchemaVersionReport, String> {
+    schema_versions::get_schema_versions()
+}
+
+/// Run the next chunk of the running schema migration (admin only)
+#[ic_cdk::update]
+fn drive_migration() -> Result<SchemaVersionReport, String> {
+    ic_cdk::println!("CALL[drive_migration] Input: none");
+    let result = schema_versions::drive_migration();
+    ic_cdk::println!("CALL[drive_migration] Output: {:?}", result);
+    result
+}
+
+/// Wallets with task state matching a filter, one page after a cursor (controller or wallet viewer)
+#[ic_cdk::query]
+fn list_wallets(filter: WalletFilter, after_wallet: Option<String>, limit: u64) -> Result<(Vec<String>, Option<String>), String> {
+    ic_cdk::println!("CALL[list_wallets] Input: filter={:?}, after_wallet={:?}, limit={}", filter, after_wallet, limit);
+    let result = task_rewards::wallet_listing::list_wallets(filter, after_wallet, limit);
+    ic_cdk::println!("CALL[list_wallets] Output: {:?}", result.as_ref().map(|(wallets, next)| (wallets.len(), next)));
+    result
+}
+
+/// Number of wallets matching a filter (controller or wallet viewer)
+#[ic_cdk::query]
+fn count_wallets(filter: WalletFilter) -> Result<u64, String> {
+    task_rewards::wallet_listing::count_wallets(filter)
+}
+
+/// Allow a principal to list and count wallets (admin only)
+#[ic_cdk::update]
+fn add_wallet_viewer(viewer: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[add_wallet_viewer] Input: viewer={}", viewer);
+    let result = task_rewards::wallet_listing::add_wallet_viewer(viewer);
+    ic_cdk::println!("CALL[add_wallet_viewer] Output: {:?}", result);
+    result
+}
+
+/// Revoke a wallet viewer (admin only)
+#[ic_cdk::update]
+fn remove_wallet_viewer(viewer: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[remove_wallet_viewer] Input: viewer={}", viewer);
+    let result = task_rewards::wallet_listing::remove_wallet_viewer(viewer);
+    ic_cdk::println!("CALL[remove_wallet_viewer] Output: {:?}", result);
+    result
+}
+
+/// List wallet viewers (admin only)
+#[ic_cdk::query]
+fn list_wallet_viewers() -> Result<Vec<Principal>, String> {
+    task_rewards::wallet_listing::list_wallet_viewers()
+}
+
+/// Submit evidence of a task that needs a verifier's attestation (controller or the wallet's bound principal)
+#[ic_cdk::update]
+fn submit_task_evidence(wallet: String, taskid: String, evidence: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[submit_task_evidence] Input: wallet={}, taskid={}, evidence={}", wallet, taskid, evidence);
+    let result = task_rewards::attestations::submit_task_evidence(wallet, taskid, evidence);
+    ic_cdk::println!("CALL[submit_task_evidence] Output: {:?}", result);
+    result
+}
+
+/// Approve or reject pending evidence (task verifiers and controllers)
+#[ic_cdk::update]
+fn attest_task(wallet: String, taskid: String, approved: bool, note: Option<String>) -> Result<(), String> {
+    ic_cdk::println!("CALL[attest_task] Input: wallet={}, taskid={}, approved={}, note={:?}", wallet, taskid, approved, note);
+    let result = task_rewards::attestations::attest_task(wallet, taskid, approved, note);
+    ic_cdk::println!("CALL[attest_task] Output: {:?}", result);
+    result
+}
+
+/// Pending evidence of a task, one page at an offset (task verifiers and controllers)
+#[ic_cdk::query]
+fn list_pending_verifications(taskid: String, offset: u64, limit: u64) -> Result<Vec<EvidenceSubmission>, String> {
+    ic_cdk::println!("CALL[list_pending_verifications] Input: taskid={}, offset={}, limit={}", taskid, offset, limit);
+    let result = task_rewards::attestations::list_pending_verifications(taskid, offset, limit);
+    ic_cdk::println!("CALL[list_pending_verifications] Output: {:?}", result.as_ref().map(|v| v.len()));
+    result
+}
+
+/// Latest evidence a wallet submitted for a task and the verifier's decision
+#[ic_cdk::query]
+fn get_evidence_submission(wallet: String, taskid: String) -> Option<EvidenceSubmission> {
+    task_rewards::attestations::get_evidence_submission(wallet, taskid)
+}
+
+/// Allow a principal to attest tasks (admin only)
+#[ic_cdk::update]
+fn add_task_verifier(verifier: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[add_task_verifier] Input: verifier={}", verifier);
+    let result = task_rewards::attestations::add_task_verifier(verifier);
+    ic_cdk::println!("CALL[add_task_verifier] Output: {:?}", result);
+    result
+}
+
+/// Revoke a task verifier (admin only)
+#[ic_cdk::update]
+fn remove_task_verifier(verifier: Principal) -> Result<(), String> {
+    ic_cdk::println!("CALL[remove_task_verifier] Input: verifier={}", verifier);
+    let result = task_rewards::attestations::remove_task_verifier(verifier);
+    ic_cdk::println!("CALL[remove_task_verifier] Output: {:?}", result);
+    result
+}
+
+/// List task verifiers (admin only)
+#[ic_cdk::query]
+fn list_task_verifiers() -> Result<Vec<Principal>, String> {
+    task_rewards::attestations::list_task_verifiers()
+}
+
+/// Propose replacing an epoch's Merkle root; another admin must approve it (admin only)
+#[ic_cdk::update]
+fn propose_epoch_root_override(epoch: u64, new_root: Vec<u8>, justification: String) -> Result<EpochRootOverride, String> {
+    ic_cdk::println!("CALL[propose_epoch_root_override] Input: epoch={}, new_root={}, justification={}", epoch, hex::encode(&new_root), justification);
+    let result = task_rewards::root_overrides::propose_epoch_root_override(epoch, new_root, justification);
+    ic_cdk::println!("CALL[propose_epoch_root_override] Output: {:?}", result);
+    result
+}
+
+/// Approve another admin's root override proposal and invalidate the epoch's issued tickets (admin only)
+#[ic_cdk::update]
+fn approve_epoch_root_override(epoch: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[approve_epoch_root_override] Input: epoch={}", epoch);
+    let result = task_rewards::root_overrides::approve_epoch_root_override(epoch);
+    ic_cdk::println!("CALL[approve_epoch_root_override] Output: {:?}", result);
+    result
+}
+
+/// Pending root override proposal of an epoch
+#[ic_cdk::query]
+fn get_epoch_root_override(epoch: u64) -> Option<EpochRootOverride> {
+    task_rewards::root_overrides::get_epoch_root_override(epoch)
+}
+
+/// Get epoch metadata
+#[ic_cdk::query]
+fn get_epoch_meta(epoch: u64) -> Option<MerkleSnapshotMeta> {
+    ic_cdk::println!("CALL[get_epoch_meta] Input: epoch={}", epoch);
+    let result = task_rewards::get_epoch_meta(epoch);
+    ic_cdk::println!("CALL[get_epoch_meta] Output: exists={}", result.is_some());
+    result
+}
+
+/// Check an export of an epoch's leaves against the entries hash recorded at build time
+#[ic_cdk::query]
+fn verify_entries_export(epoch: u64, hash: Vec<u8>) -> Result<bool, String> {
+    ic_cdk::println!("CALL[verify_entries_export] Input: epoch={}", epoch);
+    let result = task_rewards::verify_entries_export(epoch, hash);
+    ic_cdk::println!("CALL[verify_entries_export] Output: {:?}", result);
+    result
+}
+
+/// Get the most recently built epoch's root with its IC certificate
+#[ic_cdk::query]
+fn get_certified_epoch_root(epoch: u64) -> Result<CertifiedResult, String> {
+    ic_cdk::println!("CALL[get_certified_epoch_root] Input: epoch={}", epoch);
+    let result = task_rewards::get_certified_epoch_root(epoch);
+    ic_cdk::println!("CALL[get_certified_epoch_root] Output: ok={}", result.is_ok());
+    result
+}
+
+/// Get total reward distributed in an epoch
+#[ic_cdk::query]
+fn get_epoch_total_reward(epoch: u64) -> Option<u64> {
+    task_rewards::get_epoch_total_reward(epoch)
+}
+
+/// Get the principal that built an epoch snapshot
+#[ic_cdk::query]
+fn get_epoch_builder(epoch: u64) -> Option<String> {
+    task_rewards::get_epoch_builder(epoch)
+}
+
+/// Check an epoch's stored wallet index for duplicate wallets or leaf indices
+#[ic_cdk::query]
+fn validate_epoch_wallet_index(epoch: u64) -> Result<(), String> {
+    ic_cdk::println!("CALL[validate_epoch_wallet_index] Input: epoch={}", epoch);
+    let result = task_rewards::validate_epoch_wallet_index(epoch);
+    ic_cdk::println!("CALL[validate_epoch_wallet_index] Output: {:?}", result);
+    result
+}
+
+/// Stored Merkle tree layer sizes of an epoch, with a shape check
+#[ic_cdk::query]
+fn count_merkle_nodes(epoch: u64) -> Result<MerkleNodeCounts, String> {
+    ic_cdk::println!("CALL[count_merkle_nodes] Input: epoch={}", epoch);
+    let result = task_rewards::count_merkle_nodes(epoch);
+    ic_cdk::println!("CALL[count_merkle_nodes] Output: {:?}", result);
+    result
+}
+
+/// Leaves and all stored Merkle layers of an epoch (at most MAX_EXPORT_TREE_LEAVES leaves)
+#[ic_cdk::query]
+fn export_epoch_tree(epoch: u64) -> Result<EpochTreeExport, String> {
+    ic_cdk::println!("CALL[export_epoch_tree] Input: epoch={}", epoch);
+    let result = task_rewards::export_epoch_tree(epoch);
+    ic_cdk::println!("CALL[export_epoch_tree] Output: {:?}", result.as_ref().map(|export| export.leaves.len()));
+    result
+}
+
+/// Leaf hash test vectors for checking compatibility with the Solana program
+#[ic_cdk::query]
+fn get_leaf_hash_testvectors() -> Vec<LeafHashTestVector> {
+    task_rewards::get_leaf_hash_testvectors()
+}
+
+/// Leaf hash of arbitrary inputs, for debugging the byte layout against the Solana program
+#[ic_cdk::query]
+fn compute_leaf_hash_debug(epoch: u64, index: u32, wallet: String, amount: u64) -> Result<Vec<u8>, String> {
+    task_rewards::compute_leaf_hash_debug(epoch, index, wallet, amount)
+}
+
+/// Parent hash of two 32-byte nodes, for debugging the byte layout against the Solana program
+#[ic_cdk::query]
+fn compute_parent_hash_debug(left: Vec<u8>, right: Vec<u8>) -> Result<Vec<u8>, String> {
+    task_rewards::compute_parent_hash_debug(left, right)
+}
+
+/// Events with sequence number greater than `seq`, for indexer catch-up
+#[ic_cdk::query]
+fn get_events_since(seq: u64, limit: u64) -> Vec<Event> {
+    ic_cdk::println!("CALL[get_events_since] Input: seq={}, limit={}", seq, limit);
+    let result = event_log::get_events_since(seq, limit);
+    ic_cdk::println!("CALL[get_events_since] Output: {} events", result.len());
+    result
+}
+
+/// Delete events with sequence number below `seq` (admin only)
+#[ic_cdk::update]
+fn prune_events_before(seq: u64) -> Result<u64, String> {
+    ic_cdk::println!("CALL[prune_events_before] Input: seq={}", seq);
+    let result = event_log::prune_events_before(seq);
+    ic_cdk::println!("CALL[prune_events_before] Output: {:?}", result);
+    result
+}
+
+/// List epoch metadata (deprecated: returns only the newest page; use list_epochs)
+#[ic_cdk::query]
+fn list_all_epochs() -> Vec<MerkleSnapshotMeta> {
+    ic_cdk::println!("CALL[list_all_epochs] Input: none");
+    let result = task_rewards::list_all_epochs();
+    ic_cdk::println!("CALL[list_all_epochs] Output: {} epochs", result.len());
+    result
+}
+
+/// Page of epoch metadata, newest first, optionally filtered by status or creation time
+#[ic_cdk::query]
+fn list_epochs(offset: u64, limit: u64, filter: Option<EpochFilter>) -> EpochPage {
+    ic_cdk::println!("CALL[list_epochs] Input: offset={}, limit={}, filter={:?}", offset, limit, filter);
+    let result = task_rewards::list_epochs(offset, limit, filter);
+    ic_cdk::println!("CALL[list_epochs] Output: {} of {} epochs", result.epochs.len(), result.total);
+    result
+}
+
+/// Metadata of an epoch and up to `depth - 1` of its predecessors, newest first
+#[ic_cdk::query]
+fn get_epoch_chain(from_epoch: u64, depth: u32) -> Vec<MerkleSnapshotMeta> {
+    task_rewards::get_epoch_chain(from_epoch, depth)
+}
+
+/// Whether every epoch's previous_epoch link resolves to a stored epoch
+#[ic_cdk::query]
+fn epoch_chain_integrity_check() -> bool {
+    task_rewards::epoch_chain_integrity_check()
+}
+
+/// Missing epoch numbers between stored epochs, as inclusive ranges
+#[ic_cdk::query]
+fn find_epoch_gaps() -> Vec<(u64, u64)> {
+    task_rewards::find_epoch_gaps()
+}
+
+/// Whether epochs form a contiguous sequence from `expect_start` to the latest
+#[ic_cdk::query]
+fn validate_epoch_continuity(expect_start: u64) -> EpochContinuityReport {
+    task_rewards::validate_epoch_continuity(expect_start)
+}
+
+/// Fix an epoch's description after it was built (admin only)
+#[ic_cdk::update]
+fn update_epoch_description(epoch: u64, description: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[update_epoch_description] Input: epoch={}, description={}", epoch, description);
+    let result = task_rewards::update_epoch_description(epoch, description);
+    ic_cdk::println!("CALL[update_epoch_description] Output: {:?}", result);
+    result
+}
+
+// ==== AI Subscription API ====
+
+use ai_subscription_types::{ServiceType, SubscriptionRecord, SubscriptionStatus, Subscription, SubscriptionPlan};
+
+#[ic_cdk::update]
+fn ai_sub_create_service(service: ServiceType) -> Result<(), String> {
+    ic_cdk::println!("CALL[ai_sub_create_service] Input: svr_id={}", service.svr_id);
+    let result = ai_sub_service::create_service(service);
+    ic_cdk::println!("CALL[ai_sub_create_service] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn ai_sub_get_service(svr_id: String) -> Option<ServiceType> {
+    ic_cdk::println!("CALL[ai_sub_get_service] Input: svr_id={}", svr_id);
+    let result = ai_sub_service::get_service(&svr_id);
+    ic_cdk::println!("CALL[ai_sub_get_service] Output: exists={}", result.is_some());
+    result
+}
+
+#[ic_cdk::update]
+fn ai_sub_update_service(svr_id: String, service: ServiceType) -> Result<(), String> {
+    ic_cdk::println!("CALL[ai_sub_update_service] Input: svr_id={}", svr_id);
+    let result = ai_sub_service::update_service(&svr_id, service);
+    ic_cdk::println!("CALL[ai_sub_update_service] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::update]
+fn ai_sub_delete_service(svr_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[ai_sub_delete_service] Input: svr_id={}", svr_id);
+    let result = ai_sub_service::delete_service(&svr_id);
+    ic_cdk::println!("CALL[ai_sub_delete_service] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn ai_sub_list_services() -> Vec<ServiceType> {
+    ic_cdk::println!("CALL[ai_sub_list_services] Input: none");
+    let result = ai_sub_service::list_services();
+    ic_cdk::println!("CALL[ai_sub_list_services] Output: count={}", result.len());
+    result
+}
+
+#[ic_cdk::query]
+fn ai_sub_list_services_paginated(offset: u64, limit: usize) -> Vec<ServiceType> {
+    ic_cdk::println!("CALL[ai_sub_list_services_paginated] Input: offset={}, limit={}", offset, limit);
+    let result = ai_sub_service::list_services_paginated(offset, limit);
+    ic_cdk::println!("CALL[ai_sub_list_services_paginated] Output: count={}", result.len());
+    result
+}
+
+#[ic_cdk::query]
+fn ai_sub_service_count() -> u64 {
+    ai_sub_service::service_count()
+}
+
+#[ic_cdk::update]
+fn ai_sub_create_subscription_record(record: SubscriptionRecord) -> Result<u64, String> {
+    ic_cdk::println!("CALL[ai_sub_create_subscription_record] Input: principal={}, svr_id={}", record.principal_id, record.svr_id);
+    let result = ai_sub_service::create_subscription_record(record);
+    ic_cdk::println!("CALL[ai_sub_create_subscription_record] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn ai_sub_get_subscription_record(index: u64) -> Option<SubscriptionRecord> {
+    ai_sub_service::get_subscription_record(index)
+}
+
+#[ic_cdk::update]
+fn ai_sub_update_subscription_status(index: u64, status: SubscriptionStatus) -> Result<(), String> {
+    ai_sub_service::update_subscription_status(index, status)
+}
+
+#[ic_cdk::update]
+fn ai_sub_resolve_subscription(index: u64) -> Result<(), String> {
+    ai_sub_service::resolve_subscription(index)
+}
+
+#[ic_cdk::query]
+fn ai_sub_list_subscriptions_by_principal(principal_id: String) -> Vec<SubscriptionRecord> {
+    ic_cdk::println!("CALL[ai_sub_list_subscriptions_by_principal] Input: principal_id={}", principal_id);
+    let result = ai_sub_service::list_subscriptions_by_principal(&principal_id);
+    ic_cdk::println!("CALL[ai_sub_list_subscriptions_by_principal] Output: count={}", result.len());
+    result
+}
+
+#[ic_cdk::query]
+fn ai_sub_list_subscriptions_by_principal_paginated(principal_id: String, offset: u64, limit: usize) -> Vec<SubscriptionRecord> {
+    ai_sub_service::list_subscriptions_by_principal_paginated(&principal_id, offset, limit)
+}
+
+#[ic_cdk::query]
+fn ai_sub_subscription_record_count() -> u64 {
+    ai_sub_service::subscription_record_count()
+}
+
+#[ic_cdk::query]
+fn ai_sub_is_subscribed(principal_id: String, svr_id: String) -> bool {
+    ai_sub_service::is_subscribed(&principal_id, &svr_id)
+}
+
+#[ic_cdk::query]
+fn ai_sub_get_active_subscriptions(principal_id: String) -> Vec<SubscriptionRecord> {
+    ai_sub_service::get_active_subscriptions(&principal_id)
+}
+
+/// Bind a payment wallet to a principal for subscription entitlements (admin only)
+#[ic_cdk::update]
+fn bind_wallet_principal(wallet: String, principal_id: String) -> Result<(), String> {
+    ic_cdk::println!("CALL[bind_wallet_principal] Input: wallet={}, principal_id={}", wallet, principal_id);
+    let result = ai_sub_service::bind_wallet_principal(wallet, principal_id);
+    ic_cdk::println!("CALL[bind_wallet_principal] Output: {:?}", result);
+    result
+}
+
+/// Get the exact message a wallet owner must sign for bind_wallet_with_proof
+#[ic_cdk::query]
+fn get_binding_message(principal_id: String, wallet: String) -> String {
+    ai_sub_service::get_binding_message(principal_id, wallet)
+}
+
+/// Bind a Solana wallet to the caller with an ed25519 signature from the wallet's key
+#[ic_cdk::update]
+fn bind_wallet_with_proof(wallet: String, signature: Vec<u8>, message: Vec<u8>) -> Result<(), String> {
+    ic_cdk::println!("CALL[bind_wallet_with_proof] Input: wallet={}", wallet);
+    let result = ai_sub_service::bind_wallet_with_proof(wallet, signature, message);
+    ic_cdk::println!("CALL[bind_wallet_with_proof] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn get_wallet_principal(wallet: String) -> Option<String> {
+    ai_sub_service::get_wallet_principal(&wallet)
+}
+
+/// Configure the entitlement a payfor category grants (admin only)
+#[ic_cdk::update]
+fn set_subscription_plan(payfor: String, plan: SubscriptionPlan) -> Result<(), String> {
+    ic_cdk::println!("CALL[set_subscription_plan] Input: payfor={}, plan={:?}", payfor, plan);
+    let result = ai_sub_service::set_subscription_plan(payfor, plan);
+    ic_cdk::println!("CALL[set_subscription_plan] Output: {:?}", result);
+    result
+}
+
+#[ic_cdk::query]
+fn get_subscription_plan(payfor: String) -> Option<SubscriptionPlan> {
+    ai_sub_service::get_subscription_plan(&payfor)
+}
+
+/// Current payment-backed subscription of a principal
+#[ic_cdk::query]
+fn get_subscription(principal_id: String) -> Option<Subscription> {
+    ai_sub_service::get_subscription(&principal_id)
+}
+
+/// Whether a principal's subscription is active at `now` (nanoseconds)
+#[ic_cdk::query]
+fn is_subscription_active(principal_id: String, now: u64) -> bool {
+    ai_sub_service::is_subscription_active(&principal_id, now)
+}
+
+
+
+// ==== Candid Interface ====
+
+/// Schema number of the candid interface. Bump it whenever a method or a type it uses changes;
+/// the interface fingerprint test fails until the bump is made.
+pub const INTERFACE_SCHEMA_VERSION: u64 = 29;
+
+/// Crate version and candid schema number, so clients can detect interface drift between canisters
+#[ic_cdk::query]
+fn get_interface_version() -> (String, u64) {
+    (env!("CARGO_PKG_VERSION").to_string(), INTERFACE_SCHEMA_VERSION)
+}
+
+/// Candid interface generated from the exported methods
+#[ic_cdk::query(hidden = true)]
+fn __get_candid_interface_tmp_hack() -> String {
+    __export_service()
+}
+
+candid::export_service!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::collections::BTreeSet;
+
+    /// INTERFACE_SCHEMA_VERSION and the sha256 of the exported interface it was bumped for
+    const INTERFACE_FINGERPRINT: (u64, &str) = (29, "423baed3e6275f6a06e50f19ee554c9fcec68971996aa54470f296f9594fcacb");
+
+    /// Method names of the service block of a .did: lines of the form `name : (` or `"name": (`
+    fn service_methods(did: &str) -> BTreeSet<String> {
+        let service = &did[did.find("service :").expect("no service block")..];
+        service.lines()
+            .skip(1)
+            .filter_map(|line| line.trim().split_once(':'))
+            .filter(|(_, signature)| signature.trim_start().starts_with('('))
+            .map(|(name, _)| name.trim().trim_matches('"').to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_exported_methods_are_declared_in_did() {
+        let exported = service_methods(&__export_service());
+        assert!(exported.contains("get_interface_version"));
+        let declared = service_methods(include_str!("../aio-base-backend.did"));
+        let missing: Vec<&String> = exported.difference(&declared).collect();
+        assert!(missing.is_empty(), "methods missing from aio-base-backend.did: {:?}", missing);
+    }
+
+    #[test]
+    fn test_get_or_init_user_tasks_is_an_update() {
+        // It stores new task states and records an interaction; a query would discard both
+        let interface = __export_service();
+        let line = interface.lines()
+            .find(|line| line.trim_start().starts_with("get_or_init_user_tasks :"))
+            .expect("get_or_init_user_tasks is not exported");
+        assert!(!line.contains("query"), "{}", line);
+    }
+
+    #[test]
+    fn test_interface_fingerprint_matches_schema_version() {
+        // Doc comments are exported too; leave them out so rewording a comment needs no bump
+        let interface: String = __export_service().lines()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect();
+        let fingerprint = hex::encode(Sha256::digest(interface.as_bytes()));
+        assert_eq!(
+            (INTERFACE_SCHEMA_VERSION, fingerprint.as_str()),
+            INTERFACE_FINGERPRINT,
+            "the candid interface changed: bump INTERFACE_SCHEMA_VERSION and record the new fingerprint",
+        );
+    }
+}
@@ -0,0 +1,174 @@
+// Rate Limit Module - fixed-window call limits per caller and per wallet
+//
+// Limits are configured per method in stable memory; the counters live on the heap only,
+// so an upgrade simply starts fresh windows. Controllers are never limited.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use crate::stable_mem_storage::RATE_LIMIT_CONFIGS;
+
+/// Methods that can be rate limited
+pub const RATE_LIMITED_METHODS: [&str; 3] = ["complete_task", "get_claim_ticket", "record_payment"];
+
+/// How often expired windows are dropped from the heap (nanoseconds)
+const PRUNE_INTERVAL_NS: u64 = 60 * 1_000_000_000;
+
+/// Limit for one method: at most `max_per_window` calls per key every `window_secs`
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    pub max_per_window: u32,
+    pub window_secs: u64,
+}
+
+impl Storable for RateLimitConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize RateLimitConfig");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize RateLimitConfig")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Current window of one (method, key) counter
+#[derive(Clone, Debug)]
+struct Window {
+    start: u64,
+    count: u32,
+}
+
+impl Window {
+    fn end(&self, config: &RateLimitConfig) -> u64 {
+        self.start.saturating_add(config.window_secs.saturating_mul(1_000_000_000))
+    }
+}
+
+type Counters = BTreeMap<(String, String), Window>;
+
+thread_local! {
+    static COUNTERS: RefCell<Counters> = const { RefCell::new(BTreeMap::new()) };
+    static LAST_PRUNE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Set the limit for a method (admin only); `max_per_window == 0` removes the limit
+pub fn set_rate_limit(method: String, max_per_window: u32, window_secs: u64) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can set rate limits".to_string());
+    }
+    if !RATE_LIMITED_METHODS.contains(&method.as_str()) {
+        return Err(format!("Unknown rate limited method {}: expected one of {:?}", method, RATE_LIMITED_METHODS));
+    }
+    if max_per_window == 0 {
+        RATE_LIMIT_CONFIGS.with(|store| store.borrow_mut().remove(&method));
+        return Ok(());
+    }
+    if window_secs == 0 {
+        return Err("window_secs must be at least 1".to_string());
+    }
+    RATE_LIMIT_CONFIGS.with(|store| {
+        store.borrow_mut().insert(method, RateLimitConfig { max_per_window, window_secs });
+    });
+    Ok(())
+}
+
+/// List configured limits
+pub fn get_rate_limits() -> Vec<(String, RateLimitConfig)> {
+    RATE_LIMIT_CONFIGS.with(|store| store.borrow().iter().collect())
+}
+
+/// Count one call for `key`; returns the seconds until the window resets when over the limit
+fn hit(counters: &mut Counters, method: &str, key: &str, now: u64, config: &RateLimitConfig) -> Result<(), u64> {
+    let window = counters
+        .entry((method.to_string(), key.to_string()))
+        .or_insert(Window { start: now, count: 0 });
+    if now >= window.end(config) {
+        *window = Window { start: now, count: 0 };
+    }
+    if window.count >= config.max_per_window {
+        let remaining_ns = window.end(config) - now;
+        return Err(remaining_ns.div_ceil(1_000_000_000));
+    }
+    window.count += 1;
+    Ok(())
+}
+
+/// Drop windows that have ended; unknown methods use a zero-length window
+fn prune(counters: &mut Counters, now: u64, configs: &BTreeMap<String, RateLimitConfig>) {
+    counters.retain(|(method, _), window| {
+        configs.get(method).is_some_and(|config| now < window.end(config))
+    });
+}
+
+/// Enforce the limit of `method` for the caller and the given wallet.
+/// Returns `RateLimited { retry_after_secs: N }` when either is over the limit.
+pub fn check_rate_limit(method: &str, wallet: &str) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if crate::env::is_controller(&caller) {
+        return Ok(());
+    }
+    let config = match RATE_LIMIT_CONFIGS.with(|store| store.borrow().get(&method.to_string())) {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let now = crate::env::time();
+
+    COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+
+        if now.saturating_sub(LAST_PRUNE.with(|c| c.get())) >= PRUNE_INTERVAL_NS {
+            let configs: BTreeMap<String, RateLimitConfig> = get_rate_limits().into_iter().collect();
+            prune(&mut counters, now, &configs);
+            LAST_PRUNE.with(|c| c.set(now));
+        }
+
+        for key in [format!("caller:{}", caller), format!("wallet:{}", wallet)] {
+            if let Err(retry_after_secs) = hit(&mut counters, method, &key, now, &config) {
+                return Err(format!("RateLimited {{ retry_after_secs: {} }}", retry_after_secs));
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1_000_000_000;
+
+    #[test]
+    fn test_fixed_window_limits_and_resets() {
+        let config = RateLimitConfig { max_per_window: 2, window_secs: 10 };
+        let mut counters = Counters::new();
+        assert!(hit(&mut counters, "complete_task", "wallet:a", 0, &config).is_ok());
+        assert!(hit(&mut counters, "complete_task", "wallet:a", SEC, &config).is_ok());
+        assert_eq!(hit(&mut counters, "complete_task", "wallet:a", 2 * SEC, &config), Err(8));
+        // Other keys and methods have their own windows
+        assert!(hit(&mut counters, "complete_task", "wallet:b", 2 * SEC, &config).is_ok());
+        assert!(hit(&mut counters, "record_payment", "wallet:a", 2 * SEC, &config).is_ok());
+        // New window after window_secs
+        assert!(hit(&mut counters, "complete_task", "wallet:a", 10 * SEC, &config).is_ok());
+    }
+
+    #[test]
+    fn test_prune_drops_ended_windows() {
+        let config = RateLimitConfig { max_per_window: 1, window_secs: 10 };
+        let mut counters = Counters::new();
+        hit(&mut counters, "complete_task", "wallet:a", 0, &config).unwrap();
+        hit(&mut counters, "complete_task", "wallet:b", 5 * SEC, &config).unwrap();
+        hit(&mut counters, "get_claim_ticket", "wallet:a", 5 * SEC, &config).unwrap();
+
+        let configs = BTreeMap::from([("complete_task".to_string(), config)]);
+        prune(&mut counters, 12 * SEC, &configs);
+        let keys: Vec<&(String, String)> = counters.keys().collect();
+        assert_eq!(keys, vec![&("complete_task".to_string(), "wallet:b".to_string())]);
+    }
+}
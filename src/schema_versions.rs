@@ -0,0 +1,345 @@
+// Schema Versions - which layout the stored data of each structure is at, and the migrations
+// that move it to the layout this build expects
+//
+// SCHEMA_VERSIONS records a version per structure name; a structure it does not list is at
+// BASELINE_SCHEMA_VERSION (data written before the registry existed, in any of the shapes the
+// Storable fallbacks read). post_upgrade plans the registered migrations from the stored
+// versions to EXPECTED_SCHEMA_VERSIONS and traps, leaving the old code in place, if a step is
+// missing or the stored data is newer than this build.
+//
+// A migration rewrites its map MIGRATION_CHUNK entries at a time from a cursor kept in the
+// cell, so an upgrade only runs a few chunks and timers run the rest; drive_migration runs a
+// chunk on demand. A structure's version is raised once its migration finished, and reads
+// keep going through the fallbacks until then.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{StableBTreeMap, Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+use std::time::Duration;
+
+use crate::stable_mem_storage::{Memory, EPOCH_META, PAYMENTS, SCHEMA_VERSIONS, TASK_CONTRACT, USER_TASKS};
+use crate::storage_utils::paginate_btree;
+
+/// Version of a structure missing from SCHEMA_VERSIONS
+pub const BASELINE_SCHEMA_VERSION: u32 = 1;
+
+/// Entries rewritten per migration step
+pub const MIGRATION_CHUNK: u64 = 500;
+
+/// Steps run inside post_upgrade before the rest is left to timers
+const UPGRADE_MIGRATION_STEPS: u32 = 4;
+
+/// Version of each structure's stored data this build reads and writes
+pub const EXPECTED_SCHEMA_VERSIONS: &[(&str, u32)] = &[
+    ("MerkleSnapshotMeta", 4),
+    ("PaymentRecord", 2),
+    ("TaskContractItem", 5),
+    ("UserTaskState", 2),
+];
+
+/// One step of a map from entries at `from` to entries at `from + 1`
+#[derive(Debug)]
+struct Migration {
+    structure: &'static str,
+    from: u32,
+    step: fn(cursor: Option<Vec<u8>>, limit: u64) -> MigrationStep,
+}
+
+/// Entries rewritten by a step and the cursor to continue from (None once the map is done)
+type MigrationStep = (u64, Option<Vec<u8>>);
+
+// Version 2 of each: every entry re-encoded in the current shape, so no stored entry needs
+// the legacy fallbacks any more
+const MIGRATIONS: &[Migration] = &[
+    Migration { structure: "MerkleSnapshotMeta", from: 1, step: rewrite_epoch_meta },
+    // Version 3: entries_hash (zero for epochs built before it)
+    Migration { structure: "MerkleSnapshotMeta", from: 2, step: rewrite_epoch_meta },
+    // Version 4: clock_warning
+    Migration { structure: "MerkleSnapshotMeta", from: 3, step: rewrite_epoch_meta },
+    Migration { structure: "PaymentRecord", from: 1, step: rewrite_payments },
+    Migration { structure: "TaskContractItem", from: 1, step: rewrite_task_contract },
+    // Version 3: daily_checkin
+    Migration { structure: "TaskContractItem", from: 2, step: rewrite_task_contract },
+    // Version 4: paused
+    Migration { structure: "TaskContractItem", from: 3, step: rewrite_task_contract },
+    // Version 5: requires_attestation
+    Migration { structure: "TaskContractItem", from: 4, step: rewrite_task_contract },
+    Migration { structure: "UserTaskState", from: 1, step: rewrite_user_tasks },
+];
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RunningMigration {
+    pub structure: String,
+    pub from: u32,
+    pub to: u32,
+    pub cursor: Option<Vec<u8>>,  // Encoded key of the last entry rewritten; None before the first step
+    pub migrated: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SchemaVersions {
+    pub versions: BTreeMap<String, u32>,
+    pub running: Option<RunningMigration>,
+}
+
+impl Storable for SchemaVersions {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize SchemaVersions");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize SchemaVersions")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct StructureSchema {
+    pub structure: String,
+    pub stored: u32,
+    pub expected: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct SchemaVersionReport {
+    pub structures: Vec<StructureSchema>,
+    pub running: Option<RunningMigration>,
+    pub up_to_date: bool,
+}
+
+fn load() -> SchemaVersions {
+    SCHEMA_VERSIONS.with(|cell| cell.borrow().get().clone())
+}
+
+fn save(versions: SchemaVersions) {
+    SCHEMA_VERSIONS.with(|cell| cell.borrow_mut().set(versions))
+        .expect("Failed to store schema versions");
+}
+
+fn stored_version(versions: &SchemaVersions, structure: &str) -> u32 {
+    versions.versions.get(structure).copied().unwrap_or(BASELINE_SCHEMA_VERSION)
+}
+
+/// Migrations still to run, in order, to bring the stored versions to the expected ones
+fn plan(versions: &SchemaVersions) -> Result<Vec<&'static Migration>, String> {
+    if let Some(unknown) = versions.versions.keys().find(|name| !EXPECTED_SCHEMA_VERSIONS.iter().any(|(known, _)| known == name)) {
+        return Err(format!("Stored data has a schema version for {}, which this build does not know", unknown));
+    }
+    let mut pending = Vec::new();
+    for (structure, expected) in EXPECTED_SCHEMA_VERSIONS {
+        let stored = stored_version(versions, structure);
+        if stored > *expected {
+            return Err(format!(
+                "Stored {} data is at schema version {} but this build expects {}; it was written by a newer build",
+                structure, stored, expected
+            ));
+        }
+        for from in stored..*expected {
+            let migration = MIGRATIONS.iter()
+                .find(|m| m.structure == *structure && m.from == from)
+                .ok_or_else(|| format!("No migration registered for {} from schema version {} to {}", structure, from, from + 1))?;
+            pending.push(migration);
+        }
+    }
+    Ok(pending)
+}
+
+fn start(migration: &Migration) -> RunningMigration {
+    RunningMigration {
+        structure: migration.structure.to_string(),
+        from: migration.from,
+        to: migration.from + 1,
+        cursor: None,
+        migrated: 0,
+    }
+}
+
+/// Check the stored versions against this build and start the first pending migration
+/// (called from post_upgrade, which traps on an error)
+pub(crate) fn check_on_upgrade() -> Result<(), String> {
+    let mut versions = load();
+    let pending = plan(&versions)?;
+    if let Some(running) = &versions.running {
+        // A migration interrupted by this upgrade resumes only if this build plans it next
+        if pending.first().is_none_or(|m| m.structure != running.structure || m.from != running.from) {
+            return Err(format!(
+                "Migration of {} from schema version {} was interrupted and this build does not continue it",
+                running.structure, running.from
+            ));
+        }
+    } else if let Some(first) = pending.first() {
+        versions.running = Some(start(first));
+        save(versions);
+    }
+
+    for _ in 0..UPGRADE_MIGRATION_STEPS {
+        if migration_step(MIGRATION_CHUNK)? {
+            return Ok(());
+        }
+    }
+    schedule_migration_step();
+    Ok(())
+}
+
+/// Log the stored versions before an upgrade. It does not trap: a failing pre_upgrade would
+/// block the upgrade that fixes the problem.
+pub(crate) fn log_before_upgrade() {
+    let versions = load();
+    match &versions.running {
+        Some(running) => crate::env::println!(
+            "Upgrading during migration of {} to schema version {} ({} entries migrated)",
+            running.structure, running.to, running.migrated
+        ),
+        None => crate::env::println!("Upgrading with schema versions {:?}", versions.versions),
+    }
+}
+
+/// Run one step of the running migration, moving on to the next pending one when it
+/// finishes; true once nothing is left to migrate
+fn migration_step(limit: u64) -> Result<bool, String> {
+    let mut versions = load();
+    let Some(mut running) = versions.running.take() else {
+        return Ok(true);
+    };
+    let migration = MIGRATIONS.iter()
+        .find(|m| m.structure == running.structure && m.from == running.from)
+        .ok_or_else(|| format!("No migration registered for {} from schema version {}", running.structure, running.from))?;
+    let (migrated, cursor) = (migration.step)(running.cursor.take(), limit);
+    running.migrated += migrated;
+
+    if cursor.is_some() {
+        running.cursor = cursor;
+        versions.running = Some(running);
+        save(versions);
+        return Ok(false);
+    }
+    crate::env::println!("Migrated {} entries of {} to schema version {}", running.migrated, running.structure, running.to);
+    versions.versions.insert(running.structure, running.to);
+    versions.running = plan(&versions)?.first().map(|next| start(next));
+    let done = versions.running.is_none();
+    save(versions);
+    Ok(done)
+}
+
+fn schedule_migration_step() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || match migration_step(MIGRATION_CHUNK) {
+        Ok(true) => crate::env::println!("Schema migrations finished"),
+        Ok(false) => schedule_migration_step(),
+        Err(e) => crate::env::println!("Schema migration stopped: {}", e),
+    });
+}
+
+/// Stored and expected version of every structure and the migration running, if any
+pub fn schema_version_report() -> SchemaVersionReport {
+    let versions = load();
+    let structures: Vec<StructureSchema> = EXPECTED_SCHEMA_VERSIONS.iter()
+        .map(|(structure, expected)| StructureSchema {
+            structure: structure.to_string(),
+            stored: stored_version(&versions, structure),
+            expected: *expected,
+        })
+        .collect();
+    let up_to_date = versions.running.is_none() && structures.iter().all(|s| s.stored == s.expected);
+    SchemaVersionReport { structures, running: versions.running, up_to_date }
+}
+
+/// Schema versions of the stored data (admin only)
+pub fn get_schema_versions() -> Result<SchemaVersionReport, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can read schema versions".to_string());
+    }
+    Ok(schema_version_report())
+}
+
+/// Run the next chunk of the running migration now instead of waiting for its timer (admin only)
+pub fn drive_migration() -> Result<SchemaVersionReport, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can drive schema migrations".to_string());
+    }
+    migration_step(MIGRATION_CHUNK)?;
+    Ok(schema_version_report())
+}
+
+/// Re-encode up to `limit` entries after `cursor` in the current shape
+fn rewrite_chunk<K, V>(
+    store: &'static LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>,
+    cursor: Option<Vec<u8>>,
+    limit: u64,
+) -> MigrationStep
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let cursor = cursor.map(|bytes| K::from_bytes(Cow::Owned(bytes)));
+    store.with(|store| {
+        let mut map = store.borrow_mut();
+        let (page, next) = paginate_btree(&map, cursor, limit);
+        let rewritten = page.len() as u64;
+        for (key, value) in page {
+            map.insert(key, value);
+        }
+        (rewritten, next.map(|key| key.to_bytes().into_owned()))
+    })
+}
+
+fn rewrite_epoch_meta(cursor: Option<Vec<u8>>, limit: u64) -> MigrationStep {
+    rewrite_chunk(&EPOCH_META, cursor, limit)
+}
+
+fn rewrite_payments(cursor: Option<Vec<u8>>, limit: u64) -> MigrationStep {
+    rewrite_chunk(&PAYMENTS, cursor, limit)
+}
+
+fn rewrite_task_contract(cursor: Option<Vec<u8>>, limit: u64) -> MigrationStep {
+    rewrite_chunk(&TASK_CONTRACT, cursor, limit)
+}
+
+fn rewrite_user_tasks(cursor: Option<Vec<u8>>, limit: u64) -> MigrationStep {
+    rewrite_chunk(&USER_TASKS, cursor, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_rewards::UserTaskState;
+
+    #[test]
+    fn test_plan_and_chunked_migration() {
+        let mut newer = SchemaVersions::default();
+        newer.versions.insert("UserTaskState".to_string(), 3);
+        assert!(plan(&newer).unwrap_err().contains("newer build"));
+        let mut unknown = SchemaVersions::default();
+        unknown.versions.insert("Gone".to_string(), 1);
+        assert!(plan(&unknown).unwrap_err().contains("does not know"));
+        let mut missing = SchemaVersions::default();
+        missing.versions.insert("UserTaskState".to_string(), 0);
+        assert!(plan(&missing).unwrap_err().contains("from schema version 0 to 1"));
+
+        for i in 0..(2 * MIGRATION_CHUNK + 3) {
+            let wallet = format!("w{:04}", i);
+            USER_TASKS.with(|store| store.borrow_mut().insert(wallet.clone(), UserTaskState::new(wallet, Vec::new())));
+        }
+        let pending = plan(&load()).unwrap();
+        assert_eq!(pending.len(), EXPECTED_SCHEMA_VERSIONS.len() + 5);
+        save(SchemaVersions { versions: BTreeMap::new(), running: Some(start(pending[0])) });
+
+        // Empty maps finish in one step each; the wallets take three
+        let mut steps = 0;
+        while !migration_step(MIGRATION_CHUNK).unwrap() {
+            steps += 1;
+            assert!(!schema_version_report().up_to_date);
+        }
+        assert_eq!(steps, 10);
+        let report = schema_version_report();
+        assert!(report.up_to_date);
+        assert!(report.structures.iter().all(|s| s.stored == s.expected));
+        assert_eq!(plan(&load()).unwrap().len(), 0);
+        assert_eq!(USER_TASKS.with(|store| store.borrow().len()), 2 * MIGRATION_CHUNK + 3);
+    }
+}
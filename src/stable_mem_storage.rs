@@ -1,7 +1,8 @@
 // Centralized stable memory storage for all modules
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableVec};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableVec};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use std::cell::RefCell;
+use candid::Principal;
 use crate::mining_reword::{MiningRewardPolicy, RewardEntry, UserRewardKey};
 use crate::token_economy_types::RewardIdList;
 use crate::account_storage::AccountKey;
@@ -9,12 +10,32 @@ use crate::token_economy_types::AccountInfo;
 use crate::pixel_creation_types::{Project, ProjectOwnerKey};
 use crate::device_types::{DeviceInfo, DeviceOwnerKey, DeviceIdKey};
 use crate::types::Order;
-use crate::ai_types::{UserAiConfig, PrincipalKey};
+use crate::ai_types::{UserAiConfig, PrincipalKey, AgentConfigKey, AiConfigHistoryKey, AiConfigIndexKey, CatalogEntry, DeletedAiConfig, DEFAULT_MAX_AGENTS_PER_PRINCIPAL};
 use crate::task_rewards::{
-    TaskContractItem, UserTaskState, PaymentRecord, MerkleSnapshotMeta, 
-    LayerOffset, MerkleHash, EpochWalletKey, EpochLayerKey
+    TaskContractItem, UserTaskState, PaymentRecord, RefundRecord, MerkleSnapshotMeta, 
+    LayerOffset, MerkleHash, EpochWalletKey, EpochLayerKey, EpochWalletEntry, EpochNodeKey,
+    TicketIssuance, VestedTranche, WalletEpochKey, CurrencyPaymentKey, WalletPaymentKey, WalletPaymentTimeKey, WalletMigration, PayforStats, PayforWalletKey, LeaderboardKey, CertifiedEpoch, DEFAULT_EPOCH_RATE_LIMIT,
+    DEFAULT_EPOCH_DURATION_NS
 };
-use crate::ai_subscription_types::{ServiceType, SubscriptionRecord, PrincipalSubscriptionKey};
+use crate::task_rewards::referrals::{ReferralRecord, ReferralStats};
+use crate::task_rewards::notifications::{NotificationKey, TaskNotification};
+use crate::task_rewards::wallet_flags::WalletFlag;
+use crate::task_rewards::suspensions::WalletSuspension;
+use crate::task_rewards::funnel::TaskFunnelKey;
+use crate::task_rewards::root_overrides::EpochRootOverride;
+use crate::task_rewards::checkins::CheckinStreak;
+use crate::task_rewards::attestations::{EvidenceSubmission, VerificationKey};
+use crate::task_rewards::reward_logs::{RewardCalculationLog, WalletRewardLogKey};
+use crate::task_rewards::liability::LiabilitySummary;
+use crate::task_rewards::wallet_meta::WalletMeta;
+use crate::claim_signing::ClaimSigningConfig;
+use crate::schema_versions::SchemaVersions;
+use crate::rate_limit::RateLimitConfig;
+use crate::event_log::Event;
+use crate::icrc_payments::IcrcLedgerConfig;
+use crate::icrc_payouts::{IcrcPayoutConfig, IcrcPayoutRecord};
+use crate::claim_sync::{ClaimSyncConfig, EpochClaimBitmap};
+use crate::ai_subscription_types::{ServiceType, SubscriptionRecord, PrincipalSubscriptionKey, Subscription, SubscriptionPlan};
 
 // Type alias for memory
 pub type Memory = VirtualMemory<DefaultMemoryImpl>;
@@ -258,12 +279,105 @@ thread_local! {
         )
     );
 
-    // User AI Config Storage
+    // User AI Config Storage (legacy single config per principal, migrated on access)
     pub static USER_AI_CONFIG: RefCell<StableBTreeMap<PrincipalKey, UserAiConfig, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(104)))
         )
     );
+    // User AI Config per agent: (principal_id, agent_id) -> UserAiConfig
+    pub static USER_AI_AGENT_CONFIGS: RefCell<StableBTreeMap<AgentConfigKey, UserAiConfig, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(105)))
+        )
+    );
+    pub static MAX_AGENTS_PER_PRINCIPAL: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(106))),
+            DEFAULT_MAX_AGENTS_PER_PRINCIPAL
+        ).unwrap()
+    );
+    // User AI Config history: (principal_id, seq) -> UserAiConfig version
+    pub static USER_AI_CONFIG_HISTORY: RefCell<StableBTreeMap<AiConfigHistoryKey, UserAiConfig, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(107)))
+        )
+    );
+    // Secondary index: (voice_id, principal_id, agent_id) -> ()
+    pub static AI_CONFIGS_BY_VOICE: RefCell<StableBTreeMap<AiConfigIndexKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(108)))
+        )
+    );
+    // Secondary index: (agent_id, principal_id, agent_id) -> ()
+    pub static AI_CONFIGS_BY_AGENT: RefCell<StableBTreeMap<AiConfigIndexKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(109)))
+        )
+    );
+    // Admin-managed agent catalog: agent_id -> CatalogEntry
+    pub static AGENT_CATALOG: RefCell<StableBTreeMap<String, CatalogEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(110)))
+        )
+    );
+    // Admin-managed voice catalog: voice_id -> CatalogEntry
+    pub static VOICE_CATALOG: RefCell<StableBTreeMap<String, CatalogEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(112)))
+        )
+    );
+    // Soft-deleted AI configs: principal_id -> DeletedAiConfig
+    pub static DELETED_AI_CONFIGS: RefCell<StableBTreeMap<String, DeletedAiConfig, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(113)))
+        )
+    );
+
+    // ===== Referral, Notification, Payment Index, Vesting and Flag Storage (Memory IDs: 114-119) =====
+
+    // Referrals: referee wallet -> ReferralRecord
+    pub static REFERRALS: RefCell<StableBTreeMap<String, ReferralRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(114)))
+        )
+    );
+
+    // Referral counters: referrer wallet -> ReferralStats
+    pub static REFERRAL_STATS: RefCell<StableBTreeMap<String, ReferralStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(115)))
+        )
+    );
+
+    // Task status notifications: NotificationKey -> TaskNotification
+    pub static TASK_NOTIFICATIONS: RefCell<StableBTreeMap<NotificationKey, TaskNotification, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(116)))
+        )
+    );
+
+    // Payments by currency: CurrencyPaymentKey -> () (secondary index of PAYMENTS)
+    pub static PAYMENTS_BY_CURRENCY: RefCell<StableBTreeMap<CurrencyPaymentKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(117)))
+        )
+    );
+
+    // Vested leaves of epochs built with a VestingPolicy: EpochWalletKey -> VestedTranche
+    // (EPOCH_WALLET_INDEX holds the immediate leaf of the same wallet)
+    pub static EPOCH_VESTED_TRANCHES: RefCell<StableBTreeMap<EpochWalletKey, VestedTranche, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(118)))
+        )
+    );
+
+    // Wallets excluded from snapshots and claims: wallet -> WalletFlag
+    pub static FLAGGED_WALLETS: RefCell<StableBTreeMap<String, WalletFlag, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(119)))
+        )
+    );
 
     // ===== Task Rewards Storage (Memory IDs: 120-129) =====
     
@@ -281,11 +395,11 @@ thread_local! {
         )
     );
     
-    // Payment records: append-only log
-    pub static PAYMENTS: RefCell<StableVec<PaymentRecord, Memory>> = RefCell::new(
-        StableVec::init(
+    // Payment records: payment index -> PaymentRecord (archived payments are removed)
+    pub static PAYMENTS: RefCell<StableBTreeMap<u64, PaymentRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(122)))
-        ).unwrap()
+        )
     );
     
     // Epoch metadata: epoch -> MerkleSnapshotMeta
@@ -295,14 +409,14 @@ thread_local! {
         )
     );
     
-    // Epoch wallet index: EpochWalletKey -> (index, amount)
-    pub static EPOCH_WALLET_INDEX: RefCell<StableBTreeMap<EpochWalletKey, (u64, u64), Memory>> = RefCell::new(
+    // Epoch wallet index: EpochWalletKey -> EpochWalletEntry (index, amount)
+    pub static EPOCH_WALLET_INDEX: RefCell<StableBTreeMap<EpochWalletKey, EpochWalletEntry, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(124)))
         )
     );
     
-    // Merkle tree layers: flat storage of all hashes
+    // Merkle tree layers: flat storage of all hashes (legacy epochs only)
     pub static EPOCH_LAYERS: RefCell<StableVec<MerkleHash, Memory>> = RefCell::new(
         StableVec::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(125)))
@@ -316,7 +430,177 @@ thread_local! {
         )
     );
 
-    // ===== AI Subscription Storage (Memory IDs: 130-132) =====
+    // Merkle tree nodes: EpochNodeKey -> MerkleHash (prunable per epoch)
+    pub static EPOCH_NODES: RefCell<StableBTreeMap<EpochNodeKey, MerkleHash, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(128)))
+        )
+    );
+
+    // Task contract lock flag (one-way)
+    pub static TASK_CONTRACT_LOCKED: RefCell<StableCell<bool, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(129))),
+            false
+        ).unwrap()
+    );
+
+    // Payment floors: payfor category (None = global default) -> minimum amount
+    pub static PAYMENT_FLOORS: RefCell<StableBTreeMap<Option<String>, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(127)))
+        )
+    );
+
+    // ===== Task Rewards Storage, continued (Memory IDs: 140-159) =====
+
+    // Ticket issuance: EpochWalletKey -> TicketIssuance
+    pub static TICKET_ISSUANCE: RefCell<StableBTreeMap<EpochWalletKey, TicketIssuance, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(140)))
+        )
+    );
+
+    // Claim ticket signing configuration
+    pub static CLAIM_SIGNING_CONFIG: RefCell<StableCell<ClaimSigningConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(141))),
+            ClaimSigningConfig::default()
+        ).unwrap()
+    );
+
+    // Maximum epoch snapshots per 24h
+    pub static EPOCH_RATE_LIMIT: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(142))),
+            DEFAULT_EPOCH_RATE_LIMIT
+        ).unwrap()
+    );
+
+    // Creation times of recent epoch snapshots (pruned to the 24h window)
+    pub static EPOCH_CREATION_TIMES: RefCell<StableVec<u64, Memory>> = RefCell::new(
+        StableVec::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(143)))
+        ).unwrap()
+    );
+
+    // Wallet migrations: old wallet -> WalletMigration (audit log and payment alias)
+    pub static WALLET_MIGRATIONS: RefCell<StableBTreeMap<String, WalletMigration, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(144)))
+        )
+    );
+
+    // Payment stats per payfor category
+    pub static PAYFOR_STATS: RefCell<StableBTreeMap<String, PayforStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(145)))
+        )
+    );
+
+    // Wallets seen per payfor category (for unique wallet counts)
+    pub static PAYFOR_WALLETS: RefCell<StableBTreeMap<PayforWalletKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(146)))
+        )
+    );
+
+    // Rate limits: method name -> RateLimitConfig
+    pub static RATE_LIMIT_CONFIGS: RefCell<StableBTreeMap<String, RateLimitConfig, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(147)))
+        )
+    );
+
+    // Reward leaderboard: (total claimed, wallet) -> claimed task count
+    pub static REWARD_LEADERBOARD: RefCell<StableBTreeMap<LeaderboardKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(148)))
+        )
+    );
+
+    // Event log for off-chain indexers: seq -> Event
+    pub static EVENTS: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(149)))
+        )
+    );
+
+    // Next event sequence number
+    pub static NEXT_EVENT_SEQ: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(150))),
+            1
+        ).unwrap()
+    );
+
+    // Epoch whose root is in the canister's certified data
+    pub static CERTIFIED_EPOCH: RefCell<StableCell<CertifiedEpoch, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(151))),
+            CertifiedEpoch::default()
+        ).unwrap()
+    );
+
+    // Principals allowed to call record_payment
+    pub static PAYMENT_OPERATORS: RefCell<StableBTreeMap<Principal, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(152)))
+        )
+    );
+
+    // ICRC-1 ledgers accepted for payments: ledger -> IcrcLedgerConfig
+    pub static ICRC_LEDGERS: RefCell<StableBTreeMap<Principal, IcrcLedgerConfig, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(153)))
+        )
+    );
+
+    // Recorded ICRC payment blocks: (ledger, block index) -> payment id
+    pub static ICRC_PAYMENT_BLOCKS: RefCell<StableBTreeMap<(Principal, u64), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(154)))
+        )
+    );
+
+    // Ledger and cap for paying rewards out on ICRC-1
+    pub static ICRC_PAYOUT_CONFIG: RefCell<StableCell<IcrcPayoutConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(155))),
+            IcrcPayoutConfig::default()
+        ).unwrap()
+    );
+
+    // ICRC-1 reward payouts: payout id -> IcrcPayoutRecord
+    pub static ICRC_PAYOUTS: RefCell<StableBTreeMap<u64, IcrcPayoutRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(156)))
+        )
+    );
+
+    // Solana RPC and bitmap layout for syncing on-chain claims
+    pub static CLAIM_SYNC_CONFIG: RefCell<StableCell<ClaimSyncConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(157))),
+            ClaimSyncConfig::default()
+        ).unwrap()
+    );
+
+    // Claimed bitmaps: epoch -> EpochClaimBitmap
+    pub static CLAIM_BITMAPS: RefCell<StableBTreeMap<u64, EpochClaimBitmap, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(158)))
+        )
+    );
+
+    // Epochs a wallet has a leaf in: WalletEpochKey -> () (secondary index of EPOCH_WALLET_INDEX)
+    pub static WALLET_EPOCHS: RefCell<StableBTreeMap<WalletEpochKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(159)))
+        )
+    );
+
+    // ===== AI Subscription Storage (Memory IDs: 130-135) =====
     pub static AI_SERVICES: RefCell<StableBTreeMap<String, ServiceType, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(130)))
@@ -332,4 +616,197 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(132)))
         )
     );
-} 
\ No newline at end of file
+    // Entitlement plan per payfor: payfor -> SubscriptionPlan
+    pub static SUBSCRIPTION_PLANS: RefCell<StableBTreeMap<String, SubscriptionPlan, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(133)))
+        )
+    );
+    // Current entitlement: principal_id -> Subscription
+    pub static SUBSCRIPTIONS: RefCell<StableBTreeMap<String, Subscription, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(134)))
+        )
+    );
+    // Payment wallet binding: wallet -> principal_id
+    pub static WALLET_PRINCIPALS: RefCell<StableBTreeMap<String, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(135)))
+        )
+    );
+
+    // ===== Epoch Webhook, Tier Multiplier and Liability Storage (Memory IDs: 136-138) =====
+    // URL notified when an epoch is built; empty when unset
+    pub static EPOCH_WEBHOOK_URL: RefCell<StableCell<String, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(136))),
+            String::new()
+        ).unwrap()
+    );
+    // Reward expression tier_mul per subscription tier: tier -> multiplier
+    pub static TIER_MULTIPLIERS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(137)))
+        )
+    );
+    // Running totals of pending, locked and claimed rewards over all wallets
+    pub static LIABILITY_SUMMARY: RefCell<StableCell<LiabilitySummary, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(138))),
+            LiabilitySummary::default()
+        ).unwrap()
+    );
+
+    // ===== Wallet Meta Storage (Memory IDs: 160-161) =====
+    // First and last interactions: wallet -> WalletMeta
+    pub static WALLET_META: RefCell<StableBTreeMap<String, WalletMeta, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(160)))
+        )
+    );
+    // Wallets by first interaction: first_seen_ts (next free ns on a tie) -> wallet
+    pub static WALLETS_BY_FIRST_SEEN: RefCell<StableBTreeMap<u64, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(161)))
+        )
+    );
+
+    // ===== Payment Wallet Index Storage (Memory IDs: 162-163) =====
+    // Payments by wallet: WalletPaymentKey -> () (secondary index of PAYMENTS)
+    pub static PAYMENTS_BY_WALLET: RefCell<StableBTreeMap<WalletPaymentKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(162)))
+        )
+    );
+    // Payment timeline: WalletPaymentTimeKey -> () (secondary index of PAYMENTS by wallet and time)
+    pub static PAYMENTS_BY_WALLET_TS: RefCell<StableBTreeMap<WalletPaymentTimeKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(163)))
+        )
+    );
+
+    // ===== Payment Archive Storage (Memory IDs: 164-166) =====
+    // Index the next payment gets; indices are never reused once a payment is archived
+    pub static NEXT_PAYMENT_INDEX: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(164))),
+            0
+        ).unwrap()
+    );
+    // Recorded tx_refs: tx_ref -> payment index (kept when the payment is archived)
+    pub static PAYMENT_TX_REFS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(165)))
+        )
+    );
+    // Archived payments waiting to be pulled: payment index -> PaymentRecord
+    pub static ARCHIVED_PAYMENTS: RefCell<StableBTreeMap<u64, PaymentRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(166)))
+        )
+    );
+
+    // ===== Wallet Suspension Storage (Memory ID: 167) =====
+    // Suspended wallets: wallet -> WalletSuspension (expired suspensions stay until lifted)
+    pub static SUSPENDED_WALLETS: RefCell<StableBTreeMap<String, WalletSuspension, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(167)))
+        )
+    );
+
+    // ===== Task Funnel Storage (Memory ID: 168) =====
+    // Wallets per task status: TaskFunnelKey -> count (zero counts are removed)
+    pub static TASK_FUNNELS: RefCell<StableBTreeMap<TaskFunnelKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(168)))
+        )
+    );
+
+    // ===== Epoch Root Override Storage (Memory ID: 169) =====
+    // Pending root overrides awaiting a second admin: epoch -> EpochRootOverride
+    pub static EPOCH_ROOT_OVERRIDES: RefCell<StableBTreeMap<u64, EpochRootOverride, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(169)))
+        )
+    );
+
+    // ===== Wallet Viewer Storage (Memory ID: 170) =====
+    // Principals allowed to list and count wallets
+    pub static WALLET_VIEWERS: RefCell<StableBTreeMap<Principal, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(170)))
+        )
+    );
+
+    // ===== Schema Version Storage (Memory ID: 171) =====
+    // Schema version of each structure's stored data and the migration in progress
+    pub static SCHEMA_VERSIONS: RefCell<StableCell<SchemaVersions, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(171))),
+            SchemaVersions::default()
+        ).unwrap()
+    );
+
+    // ===== Check-in Streak Storage (Memory ID: 172) =====
+    // Daily check-in streak of each wallet
+    pub static CHECKIN_STREAKS: RefCell<StableBTreeMap<String, CheckinStreak, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(172)))
+        )
+    );
+
+    // ===== Task Attestation Storage (Memory IDs: 173-174) =====
+    // Principals allowed to attest tasks
+    pub static TASK_VERIFIERS: RefCell<StableBTreeMap<Principal, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(173)))
+        )
+    );
+
+    // Latest evidence submission of each (taskid, wallet)
+    pub static EVIDENCE_SUBMISSIONS: RefCell<StableBTreeMap<VerificationKey, EvidenceSubmission, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(174)))
+        )
+    );
+
+    // ===== Reward Calculation Log Storage (Memory IDs: 175-176) =====
+    // How each booked reward was calculated, in booking order
+    pub static REWARD_CALCULATION_LOGS: RefCell<StableVec<RewardCalculationLog, Memory>> = RefCell::new(
+        StableVec::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(175)))
+        ).unwrap()
+    );
+
+    // Reward logs by wallet: WalletRewardLogKey -> () (secondary index of REWARD_CALCULATION_LOGS)
+    pub static REWARD_LOGS_BY_WALLET: RefCell<StableBTreeMap<WalletRewardLogKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(176)))
+        )
+    );
+
+    // ===== Epoch Clock Storage (Memory IDs: 177-178) =====
+    // Length of an epoch in nanoseconds
+    pub static EPOCH_DURATION_NS: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(177))),
+            DEFAULT_EPOCH_DURATION_NS
+        ).unwrap()
+    );
+
+    // Start of epoch 0 in nanoseconds since the Unix epoch (0 = not set)
+    pub static EPOCH_ZERO_TS: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(178))),
+            0
+        ).unwrap()
+    );
+
+    // ===== Refund Storage (Memory ID: 179) =====
+    // Recorded refunds: tx_ref -> RefundRecord (a tx_ref is applied at most once)
+    pub static REFUNDS: RefCell<StableBTreeMap<String, RefundRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(179)))
+        )
+    );
+}
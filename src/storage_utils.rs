@@ -0,0 +1,94 @@
+// Storage Utils - helpers shared by stable-structure scans
+//
+// Cursor pagination walks a StableBTreeMap with range() from just after the previous page's
+// last key, so a page costs O(log n + limit) instead of re-skipping every earlier entry.
+
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use std::ops::Bound;
+
+/// Up to `limit` entries after `cursor` (from the first key when None), plus the cursor for
+/// the next page: the last key returned, or None when no entries are left.
+pub fn paginate_btree<K, V, M>(map: &StableBTreeMap<K, V, M>, cursor: Option<K>, limit: u64) -> (Vec<(K, V)>, Option<K>)
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+    paginate_btree_from(map, start, limit, |_| true)
+}
+
+/// Like paginate_btree, starting at `start` and ending before the first key failing `within`.
+/// Used to page through one contiguous key prefix, e.g. the wallets of an epoch.
+pub fn paginate_btree_from<K, V, M>(
+    map: &StableBTreeMap<K, V, M>,
+    start: Bound<K>,
+    limit: u64,
+    within: impl Fn(&K) -> bool,
+) -> (Vec<(K, V)>, Option<K>)
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    let limit = limit as usize;
+    let mut page: Vec<(K, V)> = map.range((start, Bound::Unbounded))
+        .take_while(|(key, _)| within(key))
+        .take(limit.saturating_add(1))
+        .collect();
+    if page.len() <= limit {
+        return (page, None);
+    }
+    page.truncate(limit);
+    let next = page.last().map(|(key, _)| key.clone());
+    (page, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    #[test]
+    fn test_pagination_has_no_duplicates_or_gaps() {
+        let mut map: StableBTreeMap<u64, u64, DefaultMemoryImpl> = StableBTreeMap::new(DefaultMemoryImpl::default());
+        // 10,000 scattered keys from a linear congruential generator
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        while map.len() < 10_000 {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            map.insert(seed >> 16, seed);
+        }
+        let expected: Vec<u64> = map.iter().map(|(key, _)| key).collect();
+
+        for page_size in [1u64, 3, 64, 997, 9_999, 10_000, 25_000] {
+            let mut seen = Vec::with_capacity(expected.len());
+            let mut cursor = None;
+            loop {
+                let (page, next) = paginate_btree(&map, cursor, page_size);
+                assert!(page.len() as u64 <= page_size);
+                seen.extend(page.into_iter().map(|(key, _)| key));
+                match next {
+                    Some(key) => cursor = Some(key),
+                    None => break,
+                }
+            }
+            assert_eq!(seen, expected, "page size {}", page_size);
+        }
+
+        assert_eq!(paginate_btree(&map, None, 0), (Vec::new(), None));
+    }
+
+    #[test]
+    fn test_paginate_from_stops_at_prefix_end() {
+        let mut map: StableBTreeMap<u64, (), DefaultMemoryImpl> = StableBTreeMap::new(DefaultMemoryImpl::default());
+        for key in 0..30u64 {
+            map.insert(key, ());
+        }
+        let (page, next) = paginate_btree_from(&map, Bound::Included(10), 5, |key| *key < 20);
+        assert_eq!(page.iter().map(|(key, _)| *key).collect::<Vec<_>>(), vec![10, 11, 12, 13, 14]);
+        assert_eq!(next, Some(14));
+        let (page, next) = paginate_btree_from(&map, Bound::Excluded(14), 5, |key| *key < 20);
+        assert_eq!(page.len(), 5);
+        assert_eq!(next, None);
+    }
+}
@@ -0,0 +1,390 @@
+// Attestations - tasks completed by an external verifier instead of complete_task
+//
+// A contract item flagged requires_attestation is refused by complete_task. The wallet's
+// owner calls submit_task_evidence, which stores the evidence and moves the task to
+// PendingVerification. A registered verifier (managed like payment operators) pulls pending
+// submissions with list_pending_verifications and calls attest_task: approved completes the
+// task as complete_task would, rejected moves it back to InProgress with the verifier's note
+// kept on the submission. Rejected evidence can be resubmitted until MAX_EVIDENCE_SUBMISSIONS
+// submissions were made for the task.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{
+    funnel, normalize_wallet, not_started_detail, notifications, record_completion, require_task_unpaused, suspensions,
+    validation::validate_taskid, TaskContractItem, TaskStatus, UserTaskDetail,
+};
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::{EVIDENCE_SUBMISSIONS, TASK_CONTRACT, TASK_VERIFIERS, USER_TASKS};
+
+/// Submissions of evidence allowed per (wallet, task), the first one included
+pub const MAX_EVIDENCE_SUBMISSIONS: u32 = 3;
+
+/// Longest evidence accepted, in characters
+pub const MAX_EVIDENCE_LEN: usize = 1_024;
+
+/// Longest verifier note accepted, in characters
+pub const MAX_ATTESTATION_NOTE_LEN: usize = 280;
+
+/// Most submissions returned by one list_pending_verifications call
+pub const MAX_VERIFICATION_PAGE: u64 = 200;
+
+/// Key of a submission. Ordered by taskid first, so the submissions of a task are contiguous.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VerificationKey {
+    pub taskid: String,
+    pub wallet: String,
+}
+
+impl Storable for VerificationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize VerificationKey");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize VerificationKey")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum VerificationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Latest evidence a wallet submitted for a task and what the verifier made of it
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct EvidenceSubmission {
+    pub wallet: String,
+    pub taskid: String,
+    pub evidence: String,
+    pub submitted_at: u64,
+    pub submissions: u32,  // Submissions made for the task so far, this one included
+    pub status: VerificationStatus,
+    pub note: Option<String>,        // Verifier's note, e.g. why the evidence was rejected
+    pub attested_by: Option<String>,
+    pub attested_at: Option<u64>,
+}
+
+impl Storable for EvidenceSubmission {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize EvidenceSubmission");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize EvidenceSubmission")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Why a contract item cannot require attestation; empty for other items. Daily check-ins
+/// are refused by checkin_item_errors.
+pub(crate) fn attestation_item_errors(item: &TaskContractItem) -> Vec<String> {
+    if !item.requires_attestation {
+        return Vec::new();
+    }
+    // Payments auto-complete payfor tasks and gates are checked by complete_task only
+    [("payfor", item.payfor.is_some()), ("gate", item.gate.is_some())]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(field, _)| format!("Task {} requires attestation and cannot have {}", item.taskid, field))
+        .collect()
+}
+
+/// Allow a principal to attest tasks (admin only)
+pub fn add_task_verifier(verifier: Principal) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can add task verifiers".to_string());
+    }
+    TASK_VERIFIERS.with(|store| store.borrow_mut().insert(verifier, ()));
+    Ok(())
+}
+
+/// Revoke a task verifier (admin only)
+pub fn remove_task_verifier(verifier: Principal) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can remove task verifiers".to_string());
+    }
+    TASK_VERIFIERS.with(|store| store.borrow_mut().remove(&verifier))
+        .map(|_| ())
+        .ok_or_else(|| format!("{} is not a task verifier", verifier))
+}
+
+/// List task verifiers (admin only)
+pub fn list_task_verifiers() -> Result<Vec<Principal>, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can list task verifiers".to_string());
+    }
+    Ok(TASK_VERIFIERS.with(|store| store.borrow().iter().map(|(verifier, _)| verifier).collect()))
+}
+
+fn require_verifier(action: &str) -> Result<Principal, String> {
+    let caller = crate::env::caller();
+    if crate::env::is_controller(&caller) || TASK_VERIFIERS.with(|store| store.borrow().contains_key(&caller)) {
+        return Ok(caller);
+    }
+    Err(format!("NotAuthorized: only task verifiers can {}", action))
+}
+
+fn validate_text(label: &str, text: &str, max_len: usize) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err(format!("{} cannot be empty", label));
+    }
+    if text.chars().count() > max_len {
+        return Err(format!("{} exceeds {} characters", label, max_len));
+    }
+    Ok(())
+}
+
+/// Submission after `previous` (None for the first), or why no more can be made
+fn next_submission(
+    previous: Option<EvidenceSubmission>,
+    wallet: &str,
+    taskid: &str,
+    evidence: String,
+    now: u64,
+) -> Result<EvidenceSubmission, String> {
+    let submissions = match &previous {
+        Some(previous) if previous.status == VerificationStatus::Pending => {
+            return Err(format!("Evidence for task {} is already waiting for verification", taskid));
+        }
+        Some(previous) if previous.submissions >= MAX_EVIDENCE_SUBMISSIONS => {
+            return Err(format!("Evidence for task {} was submitted {} times; no more submissions are accepted", taskid, previous.submissions));
+        }
+        Some(previous) => previous.submissions + 1,
+        None => 1,
+    };
+    Ok(EvidenceSubmission {
+        wallet: wallet.to_string(),
+        taskid: taskid.to_string(),
+        evidence,
+        submitted_at: now,
+        submissions,
+        status: VerificationStatus::Pending,
+        note: None,
+        attested_by: None,
+        attested_at: None,
+    })
+}
+
+/// Move an open task to PendingVerification with the submitted evidence
+fn open_verification(tasks: &mut Vec<UserTaskDetail>, item: &TaskContractItem, evidence: &str) -> Result<(), String> {
+    if !tasks.iter().any(|t| t.taskid == item.taskid) {
+        tasks.push(not_started_detail(item));
+    }
+    let task = tasks.iter_mut()
+        .find(|t| t.taskid == item.taskid && matches!(t.status, TaskStatus::NotStarted | TaskStatus::InProgress))
+        .ok_or_else(|| format!("Task {} not found or already completed for wallet", item.taskid))?;
+    task.status = TaskStatus::PendingVerification;
+    task.evidence = Some(evidence.to_string());
+    Ok(())
+}
+
+/// Submit evidence of a task that requires attestation, for a verifier to check (controller
+/// or the principal bound to the wallet)
+pub fn submit_task_evidence(wallet: String, taskid: String, evidence: String) -> Result<(), String> {
+    let wallet = normalize_wallet(&wallet)?;
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller)
+        && crate::ai_sub_service::get_wallet_principal(&wallet) != Some(caller.to_text())
+    {
+        return Err(format!("NotAuthorized: wallet {} is not bound to {}", wallet, caller));
+    }
+    suspensions::require_wallet_active(&wallet)?;
+    crate::rate_limit::check_rate_limit("complete_task", &wallet)?;
+    validate_taskid(&taskid)?;
+    validate_text("Evidence", &evidence, MAX_EVIDENCE_LEN)?;
+
+    let item = TASK_CONTRACT.with(|store| store.borrow().get(&taskid))
+        .ok_or_else(|| format!("Task {} not found in contract", taskid))?;
+    if !item.requires_attestation {
+        return Err(format!("Task {} does not take evidence for verification; use complete_task", taskid));
+    }
+    require_task_unpaused(&item)?;
+    let now = crate::env::time();
+    if !super::is_task_active(&item, now) {
+        return Err(format!("Task {} is not active", taskid));
+    }
+
+    let key = VerificationKey { taskid: taskid.clone(), wallet: wallet.clone() };
+    let submission = next_submission(EVIDENCE_SUBMISSIONS.with(|store| store.borrow().get(&key)), &wallet, &taskid, evidence, now)?;
+
+    if !USER_TASKS.with(|store| store.borrow().contains_key(&wallet)) {
+        super::get_or_init_user_tasks(wallet.clone());
+    }
+    USER_TASKS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut state = map.get(&wallet)
+            .ok_or_else(|| format!("User state not found for wallet {}", wallet))?;
+        let funnel_before = funnel::task_statuses(&state.tasks);
+        notifications::transition_task_status(&wallet, &mut state.tasks, now, |tasks| {
+            open_verification(tasks, &item, &submission.evidence)
+        })?;
+        funnel::update_funnel(&wallet, &funnel_before, &state.tasks);
+        map.insert(wallet.clone(), state);
+        Ok::<(), String>(())
+    })?;
+
+    crate::env::println!("Evidence {} of {} submitted for task {} of wallet {}", submission.submissions, MAX_EVIDENCE_SUBMISSIONS, taskid, wallet);
+    EVIDENCE_SUBMISSIONS.with(|store| store.borrow_mut().insert(key, submission));
+    event_log::emit(EventKind::EvidenceSubmitted { wallet, taskid });
+    Ok(())
+}
+
+/// Approve or reject pending evidence (task verifiers and controllers). Approved completes
+/// the task as complete_task would; rejected moves it back to InProgress, keeping `note`.
+pub fn attest_task(wallet: String, taskid: String, approved: bool, note: Option<String>) -> Result<(), String> {
+    let verifier = require_verifier("attest tasks")?;
+    let wallet = normalize_wallet(&wallet)?;
+    if let Some(note) = &note {
+        validate_text("Note", note, MAX_ATTESTATION_NOTE_LEN)?;
+    }
+    let key = VerificationKey { taskid: taskid.clone(), wallet: wallet.clone() };
+    let mut submission = EVIDENCE_SUBMISSIONS.with(|store| store.borrow().get(&key))
+        .filter(|submission| submission.status == VerificationStatus::Pending)
+        .ok_or_else(|| format!("No evidence of wallet {} is waiting for verification of task {}", wallet, taskid))?;
+    let now = crate::env::time();
+
+    if approved {
+        let item = TASK_CONTRACT.with(|store| store.borrow().get(&taskid))
+            .ok_or_else(|| format!("Task {} not found in contract", taskid))?;
+        require_task_unpaused(&item)?;
+        record_completion(wallet.clone(), item, Some(submission.evidence.clone()), now, true)?;
+    } else {
+        super::disputes::update_task(&wallet, &taskid, |task| {
+            if task.status != TaskStatus::PendingVerification {
+                return Err(format!("Task {} is {:?}, not waiting for verification", taskid, task.status));
+            }
+            task.status = TaskStatus::InProgress;
+            task.evidence = None;
+            Ok(())
+        })?;
+    }
+
+    submission.status = if approved { VerificationStatus::Approved } else { VerificationStatus::Rejected };
+    submission.note = note;
+    submission.attested_by = Some(verifier.to_text());
+    submission.attested_at = Some(now);
+    EVIDENCE_SUBMISSIONS.with(|store| store.borrow_mut().insert(key, submission));
+    crate::env::println!("Task {} of wallet {} attested by {} (approved: {})", taskid, wallet, verifier, approved);
+    event_log::emit(EventKind::TaskAttested { wallet, taskid, approved, attested_by: verifier.to_text() });
+    Ok(())
+}
+
+/// Pending submissions of a task in wallet order, skipping the first `offset` of them
+/// (task verifiers and controllers)
+pub fn list_pending_verifications(taskid: String, offset: u64, limit: u64) -> Result<Vec<EvidenceSubmission>, String> {
+    require_verifier("list pending verifications")?;
+    let start = VerificationKey { taskid: taskid.clone(), wallet: String::new() };
+    Ok(EVIDENCE_SUBMISSIONS.with(|store| {
+        store.borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.taskid == taskid)
+            .map(|(_, submission)| submission)
+            .filter(|submission| submission.status == VerificationStatus::Pending)
+            .skip(offset as usize)
+            .take(limit.min(MAX_VERIFICATION_PAGE) as usize)
+            .collect()
+    }))
+}
+
+/// Latest submission of a wallet for a task, if it made any
+pub fn get_evidence_submission(wallet: String, taskid: String) -> Option<EvidenceSubmission> {
+    let wallet = normalize_wallet(&wallet).ok()?;
+    EVIDENCE_SUBMISSIONS.with(|store| store.borrow().get(&VerificationKey { taskid, wallet }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::TestEnvironment;
+    use crate::stable_mem_storage::WALLET_PRINCIPALS;
+    use crate::task_rewards::{init_task_contract, internal_complete_task, liability, validate_task_contract_items};
+
+    fn item(taskid: &str) -> TaskContractItem {
+        TaskContractItem {
+            taskid: taskid.to_string(),
+            reward: 100,
+            payfor: None,
+            display_order: 0,
+            vesting_cliff_ns: None,
+            cooldown_secs: None,
+            referral_bonus: None,
+            gate: None,
+            active_from: None,
+            active_until: None,
+            campaign_id: None,
+            reward_expr: None,
+            reward_policy: None,
+            reward_tiers: Vec::new(),
+            daily_checkin: false,
+            paused: false,
+            requires_attestation: true,
+        }
+    }
+
+    fn status(wallet: &str) -> TaskStatus {
+        USER_TASKS.with(|store| store.borrow().get(&wallet.to_string())).unwrap()
+            .tasks.into_iter().find(|t| t.taskid == "follow").unwrap().status
+    }
+
+    #[test]
+    fn test_submit_reject_resubmit_and_approve() {
+        let admin = Principal::from_slice(&[0xad]);
+        let owner = Principal::from_slice(&[1]);
+        let verifier = Principal::from_slice(&[2]);
+        let env = TestEnvironment::install(admin, 1_000);
+        let mut paid = item("paid");
+        paid.payfor = Some("ai_subscription".to_string());
+        assert!(validate_task_contract_items(&[paid]).unwrap_err().contains("cannot have payfor"));
+        init_task_contract(vec![item("follow")]).unwrap();
+        let (rejected, approved) = (bs58::encode([1u8; 32]).into_string(), bs58::encode([2u8; 32]).into_string());
+        for wallet in [&rejected, &approved] {
+            WALLET_PRINCIPALS.with(|store| store.borrow_mut().insert(wallet.clone(), owner.to_text()));
+        }
+        assert!(internal_complete_task(rejected.clone(), "follow".to_string(), None, 1_000).unwrap_err().contains("attestation"));
+
+        env.set_caller(owner);
+        let submit = |wallet: &String| submit_task_evidence(wallet.clone(), "follow".to_string(), "https://x.com/u".to_string());
+        submit(&rejected).unwrap();
+        assert!(submit(&rejected).unwrap_err().contains("already waiting"));
+        assert_eq!(status(&rejected), TaskStatus::PendingVerification);
+        assert!(attest_task(rejected.clone(), "follow".to_string(), true, None).unwrap_err().starts_with("NotAuthorized"));
+
+        env.set_caller(admin);
+        add_task_verifier(verifier).unwrap();
+        env.set_caller(verifier);
+        for submissions in 1..=MAX_EVIDENCE_SUBMISSIONS {
+            if submissions > 1 {
+                env.set_caller(owner);
+                submit(&rejected).unwrap();
+                env.set_caller(verifier);
+            }
+            assert_eq!(list_pending_verifications("follow".to_string(), 0, 10).unwrap().len(), 1);
+            attest_task(rejected.clone(), "follow".to_string(), false, Some("No follow found".to_string())).unwrap();
+            assert_eq!(status(&rejected), TaskStatus::InProgress);
+        }
+        let submission = get_evidence_submission(rejected.clone(), "follow".to_string()).unwrap();
+        assert_eq!((submission.submissions, submission.note.as_deref()), (MAX_EVIDENCE_SUBMISSIONS, Some("No follow found")));
+        env.set_caller(owner);
+        assert!(submit(&rejected).unwrap_err().contains("no more submissions"));
+
+        submit(&approved).unwrap();
+        env.set_caller(verifier);
+        assert!(list_pending_verifications("follow".to_string(), 1, 10).unwrap().is_empty());
+        attest_task(approved.clone(), "follow".to_string(), true, None).unwrap();
+        assert_eq!(status(&approved), TaskStatus::Completed);
+        assert_eq!(liability::get_liability_summary().pending, 100);
+        assert!(attest_task(approved, "follow".to_string(), true, None).is_err());
+        assert!(list_pending_verifications("follow".to_string(), 0, 10).unwrap().is_empty());
+    }
+}
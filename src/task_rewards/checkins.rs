@@ -0,0 +1,318 @@
+// Daily check-ins - a contract task paid once per UTC day, with a bonus for consecutive days
+//
+// The contract item flagged daily_checkin is completed through check_in, never complete_task.
+// A check-in pays the task's reward times the streak multiplier: +STREAK_BONUS_BPS for every
+// consecutive day before today, capped at MAX_STREAK_MULTIPLIER_BPS. Missing a UTC day starts
+// the streak over at 1.
+//
+// Rewards accumulate in the wallet's open check-in bucket, a Completed task the next epoch
+// snapshot picks up like any other. The first bucket is the contract task itself; once a
+// bucket went into an epoch, the next check-in opens a new task "<taskid>-d<day>", the way
+// referral rewards get a task per referee.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{
+    funnel, get_or_init_user_tasks, is_task_active, liability, normalize_wallet, not_started_detail, notifications,
+    require_task_unpaused, reward_logs::{self, RewardCalculationLog}, suspensions, task_reward,
+    validation::MAX_TASKID_LEN, TaskContractItem, TaskStatus, UserTaskDetail, MAX_SINGLE_REWARD,
+};
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::{CHECKIN_STREAKS, TASK_CONTRACT, USER_TASKS};
+
+pub const DAY_NS: u64 = 86_400_000_000_000;
+
+/// Multiplier added per consecutive day, in basis points of the base reward
+pub const STREAK_BONUS_BPS: u64 = 1_000;
+
+/// Highest streak multiplier, in basis points (2x)
+pub const MAX_STREAK_MULTIPLIER_BPS: u64 = 20_000;
+
+const BPS: u64 = 10_000;
+
+/// Longest taskid of a check-in task, leaving room for the "-d<day>" of its buckets
+pub const MAX_CHECKIN_TASKID_LEN: usize = MAX_TASKID_LEN - 10;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct CheckinStreak {
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub last_checkin_day: u64,  // UTC day (time / DAY_NS) of the latest check-in
+    pub total_checkins: u64,
+}
+
+impl Storable for CheckinStreak {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize CheckinStreak");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize CheckinStreak")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A wallet's streak as the UI shows it
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct StreakView {
+    pub wallet: String,
+    pub current_streak: u32,  // 0 once a day was missed
+    pub longest_streak: u32,
+    pub total_checkins: u64,
+    pub checked_in_today: bool,
+    pub next_multiplier_bps: u64,  // What the next check-in earns if the streak holds until then
+}
+
+/// What one check-in booked
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CheckinReceipt {
+    pub taskid: String,  // Bucket the reward went into
+    pub streak: u32,
+    pub multiplier_bps: u64,
+    pub reward: u64,
+    pub bucket_total: u64,
+}
+
+fn utc_day(ns: u64) -> u64 {
+    ns / DAY_NS
+}
+
+pub fn streak_multiplier_bps(streak: u32) -> u64 {
+    (BPS + STREAK_BONUS_BPS * streak.saturating_sub(1) as u64).min(MAX_STREAK_MULTIPLIER_BPS)
+}
+
+fn streak_reward(base: u64, streak: u32) -> u64 {
+    (base as u128 * streak_multiplier_bps(streak) as u128 / BPS as u128) as u64
+}
+
+/// Whether the streak still counts today: the latest check-in was today or yesterday
+fn streak_alive(streak: &CheckinStreak, today: u64) -> bool {
+    streak.total_checkins > 0 && streak.last_checkin_day + 1 >= today
+}
+
+/// Streak a check-in at `now` reaches
+fn next_streak(streak: &CheckinStreak, now: u64) -> Result<u32, String> {
+    let today = utc_day(now);
+    if streak.total_checkins > 0 && streak.last_checkin_day == today {
+        let retry_after_secs = ((today + 1) * DAY_NS - now).div_ceil(1_000_000_000);
+        return Err(format!("AlreadyCheckedIn {{ retry_after_secs: {} }}", retry_after_secs));
+    }
+    Ok(if streak_alive(streak, today) { streak.current_streak + 1 } else { 1 })
+}
+
+pub(crate) fn bucket_taskid(taskid: &str, day: u64) -> String {
+    format!("{}-d{}", taskid, day)
+}
+
+/// Contract taskid of a check-in bucket opened after the first one
+pub(crate) fn bucket_base(taskid: &str) -> Option<&str> {
+    let (base, day) = taskid.rsplit_once("-d")?;
+    (!day.is_empty() && day.bytes().all(|b| b.is_ascii_digit())).then_some(base)
+}
+
+/// Why a contract item cannot be a daily check-in; empty for other items
+pub(crate) fn checkin_item_errors(item: &TaskContractItem) -> Vec<String> {
+    if !item.daily_checkin {
+        return Vec::new();
+    }
+    let mut errors = Vec::new();
+    if item.taskid.len() > MAX_CHECKIN_TASKID_LEN {
+        errors.push(format!("Daily check-in task {} taskid is longer than {} bytes", item.taskid, MAX_CHECKIN_TASKID_LEN));
+    }
+    let unsupported = [
+        ("payfor", item.payfor.is_some()),
+        ("vesting_cliff_ns", item.vesting_cliff_ns.is_some()),
+        ("cooldown_secs", item.cooldown_secs.is_some()),
+        ("referral_bonus", item.referral_bonus.is_some()),
+        ("gate", item.gate.is_some()),
+        ("campaign_id", item.campaign_id.is_some()),
+        ("reward_tiers", !item.reward_tiers.is_empty()),
+        ("requires_attestation", item.requires_attestation),
+    ];
+    for (field, set) in unsupported {
+        if set {
+            errors.push(format!("Daily check-in task {} cannot have {}", item.taskid, field));
+        }
+    }
+    errors
+}
+
+/// The contract's daily check-in task
+fn checkin_item() -> Result<TaskContractItem, String> {
+    TASK_CONTRACT.with(|store| store.borrow().iter().map(|(_, item)| item).find(|item| item.daily_checkin))
+        .ok_or_else(|| "No daily check-in task in the contract".to_string())
+}
+
+/// Add `reward` to the wallet's open bucket of `item`, opening one for `day` if every bucket
+/// already went into an epoch; returns the bucket's taskid and total
+fn book_checkin(tasks: &mut Vec<UserTaskDetail>, item: &TaskContractItem, reward: u64, day: u64, ts: u64, now: u64) -> (String, u64) {
+    if !tasks.iter().any(|t| t.taskid == item.taskid) {
+        tasks.push(not_started_detail(item));
+    }
+    let open = tasks.iter().position(|t| {
+        (t.taskid == item.taskid || bucket_base(&t.taskid) == Some(item.taskid.as_str()))
+            && matches!(t.status, TaskStatus::NotStarted | TaskStatus::InProgress | TaskStatus::Completed)
+    });
+    let task = match open {
+        Some(i) => &mut tasks[i],
+        None => {
+            tasks.push(UserTaskDetail { taskid: bucket_taskid(&item.taskid, day), ..not_started_detail(item) });
+            tasks.last_mut().expect("bucket just pushed")
+        }
+    };
+    if task.status == TaskStatus::Completed {
+        task.reward_amount = task.reward_amount.saturating_add(reward);
+    } else {
+        task.status = TaskStatus::Completed;
+        task.reward_amount = reward;
+        task.completed_at = ts;
+    }
+    task.last_completed_at = now;
+    (task.taskid.clone(), task.reward_amount)
+}
+
+/// Check in for today: books the streak-adjusted reward of the daily check-in task
+pub fn check_in(wallet: String) -> Result<CheckinReceipt, String> {
+    let wallet = normalize_wallet(&wallet)?;
+    suspensions::require_wallet_active(&wallet)?;
+    crate::rate_limit::check_rate_limit("check_in", &wallet)?;
+    let item = checkin_item()?;
+    let now = crate::env::time();
+    if !is_task_active(&item, now) {
+        return Err(format!("Task {} is not active", item.taskid));
+    }
+    require_task_unpaused(&item)?;
+
+    let mut streak = CHECKIN_STREAKS.with(|store| store.borrow().get(&wallet)).unwrap_or_default();
+    let current = next_streak(&streak, now)?;
+    let base_reward = task_reward(&item, &wallet)?;
+    let reward = streak_reward(base_reward, current).min(MAX_SINGLE_REWARD);
+    let day = utc_day(now);
+
+    if !USER_TASKS.with(|store| store.borrow().contains_key(&wallet)) {
+        get_or_init_user_tasks(wallet.clone());
+    }
+    let (taskid, bucket_total) = USER_TASKS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut state = map.get(&wallet)
+            .ok_or_else(|| format!("User state not found for wallet {}", wallet))?;
+        let liability_before = liability::liability_totals(&state.tasks);
+        let funnel_before = funnel::task_statuses(&state.tasks);
+        let booked = notifications::transition_task_status(&wallet, &mut state.tasks, now, |tasks| {
+            book_checkin(tasks, &item, reward, day, now, now)
+        });
+        state.refresh_totals();
+        liability::update_liability(&wallet, liability_before, liability::liability_totals(&state.tasks));
+        funnel::update_funnel(&wallet, &funnel_before, &state.tasks);
+        map.insert(wallet.clone(), state);
+        Ok::<_, String>(booked)
+    })?;
+
+    reward_logs::record(&RewardCalculationLog {
+        base_reward,
+        multiplier_bps: streak_multiplier_bps(current).min(u16::MAX as u64) as u16,
+        formula_result: item.reward_expr.is_some().then_some(base_reward),
+        ..RewardCalculationLog::flat(&wallet, &taskid, reward, now)
+    });
+
+    streak.current_streak = current;
+    streak.longest_streak = streak.longest_streak.max(current);
+    streak.last_checkin_day = day;
+    streak.total_checkins += 1;
+    CHECKIN_STREAKS.with(|store| store.borrow_mut().insert(wallet.clone(), streak));
+
+    crate::env::println!("Check-in of wallet {}: streak {}, reward {} into {}", wallet, current, reward, taskid);
+    event_log::emit(EventKind::TaskCompleted { wallet, taskid: taskid.clone() });
+    Ok(CheckinReceipt { taskid, streak: current, multiplier_bps: streak_multiplier_bps(current), reward, bucket_total })
+}
+
+/// A wallet's check-in streak; zeros for a wallet that never checked in
+pub fn get_streak(wallet: String) -> StreakView {
+    let wallet = normalize_wallet(&wallet).unwrap_or(wallet);
+    let streak = CHECKIN_STREAKS.with(|store| store.borrow().get(&wallet)).unwrap_or_default();
+    let today = utc_day(crate::env::time());
+    let alive = streak_alive(&streak, today);
+    let current_streak = if alive { streak.current_streak } else { 0 };
+    StreakView {
+        wallet,
+        current_streak,
+        longest_streak: streak.longest_streak,
+        total_checkins: streak.total_checkins,
+        checked_in_today: alive && streak.last_checkin_day == today,
+        next_multiplier_bps: streak_multiplier_bps(current_streak + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::TestEnvironment;
+    use crate::task_rewards::{build_epoch_snapshot, BuildEpochOptions, ChainTarget};
+    use candid::Principal;
+
+    fn checkin_task() -> TaskContractItem {
+        TaskContractItem {
+            taskid: "checkin".to_string(),
+            reward: 100,
+            payfor: None,
+            display_order: 0,
+            vesting_cliff_ns: None,
+            cooldown_secs: None,
+            referral_bonus: None,
+            gate: None,
+            active_from: None,
+            active_until: None,
+            campaign_id: None,
+            reward_expr: None,
+            reward_policy: None,
+            reward_tiers: Vec::new(),
+            daily_checkin: true,
+            paused: false,
+            requires_attestation: false,
+        }
+    }
+
+    #[test]
+    fn test_streak_bonus_and_buckets_flow_into_epochs() {
+        let env = TestEnvironment::install(Principal::from_slice(&[1]), 20_000 * DAY_NS + 5);
+        let wallet = bs58::encode([7u8; 32]).into_string();
+        assert!(check_in(wallet.clone()).is_err());
+        TASK_CONTRACT.with(|store| store.borrow_mut().insert("checkin".to_string(), checkin_task()));
+        assert!(super::super::internal_complete_task(wallet.clone(), "checkin".to_string(), None, env.now.get()).unwrap_err().contains("use check_in"));
+
+        // Three days in a row: 100, 110, 120 into the contract task
+        for (streak, reward) in [(1, 100), (2, 110), (3, 120)] {
+            let receipt = check_in(wallet.clone()).unwrap();
+            assert_eq!((receipt.taskid.as_str(), receipt.streak, receipt.reward), ("checkin", streak, reward));
+            assert!(check_in(wallet.clone()).unwrap_err().starts_with("AlreadyCheckedIn"));
+            env.advance(DAY_NS);
+        }
+        assert_eq!(get_streak(wallet.clone()).current_streak, 3);
+        assert_eq!(liability::get_liability_summary().pending, 330);
+
+        // The epoch takes the bucket; the next check-in opens a new one
+        let meta = build_epoch_snapshot(1, BuildEpochOptions::default(), ChainTarget::Solana, String::new(), bs58::encode([9u8; 32]).into_string(), None, None).unwrap();
+        assert_eq!(meta.total_reward_amount, 330);
+        let receipt = check_in(wallet.clone()).unwrap();
+        assert_eq!((receipt.taskid, receipt.reward, receipt.bucket_total), (bucket_taskid("checkin", 20_003), 130, 130));
+        let view = crate::task_rewards::get_user_task_state_ordered(wallet.clone());
+        assert!(view.tasks.iter().any(|t| t.taskid == "checkin-d20003" && !t.retired));
+
+        // A missed day starts over; the bonus is capped at 2x
+        env.advance(2 * DAY_NS);
+        let view = get_streak(wallet.clone());
+        assert_eq!((view.current_streak, view.longest_streak, view.next_multiplier_bps), (0, 4, 10_000));
+        assert_eq!(check_in(wallet.clone()).unwrap().bucket_total, 230);
+        assert_eq!(streak_multiplier_bps(11), 20_000);
+        assert_eq!(streak_multiplier_bps(40), 20_000);
+
+        assert_eq!(bucket_base("checkin-d20003"), Some("checkin"));
+        assert_eq!(bucket_base("checkin-daily"), None);
+        assert_eq!(checkin_item_errors(&TaskContractItem { cooldown_secs: Some(1), ..checkin_task() }).len(), 1);
+    }
+}
@@ -0,0 +1,180 @@
+// Disputes - users contesting a prepared, issued or claimed reward
+//
+// A disputed task keeps its status but no longer counts towards the reward leaderboard.
+// A controller resolves the dispute: approved clears the flag, rejected resets the task to
+// NotStarted so it has to be completed again.
+
+use super::{claimed_totals, normalize_wallet, update_leaderboard, TaskStatus, UserTaskDetail};
+use super::funnel::{task_statuses, update_funnel};
+use super::liability::{liability_totals, update_liability};
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::USER_TASKS;
+
+/// Longest dispute reason accepted, in characters
+pub const MAX_DISPUTE_REASON_LEN: usize = 280;
+
+fn validate_dispute_reason(reason: &str) -> Result<(), String> {
+    if reason.trim().is_empty() {
+        return Err("Dispute reason cannot be empty".to_string());
+    }
+    if reason.chars().count() > MAX_DISPUTE_REASON_LEN {
+        return Err(format!("Dispute reason exceeds {} characters", MAX_DISPUTE_REASON_LEN));
+    }
+    Ok(())
+}
+
+fn open_dispute(task: &mut UserTaskDetail, reason: String) -> Result<(), String> {
+    if !matches!(task.status, TaskStatus::RewardPrepared | TaskStatus::TicketIssued | TaskStatus::Claimed) {
+        return Err(format!("Task {} is {:?}; only prepared, issued or claimed rewards can be disputed", task.taskid, task.status));
+    }
+    if task.disputed {
+        return Err(format!("Task {} is already disputed", task.taskid));
+    }
+    task.disputed = true;
+    task.dispute_reason = Some(reason);
+    Ok(())
+}
+
+fn close_dispute(task: &mut UserTaskDetail, approved: bool) -> Result<(), String> {
+    if !task.disputed {
+        return Err(format!("Task {} is not disputed", task.taskid));
+    }
+    task.disputed = false;
+    task.dispute_reason = None;
+    if !approved {
+        task.status = TaskStatus::NotStarted;
+        task.completed_at = 0;
+        task.evidence = None;
+    }
+    Ok(())
+}
+
+/// Apply `apply` to one task of a wallet, keeping totals, the leaderboard, the liability
+/// summary and the funnel counters in step
+pub(super) fn update_task(
+    wallet: &str,
+    taskid: &str,
+    apply: impl FnOnce(&mut UserTaskDetail) -> Result<(), String>,
+) -> Result<(), String> {
+    USER_TASKS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut state = map.get(&wallet.to_string())
+            .ok_or_else(|| format!("User state not found for wallet {}", wallet))?;
+        let claimed_before = claimed_totals(&state.tasks);
+        let liability_before = liability_totals(&state.tasks);
+        let funnel_before = task_statuses(&state.tasks);
+        super::notifications::transition_task_status(wallet, &mut state.tasks, crate::env::time(), |tasks| {
+            let task = tasks.iter_mut()
+                .find(|t| t.taskid == taskid)
+                .ok_or_else(|| format!("Task {} not found for wallet {}", taskid, wallet))?;
+            apply(task)
+        })?;
+        state.refresh_totals();
+        update_leaderboard(wallet, claimed_before, claimed_totals(&state.tasks));
+        update_liability(wallet, liability_before, liability_totals(&state.tasks));
+        update_funnel(wallet, &funnel_before, &state.tasks);
+        map.insert(wallet.to_string(), state);
+        Ok(())
+    })
+}
+
+/// Contest the reward of a RewardPrepared, TicketIssued or Claimed task (controller or the
+/// principal bound to the wallet)
+pub fn dispute_claim(wallet: String, taskid: String, reason: String) -> Result<(), String> {
+    let wallet = normalize_wallet(&wallet)?;
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller)
+        && crate::ai_sub_service::get_wallet_principal(&wallet) != Some(caller.to_text())
+    {
+        return Err(format!("NotAuthorized: wallet {} is not bound to {}", wallet, caller));
+    }
+    validate_dispute_reason(&reason)?;
+
+    update_task(&wallet, &taskid, |task| open_dispute(task, reason))?;
+    crate::env::println!("Task {} of wallet {} disputed", taskid, wallet);
+    event_log::emit(EventKind::ClaimDisputed { wallet, taskid });
+    Ok(())
+}
+
+/// Resolve a dispute (admin only): approved clears the flag, otherwise the task is reset to
+/// NotStarted
+pub fn resolve_dispute(wallet: String, taskid: String, approved: bool) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can resolve disputes".to_string());
+    }
+    let wallet = normalize_wallet(&wallet)?;
+
+    update_task(&wallet, &taskid, |task| close_dispute(task, approved))?;
+    crate::env::println!("Dispute on task {} of wallet {} resolved (approved: {})", taskid, wallet, approved);
+    event_log::emit(EventKind::DisputeResolved { wallet, taskid, approved });
+    Ok(())
+}
+
+/// Every (wallet, task) with an open dispute, in wallet order (admin only)
+pub fn list_disputed_tasks() -> Result<Vec<(String, UserTaskDetail)>, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can list disputed tasks".to_string());
+    }
+    Ok(USER_TASKS.with(|store| {
+        store.borrow()
+            .iter()
+            .flat_map(|(wallet, state)| {
+                state.tasks.into_iter()
+                    .filter(|t| t.disputed)
+                    .map(move |t| (wallet.clone(), t))
+            })
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(status: TaskStatus) -> UserTaskDetail {
+        UserTaskDetail {
+            taskid: "premium".to_string(),
+            status,
+            completed_at: 5,
+            reward_amount: 10,
+            evidence: Some("tx".to_string()),
+            last_completed_at: 5,
+            disputed: false,
+            dispute_reason: None,
+            campaign_id: None,
+            reward_tier: None,
+            retired: false,
+        }
+    }
+
+    #[test]
+    fn test_only_settled_rewards_can_be_disputed() {
+        assert!(open_dispute(&mut task(TaskStatus::Completed), "wrong".to_string()).is_err());
+        assert!(open_dispute(&mut task(TaskStatus::NotStarted), "wrong".to_string()).is_err());
+
+        let mut claimed = task(TaskStatus::Claimed);
+        open_dispute(&mut claimed, "wrong amount".to_string()).unwrap();
+        assert!(claimed.disputed);
+        assert_eq!(claimed.dispute_reason.as_deref(), Some("wrong amount"));
+        assert!(open_dispute(&mut claimed, "again".to_string()).is_err());
+        assert_eq!(claimed_totals(&[claimed]), (0, 0));
+
+        assert!(validate_dispute_reason("  ").is_err());
+        assert!(validate_dispute_reason(&"x".repeat(MAX_DISPUTE_REASON_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_resolution_clears_flag_or_resets_task() {
+        let mut approved = task(TaskStatus::TicketIssued);
+        assert!(close_dispute(&mut approved, true).is_err());
+        open_dispute(&mut approved, "proof fails".to_string()).unwrap();
+        close_dispute(&mut approved, true).unwrap();
+        assert_eq!((approved.status.clone(), approved.disputed, approved.dispute_reason.clone()), (TaskStatus::TicketIssued, false, None));
+
+        let mut rejected = task(TaskStatus::RewardPrepared);
+        open_dispute(&mut rejected, "not mine".to_string()).unwrap();
+        close_dispute(&mut rejected, false).unwrap();
+        assert_eq!((rejected.status.clone(), rejected.disputed, rejected.completed_at), (TaskStatus::NotStarted, false, 0));
+        assert_eq!(rejected.evidence, None);
+    }
+}
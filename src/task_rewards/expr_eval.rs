@@ -0,0 +1,186 @@
+// Expr Eval - integer expressions for dynamic task rewards (TaskContractItem::reward_expr)
+//
+// Grammar (whitespace ignored):
+//   expr   := term (('+' | '-') term)*
+//   term   := factor (('*' | '/') factor)*
+//   factor := integer | variable | ('min' | 'max') '(' expr ',' expr ')' | '(' expr ')'
+// Variables: base (the task's static reward), tier_mul (the wallet's tier multiplier) and
+// epoch (the latest epoch number). All arithmetic is on u64: overflow, a negative result,
+// division by zero, unknown names and bad syntax are errors. Division truncates.
+
+/// Longest expression accepted
+pub const MAX_EXPR_LEN: usize = 256;
+
+/// Deepest nesting of parentheses and min/max calls
+const MAX_EXPR_DEPTH: usize = 16;
+
+/// Values of the variables an expression may use
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExprVars {
+    pub base: u64,
+    pub tier_mul: u64,
+    pub epoch: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(u64),
+    Ident(String),
+    Op(char),  // + - * / ( ) ,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut value = 0u64;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                value = value.checked_mul(10)
+                    .and_then(|v| v.checked_add(digit as u64))
+                    .ok_or_else(|| "Integer literal overflows u64".to_string())?;
+                chars.next();
+            }
+            tokens.push(Token::Num(value));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(name));
+        } else if "+-*/(),".contains(c) {
+            tokens.push(Token::Op(c));
+            chars.next();
+        } else {
+            return Err(format!("Unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a ExprVars,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(c)) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<(), String> {
+        if self.peek_op() != Some(op) {
+            return Err(format!("Expected '{}' at token {}", op, self.pos + 1));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expr(&mut self, depth: usize) -> Result<u64, String> {
+        let mut value = self.term(depth)?;
+        while let Some(op @ ('+' | '-')) = self.peek_op() {
+            self.pos += 1;
+            let rhs = self.term(depth)?;
+            value = if op == '+' { value.checked_add(rhs) } else { value.checked_sub(rhs) }
+                .ok_or_else(|| format!("Integer overflow in {} {} {}", value, op, rhs))?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self, depth: usize) -> Result<u64, String> {
+        let mut value = self.factor(depth)?;
+        while let Some(op @ ('*' | '/')) = self.peek_op() {
+            self.pos += 1;
+            let rhs = self.factor(depth)?;
+            value = if op == '*' {
+                value.checked_mul(rhs).ok_or_else(|| format!("Integer overflow in {} * {}", value, rhs))?
+            } else {
+                value.checked_div(rhs).ok_or_else(|| "Division by zero".to_string())?
+            };
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self, depth: usize) -> Result<u64, String> {
+        if depth >= MAX_EXPR_DEPTH {
+            return Err(format!("Expression nested deeper than {}", MAX_EXPR_DEPTH));
+        }
+        let token = self.tokens.get(self.pos).cloned()
+            .ok_or_else(|| "Unexpected end of expression".to_string())?;
+        self.pos += 1;
+        match token {
+            Token::Num(value) => Ok(value),
+            Token::Op('(') => {
+                let value = self.expr(depth + 1)?;
+                self.expect_op(')')?;
+                Ok(value)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "base" => Ok(self.vars.base),
+                "tier_mul" => Ok(self.vars.tier_mul),
+                "epoch" => Ok(self.vars.epoch),
+                "min" | "max" => {
+                    self.expect_op('(')?;
+                    let a = self.expr(depth + 1)?;
+                    self.expect_op(',')?;
+                    let b = self.expr(depth + 1)?;
+                    self.expect_op(')')?;
+                    Ok(if name == "min" { a.min(b) } else { a.max(b) })
+                }
+                _ => Err(format!("Undefined variable {}", name)),
+            },
+            Token::Op(op) => Err(format!("Unexpected '{}' at token {}", op, self.pos)),
+        }
+    }
+}
+
+/// Evaluate `expr` with `vars`
+pub fn evaluate(expr: &str, vars: &ExprVars) -> Result<u64, String> {
+    if expr.len() > MAX_EXPR_LEN {
+        return Err(format!("Expression longer than {} bytes", MAX_EXPR_LEN));
+    }
+    let mut parser = Parser { tokens: tokenize(expr)?, pos: 0, vars };
+    let value = parser.expr(0)?;
+    if parser.pos != parser.tokens.len() {
+        return Err(format!("Unexpected trailing input at token {}", parser.pos + 1));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARS: ExprVars = ExprVars { base: 100, tier_mul: 3, epoch: 7 };
+
+    #[test]
+    fn test_evaluates_precedence_variables_and_calls() {
+        assert_eq!(evaluate("base * tier_mul", &VARS), Ok(300));
+        assert_eq!(evaluate("base + 2 * epoch - 4", &VARS), Ok(110));
+        assert_eq!(evaluate("(base + 2) * 2", &VARS), Ok(204));
+        assert_eq!(evaluate("base / 3", &VARS), Ok(33));
+        assert_eq!(evaluate("min(base * tier_mul, 250) + max(epoch, 10)", &VARS), Ok(260));
+        assert_eq!(evaluate(" 42 ", &VARS), Ok(42));
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        assert_eq!(evaluate("base / (epoch - 7)", &VARS), Err("Division by zero".to_string()));
+        assert!(evaluate("base - 101", &VARS).unwrap_err().contains("overflow"));
+        assert!(evaluate("18446744073709551615 * 2", &VARS).unwrap_err().contains("overflow"));
+        assert!(evaluate("99999999999999999999", &VARS).unwrap_err().contains("overflows"));
+        assert_eq!(evaluate("bonus + 1", &VARS), Err("Undefined variable bonus".to_string()));
+        for bad in ["", "base +", "(base", "base)", "min(base)", "base 2", "base % 2", "-base", "min base"] {
+            assert!(evaluate(bad, &VARS).is_err(), "{:?} should fail", bad);
+        }
+        assert!(evaluate(&format!("{}1{}", "(".repeat(MAX_EXPR_DEPTH), ")".repeat(MAX_EXPR_DEPTH)), &VARS).is_err());
+        assert!(evaluate(&"1+".repeat(MAX_EXPR_LEN), &VARS).is_err());
+    }
+}
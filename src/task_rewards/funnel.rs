@@ -0,0 +1,363 @@
+// Funnel - per-task counts of wallets in each task status, for completion funnels
+//
+// TASK_FUNNELS holds one counter per (taskid, status). Every write that changes a wallet's
+// tasks takes task_statuses of its tasks before and passes them with the tasks after to
+// update_funnel, the way the liability summary is kept; creating a state or adding a task to
+// stored states counts as a change from no tasks.
+//
+// rebuild_task_funnels recounts USER_TASKS in chunks of FUNNEL_RECOUNT_CHUNK wallets, one timer
+// per chunk. Changes to wallets the recount has already passed are applied to the recount too,
+// so the rebuilt counters are current when the last chunk replaces the stored ones. An upgrade
+// onto stored states without counters starts the same rebuild.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::{TaskStatus, UserTaskDetail};
+use crate::dry_run;
+use crate::stable_mem_storage::{TASK_FUNNELS, USER_TASKS};
+use crate::storage_utils::paginate_btree;
+
+/// Wallets read per recount step
+pub const FUNNEL_RECOUNT_CHUNK: u64 = 500;
+
+/// Key of one funnel counter
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskFunnelKey {
+    pub taskid: String,
+    pub status: TaskStatus,
+}
+
+impl Storable for TaskFunnelKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize TaskFunnelKey");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize TaskFunnelKey")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// How many wallets have a task in each status
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TaskFunnel {
+    pub taskid: String,
+    pub not_started: u64,
+    pub in_progress: u64,
+    pub completed: u64,
+    pub reward_prepared: u64,
+    pub ticket_issued: u64,
+    pub claimed: u64,
+    pub pending_verification: u64,
+}
+
+impl TaskFunnel {
+    fn count_mut(&mut self, status: &TaskStatus) -> &mut u64 {
+        match status {
+            TaskStatus::NotStarted => &mut self.not_started,
+            TaskStatus::InProgress => &mut self.in_progress,
+            TaskStatus::Completed => &mut self.completed,
+            TaskStatus::RewardPrepared => &mut self.reward_prepared,
+            TaskStatus::TicketIssued => &mut self.ticket_issued,
+            TaskStatus::Claimed => &mut self.claimed,
+            TaskStatus::PendingVerification => &mut self.pending_verification,
+        }
+    }
+}
+
+/// Recount in progress (heap only; an upgrade mid-recount drops it and the stored counters stay)
+struct Recount {
+    scanned_through: Option<String>,  // Last wallet counted; None before the first chunk
+    counts: BTreeMap<TaskFunnelKey, u64>,
+}
+
+thread_local! {
+    static RECOUNT: RefCell<Option<Recount>> = const { RefCell::new(None) };
+}
+
+/// Funnel counters one wallet's tasks contribute to
+pub(crate) fn task_statuses(tasks: &[UserTaskDetail]) -> Vec<TaskFunnelKey> {
+    tasks.iter()
+        .map(|task| TaskFunnelKey { taskid: task.taskid.clone(), status: task.status.clone() })
+        .collect()
+}
+
+/// Counter changes from a wallet's task statuses `before` a write to its tasks `after` it
+fn funnel_deltas(before: &[TaskFunnelKey], after: &[UserTaskDetail]) -> BTreeMap<TaskFunnelKey, i64> {
+    let mut deltas: BTreeMap<TaskFunnelKey, i64> = BTreeMap::new();
+    for key in before {
+        *deltas.entry(key.clone()).or_default() -= 1;
+    }
+    for key in task_statuses(after) {
+        *deltas.entry(key).or_default() += 1;
+    }
+    deltas.retain(|_, delta| *delta != 0);
+    deltas
+}
+
+fn apply_delta(count: u64, delta: i64) -> u64 {
+    if delta < 0 {
+        count.saturating_sub(delta.unsigned_abs())
+    } else {
+        count.saturating_add(delta as u64)
+    }
+}
+
+/// Move the counters from a wallet's task statuses `before` a write to its tasks `after` it
+pub(crate) fn update_funnel(wallet: &str, before: &[TaskFunnelKey], after: &[UserTaskDetail]) {
+    let deltas = funnel_deltas(before, after);
+    if deltas.is_empty() {
+        return;
+    }
+    TASK_FUNNELS.with(|store| {
+        let mut map = store.borrow_mut();
+        for (key, delta) in &deltas {
+            dry_run::shadow_entry(&TASK_FUNNELS, &map, key);
+            let count = apply_delta(map.get(key).unwrap_or(0), *delta);
+            if count == 0 {
+                map.remove(key);
+            } else {
+                map.insert(key.clone(), count);
+            }
+        }
+    });
+    // A dry run's change is undone by restoring the entries; the recount never sees it
+    if dry_run::is_active() {
+        return;
+    }
+    RECOUNT.with(|recount| {
+        if let Some(recount) = recount.borrow_mut().as_mut() {
+            if recount.scanned_through.as_deref().is_some_and(|last| wallet <= last) {
+                for (key, delta) in deltas {
+                    let count = recount.counts.entry(key).or_default();
+                    *count = apply_delta(*count, delta);
+                }
+            }
+        }
+    });
+}
+
+/// Wallets per status for one task; all zero for an unknown task
+pub fn get_task_funnel(taskid: String) -> TaskFunnel {
+    let start = TaskFunnelKey { taskid: taskid.clone(), status: TaskStatus::NotStarted };
+    let mut funnel = TaskFunnel { taskid: taskid.clone(), ..Default::default() };
+    TASK_FUNNELS.with(|store| {
+        for (key, count) in store.borrow().range(start..).take_while(|(key, _)| key.taskid == taskid) {
+            *funnel.count_mut(&key.status) = count;
+        }
+    });
+    funnel
+}
+
+/// Funnel of every task any wallet has, in taskid order
+pub fn get_all_task_funnels() -> Vec<TaskFunnel> {
+    let mut funnels: Vec<TaskFunnel> = Vec::new();
+    TASK_FUNNELS.with(|store| {
+        for (key, count) in store.borrow().iter() {
+            if funnels.last().is_none_or(|funnel| funnel.taskid != key.taskid) {
+                funnels.push(TaskFunnel { taskid: key.taskid.clone(), ..Default::default() });
+            }
+            if let Some(funnel) = funnels.last_mut() {
+                *funnel.count_mut(&key.status) = count;
+            }
+        }
+    });
+    funnels
+}
+
+/// Rebuild the counters from every wallet's tasks, one chunk per timer (admin only)
+pub fn rebuild_task_funnels() -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can rebuild task funnels".to_string());
+    }
+    start_recount()?;
+    schedule_recount_step();
+    Ok(())
+}
+
+/// Count the funnels of states stored before the counters existed (called from post_upgrade)
+pub(crate) fn rebuild_if_empty() {
+    let empty = TASK_FUNNELS.with(|store| store.borrow().is_empty());
+    if empty && !USER_TASKS.with(|store| store.borrow().is_empty()) && start_recount().is_ok() {
+        schedule_recount_step();
+    }
+}
+
+fn start_recount() -> Result<(), String> {
+    RECOUNT.with(|recount| {
+        let mut recount = recount.borrow_mut();
+        if recount.is_some() {
+            return Err("Task funnel rebuild already in progress".to_string());
+        }
+        *recount = Some(Recount { scanned_through: None, counts: BTreeMap::new() });
+        Ok(())
+    })
+}
+
+fn schedule_recount_step() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if recount_step(FUNNEL_RECOUNT_CHUNK) {
+            crate::env::println!("Task funnels rebuilt");
+        } else {
+            schedule_recount_step();
+        }
+    });
+}
+
+/// Count the next `limit` wallets; true once the recount finished and replaced the counters
+fn recount_step(limit: u64) -> bool {
+    let Some(cursor) = RECOUNT.with(|recount| recount.borrow().as_ref().map(|r| r.scanned_through.clone())) else {
+        return true;
+    };
+    let (page, next_cursor) = USER_TASKS.with(|store| paginate_btree(&store.borrow(), cursor, limit));
+
+    let finished = RECOUNT.with(|recount| {
+        let mut slot = recount.borrow_mut();
+        let recount = slot.as_mut().expect("recount in progress");
+        for (_, state) in &page {
+            for key in task_statuses(&state.tasks) {
+                *recount.counts.entry(key).or_default() += 1;
+            }
+        }
+        match next_cursor {
+            Some(next) => {
+                recount.scanned_through = Some(next);
+                None
+            }
+            None => slot.take().map(|r| r.counts),
+        }
+    });
+
+    match finished {
+        Some(counts) => {
+            TASK_FUNNELS.with(|store| {
+                let mut map = store.borrow_mut();
+                let stale: Vec<TaskFunnelKey> = map.iter().map(|(key, _)| key).collect();
+                for key in stale {
+                    map.remove(&key);
+                }
+                for (key, count) in counts.into_iter().filter(|(_, count)| *count > 0) {
+                    map.insert(key, count);
+                }
+            });
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_rewards::UserTaskState;
+
+    const STATUSES: [TaskStatus; 7] = [
+        TaskStatus::NotStarted,
+        TaskStatus::InProgress,
+        TaskStatus::Completed,
+        TaskStatus::RewardPrepared,
+        TaskStatus::TicketIssued,
+        TaskStatus::Claimed,
+        TaskStatus::PendingVerification,
+    ];
+
+    /// xorshift64, so the replay is the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn task(taskid: &str, status: TaskStatus) -> UserTaskDetail {
+        UserTaskDetail {
+            taskid: taskid.to_string(),
+            status,
+            completed_at: 0,
+            reward_amount: 10,
+            evidence: None,
+            last_completed_at: 0,
+            disputed: false,
+            dispute_reason: None,
+            campaign_id: None,
+            reward_tier: None,
+            retired: false,
+        }
+    }
+
+    /// Change a wallet's tasks the way the endpoints do: statuses before, write, tasks after
+    fn write(wallet: &str, apply: impl FnOnce(&mut Vec<UserTaskDetail>)) {
+        let mut state = USER_TASKS.with(|store| store.borrow().get(&wallet.to_string()))
+            .unwrap_or_else(|| UserTaskState::new(wallet.to_string(), Vec::new()));
+        let before = task_statuses(&state.tasks);
+        apply(&mut state.tasks);
+        update_funnel(wallet, &before, &state.tasks);
+        USER_TASKS.with(|store| store.borrow_mut().insert(wallet.to_string(), state));
+    }
+
+    fn brute_force() -> Vec<TaskFunnel> {
+        let mut funnels: BTreeMap<String, TaskFunnel> = BTreeMap::new();
+        USER_TASKS.with(|store| {
+            for (_, state) in store.borrow().iter() {
+                for task in &state.tasks {
+                    let funnel = funnels.entry(task.taskid.clone())
+                        .or_insert_with(|| TaskFunnel { taskid: task.taskid.clone(), ..Default::default() });
+                    *funnel.count_mut(&task.status) += 1;
+                }
+            }
+        });
+        funnels.into_values().collect()
+    }
+
+    /// One random transition: add a task, move a task to another status or drop one
+    fn random_write(rng: &mut Rng) {
+        let wallet = format!("w{}", rng.below(6));
+        let taskid = format!("t{}", rng.below(4));
+        let status = STATUSES[rng.below(STATUSES.len())].clone();
+        let action = rng.below(5);
+        write(&wallet, |tasks| match tasks.iter().position(|t| t.taskid == taskid) {
+            None => tasks.push(task(&taskid, status)),
+            Some(i) if action == 0 => {
+                tasks.remove(i);
+            }
+            Some(i) => tasks[i].status = status,
+        });
+    }
+
+    #[test]
+    fn test_counters_match_brute_force_after_random_transitions() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..400 {
+            random_write(&mut rng);
+        }
+        assert_eq!(get_all_task_funnels(), brute_force());
+        let t0 = brute_force().into_iter().find(|f| f.taskid == "t0").unwrap();
+        assert_eq!(get_task_funnel("t0".to_string()), t0);
+        assert_eq!(get_task_funnel("missing".to_string()), TaskFunnel { taskid: "missing".to_string(), ..Default::default() });
+
+        // Drift: the stored counters are wrong until a rebuild replaces them
+        TASK_FUNNELS.with(|store| store.borrow_mut().insert(TaskFunnelKey { taskid: "t9".to_string(), status: TaskStatus::Claimed }, 7));
+        start_recount().unwrap();
+        assert!(start_recount().is_err());
+        while !recount_step(2) {
+            // Writes land both behind and ahead of the cursor while the rebuild runs
+            for _ in 0..5 {
+                random_write(&mut rng);
+            }
+        }
+        assert_eq!(get_all_task_funnels(), brute_force());
+        assert!(start_recount().is_ok());
+    }
+}
@@ -0,0 +1,123 @@
+// Gates - holdings a wallet's bound principal needs before it may complete a task
+//
+// A gated task is checked with an inter-canister query against the principal bound to the
+// wallet (see ai_sub_service::bind_wallet_with_proof):
+//   TokenBalance   icrc1_balance_of on an ICRC-1 ledger must be at least `min`
+//   NftOwnership   icrc7_balance_of on an ICRC-7 collection canister must be non-zero
+// Failures are prefixed GateNotSatisfied. While the check is awaited the (wallet, taskid) pair
+// is marked in flight, so a second completion of the same task is refused with
+// CompletionInFlight instead of racing the first.
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use icrc_ledger_types::icrc1::account::Account;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+/// Holding required to complete a task
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TaskGate {
+    TokenBalance { ledger: Principal, min: u64 },
+    NftOwnership { canister: Principal, collection: String },  // collection names the NFTs in errors
+}
+
+thread_local! {
+    // (wallet, taskid) pairs with a gate check awaiting another canister (heap only)
+    static COMPLETIONS_IN_FLIGHT: RefCell<BTreeSet<(String, String)>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// Marks a (wallet, taskid) completion in flight until dropped
+pub(crate) struct CompletionGuard {
+    key: (String, String),
+}
+
+impl CompletionGuard {
+    pub(crate) fn acquire(wallet: &str, taskid: &str) -> Result<Self, String> {
+        let key = (wallet.to_string(), taskid.to_string());
+        let inserted = COMPLETIONS_IN_FLIGHT.with(|set| set.borrow_mut().insert(key.clone()));
+        if !inserted {
+            return Err(format!("CompletionInFlight: task {} of wallet {} is already being completed", taskid, wallet));
+        }
+        Ok(CompletionGuard { key })
+    }
+}
+
+impl Drop for CompletionGuard {
+    fn drop(&mut self) {
+        COMPLETIONS_IN_FLIGHT.with(|set| set.borrow_mut().remove(&self.key));
+    }
+}
+
+/// Whether a balance read from a gate's canister satisfies it
+fn gate_satisfied(gate: &TaskGate, balance: &Nat) -> bool {
+    match gate {
+        TaskGate::TokenBalance { min, .. } => *balance >= *min,
+        TaskGate::NftOwnership { .. } => *balance > 0u64,
+    }
+}
+
+/// Check the gate against the principal bound to `wallet`
+pub(crate) async fn check_gate(gate: &TaskGate, wallet: &str) -> Result<(), String> {
+    let principal_id = crate::ai_sub_service::get_wallet_principal(wallet)
+        .ok_or_else(|| format!("GateNotSatisfied: wallet {} is not bound to a principal", wallet))?;
+    let owner = Principal::from_text(&principal_id)
+        .map_err(|e| format!("GateNotSatisfied: invalid bound principal {}: {}", principal_id, e))?;
+    let account = Account { owner, subaccount: None };
+
+    let balance = match gate {
+        TaskGate::TokenBalance { ledger, .. } => {
+            let (balance,): (Nat,) = ic_cdk::call(*ledger, "icrc1_balance_of", (account,))
+                .await
+                .map_err(|(code, msg)| format!("GateNotSatisfied: icrc1_balance_of on {} failed ({:?}): {}", ledger, code, msg))?;
+            balance
+        }
+        TaskGate::NftOwnership { canister, .. } => {
+            let (balances,): (Vec<Nat>,) = ic_cdk::call(*canister, "icrc7_balance_of", (vec![account],))
+                .await
+                .map_err(|(code, msg)| format!("GateNotSatisfied: icrc7_balance_of on {} failed ({:?}): {}", canister, code, msg))?;
+            balances.into_iter().next().unwrap_or_default()
+        }
+    };
+
+    if gate_satisfied(gate, &balance) {
+        return Ok(());
+    }
+    Err(match gate {
+        TaskGate::TokenBalance { ledger, min } => format!(
+            "GateNotSatisfied: principal {} holds {} on ledger {}, needs at least {}",
+            owner, balance, ledger, min
+        ),
+        TaskGate::NftOwnership { canister, collection } => format!(
+            "GateNotSatisfied: principal {} holds no NFT of collection {} ({})",
+            owner, collection, canister
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate_thresholds() {
+        let ledger = Principal::anonymous();
+        let token = TaskGate::TokenBalance { ledger, min: 100 };
+        assert!(!gate_satisfied(&token, &Nat::from(99u64)));
+        assert!(gate_satisfied(&token, &Nat::from(100u64)));
+
+        let nft = TaskGate::NftOwnership { canister: ledger, collection: "genesis".to_string() };
+        assert!(!gate_satisfied(&nft, &Nat::from(0u64)));
+        assert!(gate_satisfied(&nft, &Nat::from(1u64)));
+    }
+
+    #[test]
+    fn test_completion_guard_blocks_same_task_until_dropped() {
+        let guard = CompletionGuard::acquire("w", "premium").unwrap();
+        assert!(CompletionGuard::acquire("w", "premium").err().unwrap().starts_with("CompletionInFlight"));
+        assert!(CompletionGuard::acquire("w", "other").is_ok());
+        assert!(CompletionGuard::acquire("w2", "premium").is_ok());
+
+        drop(guard);
+        assert!(CompletionGuard::acquire("w", "premium").is_ok());
+    }
+}
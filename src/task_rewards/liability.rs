@@ -0,0 +1,263 @@
+// Liability - running totals of the PMUG the canister owes across every wallet
+//
+// Each task contributes its reward_amount by status:
+//   Completed                    pending           (waiting for the next epoch snapshot)
+//   RewardPrepared, TicketIssued locked_unclaimed  (in an epoch leaf, not claimed yet)
+//   Claimed                      claimed_lifetime
+// Every write that changes task statuses takes liability_totals of the wallet's tasks before
+// and after and passes both to update_liability, the way the leaderboard is kept.
+//
+// recompute_liability_summary rebuilds the totals from USER_TASKS in chunks of
+// LIABILITY_RECOUNT_CHUNK wallets, one timer per chunk. Changes to wallets the recount has
+// already passed are applied to the recount too, so the rebuilt totals are current when the
+// last chunk replaces the stored ones.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use super::{TaskStatus, UserTaskDetail};
+use crate::dry_run;
+use crate::stable_mem_storage::{LIABILITY_SUMMARY, USER_TASKS};
+use crate::storage_utils::paginate_btree;
+
+/// Wallets read per recount step
+pub const LIABILITY_RECOUNT_CHUNK: u64 = 500;
+
+/// Outstanding and settled rewards, summed over all wallets
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LiabilitySummary {
+    pub pending: u64,
+    pub locked_unclaimed: u64,
+    pub claimed_lifetime: u64,
+}
+
+impl Storable for LiabilitySummary {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize LiabilitySummary");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize LiabilitySummary")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl LiabilitySummary {
+    fn add(&mut self, other: &LiabilitySummary) {
+        self.pending = self.pending.saturating_add(other.pending);
+        self.locked_unclaimed = self.locked_unclaimed.saturating_add(other.locked_unclaimed);
+        self.claimed_lifetime = self.claimed_lifetime.saturating_add(other.claimed_lifetime);
+    }
+
+    fn apply_delta(&mut self, before: &LiabilitySummary, after: &LiabilitySummary) {
+        self.pending = self.pending.saturating_sub(before.pending).saturating_add(after.pending);
+        self.locked_unclaimed = self.locked_unclaimed.saturating_sub(before.locked_unclaimed).saturating_add(after.locked_unclaimed);
+        self.claimed_lifetime = self.claimed_lifetime.saturating_sub(before.claimed_lifetime).saturating_add(after.claimed_lifetime);
+    }
+}
+
+/// Recount in progress (heap only; an upgrade mid-recount drops it and the stored totals stay)
+struct Recount {
+    scanned_through: Option<String>,  // Last wallet counted; None before the first chunk
+    totals: LiabilitySummary,
+}
+
+thread_local! {
+    static RECOUNT: RefCell<Option<Recount>> = const { RefCell::new(None) };
+}
+
+/// Liability of one wallet's tasks
+pub(crate) fn liability_totals(tasks: &[UserTaskDetail]) -> LiabilitySummary {
+    let mut totals = LiabilitySummary::default();
+    for task in tasks {
+        let bucket = match task.status {
+            TaskStatus::Completed => &mut totals.pending,
+            TaskStatus::RewardPrepared | TaskStatus::TicketIssued => &mut totals.locked_unclaimed,
+            TaskStatus::Claimed => &mut totals.claimed_lifetime,
+            TaskStatus::NotStarted | TaskStatus::InProgress | TaskStatus::PendingVerification => continue,
+        };
+        *bucket = bucket.saturating_add(task.reward_amount);
+    }
+    totals
+}
+
+/// Move the totals from a wallet's liability `before` a write to the one `after` it
+pub(crate) fn update_liability(wallet: &str, before: LiabilitySummary, after: LiabilitySummary) {
+    if before == after {
+        return;
+    }
+    LIABILITY_SUMMARY.with(|cell| {
+        let mut summary = *cell.borrow().get();
+        summary.apply_delta(&before, &after);
+        dry_run::shadow_cell(&LIABILITY_SUMMARY, &cell.borrow());
+        cell.borrow_mut().set(summary).expect("Failed to store liability summary");
+    });
+    // A dry run's change is undone by restoring the cell; the recount never sees it
+    if dry_run::is_active() {
+        return;
+    }
+    RECOUNT.with(|recount| {
+        if let Some(recount) = recount.borrow_mut().as_mut() {
+            if recount.scanned_through.as_deref().is_some_and(|last| wallet <= last) {
+                recount.totals.apply_delta(&before, &after);
+            }
+        }
+    });
+}
+
+/// Totals promised but not settled (pending, locked_unclaimed) and settled (claimed_lifetime)
+pub fn get_liability_summary() -> LiabilitySummary {
+    LIABILITY_SUMMARY.with(|cell| *cell.borrow().get())
+}
+
+/// Rebuild the totals from every wallet's tasks, one chunk per timer (admin only)
+pub fn recompute_liability_summary() -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can recompute the liability summary".to_string());
+    }
+    start_recount()?;
+    schedule_recount_step();
+    Ok(())
+}
+
+fn start_recount() -> Result<(), String> {
+    RECOUNT.with(|recount| {
+        let mut recount = recount.borrow_mut();
+        if recount.is_some() {
+            return Err("Liability recount already in progress".to_string());
+        }
+        *recount = Some(Recount { scanned_through: None, totals: LiabilitySummary::default() });
+        Ok(())
+    })
+}
+
+fn schedule_recount_step() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if recount_step(LIABILITY_RECOUNT_CHUNK) {
+            crate::env::println!("Liability summary recomputed: {:?}", get_liability_summary());
+        } else {
+            schedule_recount_step();
+        }
+    });
+}
+
+/// Count the next `limit` wallets; true once the recount finished and replaced the totals
+fn recount_step(limit: u64) -> bool {
+    let Some(cursor) = RECOUNT.with(|recount| recount.borrow().as_ref().map(|r| r.scanned_through.clone())) else {
+        return true;
+    };
+    let (page, next_cursor) = USER_TASKS.with(|store| paginate_btree(&store.borrow(), cursor, limit));
+
+    let finished = RECOUNT.with(|recount| {
+        let mut slot = recount.borrow_mut();
+        let recount = slot.as_mut().expect("recount in progress");
+        for (_, state) in &page {
+            recount.totals.add(&liability_totals(&state.tasks));
+        }
+        match next_cursor {
+            Some(next) => {
+                recount.scanned_through = Some(next);
+                None
+            }
+            None => slot.take().map(|r| r.totals),
+        }
+    });
+
+    match finished {
+        Some(totals) => {
+            LIABILITY_SUMMARY.with(|cell| cell.borrow_mut().set(totals).expect("Failed to store liability summary"));
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_rewards::{apply_claim_result, ClaimResultStatus, UserTaskState};
+
+    fn task(taskid: &str, status: TaskStatus, reward_amount: u64) -> UserTaskDetail {
+        UserTaskDetail {
+            taskid: taskid.to_string(),
+            status,
+            completed_at: 0,
+            reward_amount,
+            evidence: None,
+            last_completed_at: 0,
+            disputed: false,
+            dispute_reason: None,
+            campaign_id: None,
+            reward_tier: None,
+            retired: false,
+        }
+    }
+
+    /// Change a wallet's tasks the way the endpoints do: totals before, write, totals after
+    fn write(wallet: &str, apply: impl FnOnce(&mut Vec<UserTaskDetail>)) {
+        let mut state = USER_TASKS.with(|store| store.borrow().get(&wallet.to_string()))
+            .unwrap_or_else(|| UserTaskState::new(wallet.to_string(), Vec::new()));
+        let before = liability_totals(&state.tasks);
+        apply(&mut state.tasks);
+        state.refresh_totals();
+        update_liability(wallet, before, liability_totals(&state.tasks));
+        USER_TASKS.with(|store| store.borrow_mut().insert(wallet.to_string(), state));
+    }
+
+    fn brute_force() -> LiabilitySummary {
+        let mut totals = LiabilitySummary::default();
+        USER_TASKS.with(|store| {
+            for (_, state) in store.borrow().iter() {
+                totals.add(&liability_totals(&state.tasks));
+            }
+        });
+        totals
+    }
+
+    fn set_status(taskid: &'static str, status: TaskStatus) -> impl FnOnce(&mut Vec<UserTaskDetail>) {
+        move |tasks| tasks.iter_mut().filter(|t| t.taskid == taskid).for_each(|t| t.status = status.clone())
+    }
+
+    #[test]
+    fn test_counters_match_brute_force_recount() {
+        write("w1", |tasks| tasks.push(task("t1", TaskStatus::Completed, 100)));
+        write("w2", |tasks| tasks.push(task("t1", TaskStatus::Completed, 50)));
+        write("w2", |tasks| tasks.push(task("t2", TaskStatus::InProgress, 70)));
+        write("w3", |tasks| tasks.push(task("t1", TaskStatus::Completed, 30)));
+        write("w1", set_status("t1", TaskStatus::RewardPrepared));
+        write("w2", set_status("t1", TaskStatus::TicketIssued));
+        write("w2", |tasks| apply_claim_result(tasks, &ClaimResultStatus::Success));
+        write("w3", set_status("t1", TaskStatus::TicketIssued));
+        write("w3", |tasks| apply_claim_result(tasks, &ClaimResultStatus::Failed));
+        write("w2", set_status("t2", TaskStatus::Completed));
+
+        assert_eq!(get_liability_summary(), LiabilitySummary { pending: 70, locked_unclaimed: 130, claimed_lifetime: 50 });
+        assert_eq!(get_liability_summary(), brute_force());
+
+        // Drift: the stored totals are wrong until a recount replaces them
+        LIABILITY_SUMMARY.with(|cell| cell.borrow_mut().set(LiabilitySummary { pending: 1, ..Default::default() }).unwrap());
+        start_recount().unwrap();
+        assert!(start_recount().is_err());
+
+        assert!(!recount_step(1));
+        // w1 is behind the cursor, w3 ahead of it; both must end up counted once
+        write("w1", set_status("t1", TaskStatus::TicketIssued));
+        write("w1", |tasks| apply_claim_result(tasks, &ClaimResultStatus::Success));
+        write("w3", |tasks| tasks.push(task("t2", TaskStatus::Completed, 5)));
+        assert!(!recount_step(1));
+        write("w4", |tasks| tasks.push(task("t1", TaskStatus::Completed, 8)));
+        assert!(!recount_step(1));
+        assert!(recount_step(1));
+
+        assert_eq!(get_liability_summary(), brute_force());
+        assert_eq!(get_liability_summary(), LiabilitySummary { pending: 83, locked_unclaimed: 30, claimed_lifetime: 150 });
+        assert!(recount_step(1));
+        assert!(start_recount().is_ok());
+    }
+}
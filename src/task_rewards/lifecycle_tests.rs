@@ -0,0 +1,173 @@
+// Lifecycle tests - the reward flow end to end under a TestEnvironment
+//
+// Contract -> completions -> snapshot -> tickets -> claim results -> next snapshot, with the
+// amounts and statuses checked after every step. Proofs are checked with a verifier written
+// from the leaf and parent hash spec, independent of the canister's Merkle code.
+
+use candid::Principal;
+use sha2::{Digest, Sha256};
+
+use super::*;
+use crate::env::TestEnvironment;
+use crate::stable_mem_storage::WALLET_PRINCIPALS;
+
+const DAY_NS: u64 = 86_400_000_000_000;
+const WALLETS: u8 = 50;
+
+fn admin() -> Principal {
+    Principal::from_slice(&[0xad; 29])
+}
+
+fn owner(i: u8) -> Principal {
+    Principal::from_slice(&[i, 0x0e])
+}
+
+fn wallet(i: u8) -> String {
+    bs58::encode([i + 1; 32]).into_string()
+}
+
+fn item(taskid: &str, reward: u64) -> TaskContractItem {
+    TaskContractItem {
+        taskid: taskid.to_string(),
+        reward,
+        payfor: None,
+        display_order: 0,
+        vesting_cliff_ns: None,
+        cooldown_secs: None,
+        referral_bonus: None,
+        gate: None,
+        active_from: None,
+        active_until: None,
+        campaign_id: None,
+        reward_expr: None,
+        reward_policy: None,
+        reward_tiers: Vec::new(),
+        daily_checkin: false,
+        paused: false,
+        requires_attestation: false,
+    }
+}
+
+/// SHA256(epoch_le || index_le || wallet || amount_le) folded with sorted-pair SHA256 parents
+fn independently_verifies(ticket: &ClaimTicket) -> bool {
+    let wallet_bytes = bs58::decode(&ticket.wallet).into_vec().unwrap();
+    let mut node: Vec<u8> = Sha256::new()
+        .chain_update(ticket.epoch.to_le_bytes())
+        .chain_update(ticket.index.to_le_bytes())
+        .chain_update(&wallet_bytes)
+        .chain_update(ticket.amount.to_le_bytes())
+        .finalize()
+        .to_vec();
+    for sibling in &ticket.proof {
+        let (low, high) = if node <= *sibling { (&node, sibling) } else { (sibling, &node) };
+        node = Sha256::new().chain_update(low).chain_update(high).finalize().to_vec();
+    }
+    node == ticket.root
+}
+
+fn statuses(wallet: &str) -> Vec<(String, TaskStatus)> {
+    let state = USER_TASKS.with(|store| store.borrow().get(&wallet.to_string())).unwrap();
+    let mut statuses: Vec<_> = state.tasks.iter().map(|t| (t.taskid.clone(), t.status.clone())).collect();
+    statuses.sort();
+    statuses
+}
+
+fn build(epoch: u64) -> MerkleSnapshotMeta {
+    let options = BuildEpochOptions { max_participants: None, min_reward_filter: None, claim_deadline: None, auto_lock: true };
+    build_epoch_snapshot(epoch, options, ChainTarget::Solana, format!("Epoch {}", epoch), wallet(200), None, None).unwrap()
+}
+
+/// Issue a ticket as the wallet's owner and check it against the root independently
+fn issue_ticket(env: &TestEnvironment, i: u8, epoch: u64, amount: u64) -> ClaimTicket {
+    env.set_caller(owner(i));
+    let ticket = get_claim_ticket(wallet(i)).unwrap();
+    env.set_caller(admin());
+    assert_eq!((ticket.epoch, ticket.amount), (epoch, amount));
+    assert!(independently_verifies(&ticket), "proof of wallet {} does not verify", i);
+    ticket
+}
+
+#[test]
+fn test_reward_lifecycle_end_to_end() {
+    let env = TestEnvironment::install(admin(), 1_700_000_000_000_000_000);
+    use TaskStatus::*;
+
+    // Contract: everyone follows, even wallets also post
+    init_task_contract(vec![item("follow", 100), item("post", 250)]).unwrap();
+    env.set_caller(owner(0));
+    assert!(init_task_contract(vec![item("other", 1)]).is_err());
+    env.set_caller(admin());
+
+    for i in 0..WALLETS {
+        WALLET_PRINCIPALS.with(|store| store.borrow_mut().insert(wallet(i), owner(i).to_text()));
+        internal_complete_task(wallet(i), "follow".to_string(), None, env.now.get()).unwrap();
+        if i.is_multiple_of(2) {
+            internal_complete_task(wallet(i), "post".to_string(), Some("https://x.com/p".to_string()), env.now.get()).unwrap();
+        }
+        env.advance(1_000);
+    }
+    assert!(internal_complete_task(wallet(0), "follow".to_string(), None, env.now.get()).is_err());
+    assert_eq!(statuses(&wallet(0)), vec![("follow".to_string(), Completed), ("post".to_string(), Completed)]);
+    assert_eq!(statuses(&wallet(1)), vec![("follow".to_string(), Completed), ("post".to_string(), NotStarted)]);
+    let expected = |i: u8| if i.is_multiple_of(2) { 350 } else { 100 };
+    let total: u64 = (0..WALLETS).map(expected).sum();
+    assert_eq!(liability::get_liability_summary().pending, total);
+
+    // Snapshot: one leaf per wallet, certified, every task prepared
+    let meta = build(1);
+    assert_eq!((meta.leaves_count, meta.total_reward_amount, meta.locked), (WALLETS as u64, total, true));
+    assert_eq!(*env.certified_data.borrow(), certified_epoch_hash(1, &meta.root).to_vec());
+    assert!(statuses(&wallet(1)).contains(&("follow".to_string(), RewardPrepared)));
+    assert_eq!(liability::get_liability_summary(), liability::LiabilitySummary { pending: 0, locked_unclaimed: total, claimed_lifetime: 0 });
+
+    // Tickets: only the owner may issue, and every proof verifies against the root
+    env.set_caller(owner(1));
+    assert!(get_claim_ticket(wallet(0)).unwrap_err().starts_with("NotAuthorized"));
+    env.set_caller(admin());
+    for i in 0..WALLETS {
+        let ticket = issue_ticket(&env, i, 1, expected(i));
+        assert_eq!(ticket.root, meta.root.to_vec());
+        assert!(verify_claim_ticket(&ticket).unwrap());
+        assert!(statuses(&wallet(i)).iter().all(|(_, status)| *status != RewardPrepared));
+    }
+    assert_eq!(epoch_issuance_counts(1), (WALLETS as u64, 0));
+
+    // Claims: a stranger cannot report a result; the first half succeeds, the second half
+    // fails and can be issued again
+    env.set_caller(owner(1));
+    let err = mark_claim_result(wallet(0), 1, ClaimResultStatus::Failed, None).unwrap_err();
+    assert!(err.starts_with("NotAuthorized"), "{}", err);
+    assert!(statuses(&wallet(0)).iter().any(|(_, status)| *status == TicketIssued));
+    env.set_caller(admin());
+    for i in 0..WALLETS {
+        let status = if i < WALLETS / 2 { ClaimResultStatus::Success } else { ClaimResultStatus::Failed };
+        mark_claim_result(wallet(i), 1, status, Some(format!("sig{}", i))).unwrap();
+    }
+    let claimed: u64 = (0..WALLETS / 2).map(expected).sum();
+    assert_eq!(liability::get_liability_summary(), liability::LiabilitySummary { pending: 0, locked_unclaimed: total - claimed, claimed_lifetime: claimed });
+    assert!(statuses(&wallet(0)).iter().all(|(_, status)| *status == Claimed));
+    assert_eq!(statuses(&wallet(WALLETS - 1))[0], ("follow".to_string(), RewardPrepared));
+    assert_eq!(epoch_issuance_counts(1), (WALLETS as u64 / 2, WALLETS as u64 / 2));
+    assert!(get_claim_ticket(wallet(0)).unwrap_err().starts_with("AlreadyClaimed"));
+    issue_ticket(&env, WALLETS - 1, 1, expected(WALLETS - 1));
+
+    // Second epoch: a new task for the claimed half, built once the rate limit window passed
+    add_task_to_contract(item("refer", 40), true).unwrap();
+    for i in 0..WALLETS / 2 {
+        internal_complete_task(wallet(i), "refer".to_string(), None, env.now.get()).unwrap();
+    }
+    assert!(build_epoch_snapshot(
+        2, BuildEpochOptions::default(), ChainTarget::Solana, String::new(), wallet(200), None, None,
+    ).unwrap_err().contains("rate limit"));
+    env.advance(DAY_NS);
+    let meta2 = build(2);
+    assert_eq!((meta2.leaves_count, meta2.total_reward_amount, meta2.previous_epoch), (WALLETS as u64 / 2, 40 * WALLETS as u64 / 2, Some(1)));
+    for i in 0..WALLETS / 2 {
+        let ticket = issue_ticket(&env, i, 2, 40);
+        assert_eq!(ticket.root, meta2.root.to_vec());
+        mark_claim_result(wallet(i), 2, ClaimResultStatus::Success, None).unwrap();
+        assert!(statuses(&wallet(i)).iter().all(|(_, status)| matches!(status, Claimed | NotStarted)));
+    }
+    assert_eq!(liability::get_liability_summary().claimed_lifetime, claimed + 40 * WALLETS as u64 / 2);
+    assert_eq!(find_epoch_gaps(), Vec::new());
+}
@@ -0,0 +1,251 @@
+// Notifications - per-wallet queue of task status changes
+//
+// Every status change of a user task goes through transition_task_status, which compares the
+// statuses before and after and queues a TaskNotification for each change users care about.
+// A wallet keeps at most MAX_NOTIFICATIONS_PER_WALLET notifications; on overflow read ones are
+// dropped first, then the oldest unread.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::{normalize_wallet, TaskStatus, UserTaskDetail};
+use crate::dry_run;
+use crate::stable_mem_storage::TASK_NOTIFICATIONS;
+
+/// Notifications kept per wallet
+pub const MAX_NOTIFICATIONS_PER_WALLET: usize = 100;
+
+/// Task status change a notification reports
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TaskEvent {
+    TaskCompleted,
+    RewardPrepared,
+    TicketIssued,
+    ClaimSucceeded,
+    ClaimFailed,  // TicketIssued went back to RewardPrepared
+    EvidenceRejected,  // A verifier rejected the evidence; the task is InProgress again
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TaskNotification {
+    pub notification_id: u64,
+    pub wallet: String,
+    pub taskid: String,
+    pub event: TaskEvent,
+    pub ts: u64,
+    pub read: bool,
+}
+
+impl Storable for TaskNotification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize TaskNotification");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize TaskNotification")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Key for the notification queue. Ordered by wallet first, so a wallet's notifications are
+/// contiguous, oldest first.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NotificationKey {
+    pub wallet: String,
+    pub notification_id: u64,
+}
+
+impl Storable for NotificationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize NotificationKey");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize NotificationKey")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Event for a task whose status went from `before` (None for a new task) to `after`
+fn status_event(before: Option<&TaskStatus>, after: &TaskStatus) -> Option<TaskEvent> {
+    if before == Some(after) {
+        return None;
+    }
+    match (before, after) {
+        (_, TaskStatus::Completed) => Some(TaskEvent::TaskCompleted),
+        (Some(TaskStatus::TicketIssued), TaskStatus::RewardPrepared) => Some(TaskEvent::ClaimFailed),
+        (_, TaskStatus::RewardPrepared) => Some(TaskEvent::RewardPrepared),
+        (_, TaskStatus::TicketIssued) => Some(TaskEvent::TicketIssued),
+        (_, TaskStatus::Claimed) => Some(TaskEvent::ClaimSucceeded),
+        (Some(TaskStatus::PendingVerification), TaskStatus::InProgress) => Some(TaskEvent::EvidenceRejected),
+        _ => None,
+    }
+}
+
+/// Apply `apply` to a wallet's tasks and queue a notification for every status change it made
+pub(crate) fn transition_task_status<R>(
+    wallet: &str,
+    tasks: &mut Vec<UserTaskDetail>,
+    ts: u64,
+    apply: impl FnOnce(&mut Vec<UserTaskDetail>) -> R,
+) -> R {
+    let before: HashMap<String, TaskStatus> = tasks.iter()
+        .map(|t| (t.taskid.clone(), t.status.clone()))
+        .collect();
+    let result = apply(tasks);
+    for task in tasks.iter() {
+        if let Some(event) = status_event(before.get(&task.taskid), &task.status) {
+            push_notification(wallet, &task.taskid, event, ts);
+        }
+    }
+    result
+}
+
+fn wallet_keys(wallet: &str) -> std::ops::RangeFrom<NotificationKey> {
+    NotificationKey { wallet: wallet.to_string(), notification_id: 0 }..
+}
+
+fn push_notification(wallet: &str, taskid: &str, event: TaskEvent, ts: u64) {
+    TASK_NOTIFICATIONS.with(|store| {
+        let mut map = store.borrow_mut();
+        let queued: Vec<(NotificationKey, bool)> = map.range(wallet_keys(wallet))
+            .take_while(|(key, _)| key.wallet == wallet)
+            .map(|(key, notification)| (key, notification.read))
+            .collect();
+
+        if queued.len() >= MAX_NOTIFICATIONS_PER_WALLET {
+            let oldest = queued.iter().find(|(_, read)| *read).unwrap_or(&queued[0]);
+            dry_run::shadow_entry(&TASK_NOTIFICATIONS, &map, &oldest.0);
+            map.remove(&oldest.0);
+        }
+
+        let notification_id = queued.last().map_or(1, |(key, _)| key.notification_id + 1);
+        let key = NotificationKey { wallet: wallet.to_string(), notification_id };
+        dry_run::shadow_entry(&TASK_NOTIFICATIONS, &map, &key);
+        map.insert(
+            key,
+            TaskNotification {
+                notification_id,
+                wallet: wallet.to_string(),
+                taskid: taskid.to_string(),
+                event,
+                ts,
+                read: false,
+            },
+        );
+    });
+}
+
+/// Unread notifications of a wallet, oldest first
+pub fn get_pending_notifications(wallet: String) -> Vec<TaskNotification> {
+    let wallet = normalize_wallet(&wallet).unwrap_or(wallet);
+    TASK_NOTIFICATIONS.with(|store| {
+        store.borrow()
+            .range(wallet_keys(&wallet))
+            .take_while(|(key, _)| key.wallet == wallet)
+            .filter(|(_, notification)| !notification.read)
+            .map(|(_, notification)| notification)
+            .collect()
+    })
+}
+
+/// Mark notifications of a wallet as read (controller or the wallet's bound principal).
+/// Returns how many unread notifications were marked; unknown ids are ignored.
+pub fn mark_notifications_read(wallet: String, notification_ids: Vec<u64>) -> Result<u64, String> {
+    let wallet = normalize_wallet(&wallet)?;
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller)
+        && crate::ai_sub_service::get_wallet_principal(&wallet) != Some(caller.to_text())
+    {
+        return Err(format!("NotAuthorized: wallet {} is not bound to {}", wallet, caller));
+    }
+    Ok(mark_read(&wallet, &notification_ids))
+}
+
+fn mark_read(wallet: &str, notification_ids: &[u64]) -> u64 {
+    TASK_NOTIFICATIONS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut marked = 0u64;
+        for &notification_id in notification_ids {
+            let key = NotificationKey { wallet: wallet.to_string(), notification_id };
+            if let Some(mut notification) = map.get(&key) {
+                if !notification.read {
+                    notification.read = true;
+                    map.insert(key, notification);
+                    marked += 1;
+                }
+            }
+        }
+        marked
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(taskid: &str, status: TaskStatus) -> UserTaskDetail {
+        UserTaskDetail {
+            taskid: taskid.to_string(),
+            status,
+            completed_at: 0,
+            reward_amount: 10,
+            evidence: None,
+            last_completed_at: 0,
+            disputed: false,
+            dispute_reason: None,
+            campaign_id: None,
+            reward_tier: None,
+            retired: false,
+        }
+    }
+
+    #[test]
+    fn test_status_changes_queue_notifications() {
+        let mut tasks = vec![task("a", TaskStatus::NotStarted), task("b", TaskStatus::TicketIssued)];
+        transition_task_status("w1", &mut tasks, 5, |tasks| {
+            tasks[0].status = TaskStatus::Completed;
+            tasks[1].status = TaskStatus::RewardPrepared;
+            tasks.push(task("c", TaskStatus::Completed));
+        });
+        transition_task_status("w1", &mut tasks, 6, |_| ());
+
+        let pending = get_pending_notifications("w1".to_string());
+        let events: Vec<(&str, TaskEvent)> = pending.iter().map(|n| (n.taskid.as_str(), n.event.clone())).collect();
+        assert_eq!(events, vec![
+            ("a", TaskEvent::TaskCompleted),
+            ("b", TaskEvent::ClaimFailed),
+            ("c", TaskEvent::TaskCompleted),
+        ]);
+        assert_eq!(pending.iter().map(|n| n.notification_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        assert_eq!(mark_read("w1", &[2, 3, 3, 99]), 2);
+        assert_eq!(get_pending_notifications("w1".to_string()).len(), 1);
+        assert!(get_pending_notifications("w2".to_string()).is_empty());
+    }
+
+    #[test]
+    fn test_queue_is_capped_dropping_read_then_oldest() {
+        for i in 0..MAX_NOTIFICATIONS_PER_WALLET as u64 {
+            push_notification("w", &format!("t{}", i), TaskEvent::TicketIssued, i);
+        }
+        mark_read("w", &[50]);
+        push_notification("w", "next", TaskEvent::ClaimSucceeded, 100);
+        let pending = get_pending_notifications("w".to_string());
+        assert_eq!(pending.len(), MAX_NOTIFICATIONS_PER_WALLET);
+        assert_eq!(pending[0].notification_id, 1);
+
+        push_notification("w", "last", TaskEvent::ClaimSucceeded, 101);
+        let pending = get_pending_notifications("w".to_string());
+        assert_eq!(pending.len(), MAX_NOTIFICATIONS_PER_WALLET);
+        assert_eq!(pending[0].notification_id, 2);
+        assert_eq!(pending.last().map(|n| n.notification_id), Some(102));
+    }
+}
@@ -0,0 +1,144 @@
+// Payment archive - move old payments out of the payment log to cap stable memory growth
+//
+// archive_payments moves payments recorded before a cutoff from PAYMENTS into the
+// ARCHIVED_PAYMENTS queue and drops them from the currency, wallet and timeline indexes. An
+// off-canister store pulls the queue with get_archived_batch; each call acknowledges (and
+// deletes) everything up to the cursor it passes and returns the next batch.
+//
+// What outlives an archived payment: its tx_ref in PAYMENT_TX_REFS (replays stay rejected),
+// the payfor stats and payer counts, and the wallet meta. Payment indices are never reused.
+
+use candid::{CandidType, Deserialize};
+
+use super::{for_each_payment_index, IndexedPayment, MAX_SCAN_PAGE};
+use crate::stable_mem_storage::{ARCHIVED_PAYMENTS, NEXT_PAYMENT_INDEX, PAYMENTS};
+
+/// Payments returned per get_archived_batch call
+pub const ARCHIVE_BATCH_SIZE: u64 = 100;
+
+/// A batch of archived payments; pass next_cursor to the next call to acknowledge this one
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedPaymentBatch {
+    pub payments: Vec<IndexedPayment>,
+    pub next_cursor: Option<u64>,  // Index of the last payment returned; None when the queue is empty
+}
+
+/// Archive up to `max_records` (at most MAX_SCAN_PAGE) of the oldest payments recorded before
+/// `before_ts`; returns how many were archived (admin only)
+pub fn archive_payments(before_ts: u64, max_records: u64) -> Result<u64, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can archive payments".to_string());
+    }
+    let archived = move_to_archive(before_ts, max_records);
+    crate::env::println!("Archived {} payments recorded before {}", archived, before_ts);
+    Ok(archived)
+}
+
+fn move_to_archive(before_ts: u64, max_records: u64) -> u64 {
+    // Payments are stamped with canister time, so index order is time order
+    let batch: Vec<IndexedPayment> = PAYMENTS.with(|store| {
+        store.borrow()
+            .iter()
+            .take_while(|(_, payment)| payment.ts < before_ts)
+            .take(max_records.min(MAX_SCAN_PAGE) as usize)
+            .map(|(payment_index, payment)| IndexedPayment { payment_index, payment })
+            .collect()
+    });
+    for IndexedPayment { payment_index, payment } in &batch {
+        for_each_payment_index(*payment_index, payment, false);
+        PAYMENTS.with(|store| store.borrow_mut().remove(payment_index));
+        ARCHIVED_PAYMENTS.with(|store| store.borrow_mut().insert(*payment_index, payment.clone()));
+    }
+    batch.len() as u64
+}
+
+/// Acknowledge the archived payments up to `cursor` and return the next batch (admin only)
+pub fn get_archived_batch(cursor: Option<u64>) -> Result<ArchivedPaymentBatch, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can pull archived payments".to_string());
+    }
+    Ok(pull_batch(cursor, ARCHIVE_BATCH_SIZE))
+}
+
+fn pull_batch(cursor: Option<u64>, limit: u64) -> ArchivedPaymentBatch {
+    ARCHIVED_PAYMENTS.with(|store| {
+        let mut map = store.borrow_mut();
+        if let Some(cursor) = cursor {
+            let acknowledged: Vec<u64> = map.range(..=cursor).map(|(index, _)| index).collect();
+            for index in acknowledged {
+                map.remove(&index);
+            }
+        }
+        let payments: Vec<IndexedPayment> = map.iter()
+            .take(limit as usize)
+            .map(|(payment_index, payment)| IndexedPayment { payment_index, payment })
+            .collect();
+        let next_cursor = payments.last().map(|p| p.payment_index);
+        ArchivedPaymentBatch { payments, next_cursor }
+    })
+}
+
+/// Whether `payment_index` was recorded and has since been archived
+pub(crate) fn is_archived(payment_index: u64) -> bool {
+    payment_index < NEXT_PAYMENT_INDEX.with(|cell| *cell.borrow().get())
+        && !PAYMENTS.with(|store| store.borrow().contains_key(&payment_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stable_mem_storage::{PAYMENTS_BY_CURRENCY, PAYMENTS_BY_WALLET, PAYMENTS_BY_WALLET_TS};
+    use crate::task_rewards::{list_payments_cursor, store_payment, PaymentCurrency, PaymentRecord};
+
+    fn payment(wallet: &str, tx_ref: &str, ts: u64) -> PaymentRecord {
+        PaymentRecord {
+            wallet: wallet.to_string(),
+            amount_paid: 10,
+            tx_ref: tx_ref.to_string(),
+            ts,
+            payfor: None,
+            recorded_by: None,
+            token: None,
+            client_ts: None,
+            currency: PaymentCurrency::Pmug,
+            exchange_rate: None,
+        }
+    }
+
+    fn index_sizes() -> (u64, u64, u64) {
+        (
+            PAYMENTS_BY_CURRENCY.with(|store| store.borrow().len()),
+            PAYMENTS_BY_WALLET.with(|store| store.borrow().len()),
+            PAYMENTS_BY_WALLET_TS.with(|store| store.borrow().len()),
+        )
+    }
+
+    #[test]
+    fn test_archive_pull_and_replay_rejection() {
+        for (i, wallet) in ["w1", "w2", "w1", "w2"].iter().enumerate() {
+            assert_eq!(store_payment(&payment(wallet, &format!("tx{}", i), 100 + i as u64)), Ok(i as u64));
+        }
+        assert!(store_payment(&payment("w1", "tx0", 200)).unwrap_err().starts_with("DuplicateTxRef"));
+
+        assert_eq!(move_to_archive(102, 1), 1);
+        assert_eq!(move_to_archive(102, 10), 1);
+        assert_eq!(move_to_archive(102, 10), 0);
+        assert_eq!(index_sizes(), (2, 2, 2));
+        assert!(is_archived(0) && is_archived(1));
+        assert!(!is_archived(2) && !is_archived(4));
+
+        // Archived tx_refs stay rejected and indices are not reused
+        assert!(store_payment(&payment("w2", "tx1", 300)).unwrap_err().starts_with("DuplicateTxRef"));
+        assert_eq!(store_payment(&payment("w2", "tx4", 300)), Ok(4));
+        let (page, next) = list_payments_cursor(None, None, 10);
+        assert_eq!((page.len(), next), (3, None));
+
+        let batch = pull_batch(None, 1);
+        assert_eq!((batch.payments[0].payment_index, batch.next_cursor), (0, Some(0)));
+        let batch = pull_batch(batch.next_cursor, 1);
+        assert_eq!((batch.payments[0].payment.tx_ref.as_str(), batch.next_cursor), ("tx1", Some(1)));
+        let batch = pull_batch(batch.next_cursor, 1);
+        assert!(batch.payments.is_empty() && batch.next_cursor.is_none());
+        assert_eq!(ARCHIVED_PAYMENTS.with(|store| store.borrow().len()), 0);
+    }
+}
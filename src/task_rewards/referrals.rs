@@ -0,0 +1,251 @@
+// Referrals - referrer/referee registry and the referral bonus of contract tasks
+//
+// A referee wallet can be referred once. When it completes a contract task that carries a
+// referral_bonus of (referrer_amount, referee_amount), the referee's task books
+// reward + referee_amount and the referrer is credited with a Completed "referral-<referee>"
+// task worth referrer_amount. Each referral converts once; the referrer gets one such task per
+// converted referee.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{get_or_init_user_tasks, normalize_wallet, TaskStatus, UserTaskDetail};
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::{REFERRALS, REFERRAL_STATS, USER_TASKS};
+
+/// Prefix of the synthetic task credited to a referrer
+pub const REFERRAL_TASK_PREFIX: &str = "referral-";
+
+/// Referral of one referee wallet
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ReferralRecord {
+    pub referrer: String,
+    pub registered_at: u64,
+    pub converted_at: Option<u64>,  // Set when the referee completes a task with a referral bonus
+}
+
+impl Storable for ReferralRecord {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize ReferralRecord");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize ReferralRecord")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Referral counters of one referrer wallet
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+pub struct ReferralStats {
+    pub wallet: String,
+    pub referees: u64,
+    pub converted: u64,
+    pub referrer_rewards: u64,  // Sum of referrer amounts credited
+}
+
+impl Storable for ReferralStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize ReferralStats");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize ReferralStats")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Taskid of the referral task credited to the referrer of `referee`
+pub fn referral_taskid(referee: &str) -> String {
+    format!("{}{}", REFERRAL_TASK_PREFIX, referee)
+}
+
+/// Whether registering referrer -> referee would close a loop: walks up the referrer chain
+/// (each wallet has at most one referrer) and looks for the referee
+fn creates_cycle(referrer: &str, referee: &str, referrer_of: impl Fn(&str) -> Option<String>) -> bool {
+    let mut current = referrer.to_string();
+    loop {
+        if current == referee {
+            return true;
+        }
+        match referrer_of(&current) {
+            Some(next) => current = next,
+            None => return false,
+        }
+    }
+}
+
+/// Append the Completed referral task for `referee` unless the referrer already has it
+fn push_referral_task(tasks: &mut Vec<UserTaskDetail>, referee: &str, amount: u64, ts: u64, now: u64) -> bool {
+    let taskid = referral_taskid(referee);
+    if tasks.iter().any(|t| t.taskid == taskid) {
+        return false;
+    }
+    tasks.push(UserTaskDetail {
+        taskid,
+        status: TaskStatus::Completed,
+        completed_at: ts,
+        reward_amount: amount,
+        evidence: Some(referee.to_string()),
+        last_completed_at: now,
+        disputed: false,
+        dispute_reason: None,
+        campaign_id: None,
+        reward_tier: None,
+        retired: false,
+    });
+    true
+}
+
+/// Register `referee_wallet` as referred by `referrer_wallet`. Callable by a controller or by
+/// the principal bound to the referee wallet.
+pub fn register_referral(referrer_wallet: String, referee_wallet: String) -> Result<(), String> {
+    let referrer = normalize_wallet(&referrer_wallet)?;
+    let referee = normalize_wallet(&referee_wallet)?;
+
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller)
+        && crate::ai_sub_service::get_wallet_principal(&referee) != Some(caller.to_text())
+    {
+        return Err(format!("NotAuthorized: wallet {} is not bound to {}", referee, caller));
+    }
+
+    if referrer == referee {
+        return Err("Self-referral is not allowed".to_string());
+    }
+    if let Some(existing) = REFERRALS.with(|store| store.borrow().get(&referee)) {
+        return Err(format!("Wallet {} was already referred by {}", referee, existing.referrer));
+    }
+    let cycle = REFERRALS.with(|store| {
+        let map = store.borrow();
+        creates_cycle(&referrer, &referee, |wallet| map.get(&wallet.to_string()).map(|r| r.referrer))
+    });
+    if cycle {
+        return Err(format!("Referral cycle: {} is already upstream of {}", referee, referrer));
+    }
+
+    let record = ReferralRecord {
+        referrer: referrer.clone(),
+        registered_at: crate::env::time(),
+        converted_at: None,
+    };
+    REFERRALS.with(|store| store.borrow_mut().insert(referee.clone(), record));
+    update_stats(&referrer, |stats| stats.referees += 1);
+
+    event_log::emit(EventKind::ReferralRegistered { referrer, referee });
+    Ok(())
+}
+
+/// Referrer of `referee` when its referral has not converted yet
+pub(crate) fn pending_referrer(referee: &str) -> Option<String> {
+    REFERRALS.with(|store| store.borrow().get(&referee.to_string()))
+        .filter(|record| record.converted_at.is_none())
+        .map(|record| record.referrer)
+}
+
+/// Convert the referral of `referee`: credit its referrer with a Completed referral task
+/// worth `referrer_amount` and count the conversion. Call after the referee's task is booked.
+pub(crate) fn convert_referral(referee: &str, referrer: &str, referrer_amount: u64, ts: u64, now: u64) {
+    let taskid = referral_taskid(referee);
+
+    let referrer_exists = USER_TASKS.with(|store| store.borrow().contains_key(&referrer.to_string()));
+    if !referrer_exists {
+        get_or_init_user_tasks(referrer.to_string());
+    }
+
+    USER_TASKS.with(|store| {
+        let mut map = store.borrow_mut();
+        if let Some(mut state) = map.get(&referrer.to_string()) {
+            let liability_before = super::liability::liability_totals(&state.tasks);
+            let funnel_before = super::funnel::task_statuses(&state.tasks);
+            let pushed = super::notifications::transition_task_status(referrer, &mut state.tasks, now, |tasks| {
+                push_referral_task(tasks, referee, referrer_amount, ts, now)
+            });
+            if pushed {
+                super::reward_logs::record(&super::reward_logs::RewardCalculationLog::flat(referrer, &taskid, referrer_amount, now));
+                state.refresh_totals();
+                super::liability::update_liability(referrer, liability_before, super::liability::liability_totals(&state.tasks));
+                super::funnel::update_funnel(referrer, &funnel_before, &state.tasks);
+                map.insert(referrer.to_string(), state);
+            }
+        }
+    });
+
+    REFERRALS.with(|store| {
+        let mut map = store.borrow_mut();
+        if let Some(mut record) = map.get(&referee.to_string()) {
+            record.converted_at = Some(ts);
+            map.insert(referee.to_string(), record);
+        }
+    });
+    update_stats(referrer, |stats| {
+        stats.converted += 1;
+        stats.referrer_rewards = stats.referrer_rewards.saturating_add(referrer_amount);
+    });
+
+    crate::env::println!("Referral of {} by {} converted", referee, referrer);
+    event_log::emit(EventKind::ReferralConverted {
+        referrer: referrer.to_string(),
+        referee: referee.to_string(),
+        taskid,
+    });
+}
+
+fn update_stats(referrer: &str, apply: impl FnOnce(&mut ReferralStats)) {
+    REFERRAL_STATS.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut stats = map.get(&referrer.to_string()).unwrap_or_else(|| ReferralStats {
+            wallet: referrer.to_string(),
+            ..Default::default()
+        });
+        apply(&mut stats);
+        map.insert(referrer.to_string(), stats);
+    });
+}
+
+/// How many wallets `wallet` referred and how many of them converted
+pub fn get_referral_stats(wallet: String) -> Result<ReferralStats, String> {
+    let wallet = normalize_wallet(&wallet)?;
+    Ok(REFERRAL_STATS.with(|store| store.borrow().get(&wallet)).unwrap_or(ReferralStats {
+        wallet,
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_cycle_detection_walks_referrer_chain() {
+        // a referred b, b referred c
+        let referrers: HashMap<&str, &str> = [("b", "a"), ("c", "b")].into_iter().collect();
+        let referrer_of = |wallet: &str| referrers.get(wallet).map(|r| r.to_string());
+
+        assert!(creates_cycle("c", "a", referrer_of));
+        assert!(creates_cycle("b", "a", referrer_of));
+        assert!(creates_cycle("a", "a", referrer_of));
+        assert!(!creates_cycle("c", "d", referrer_of));
+        assert!(!creates_cycle("a", "d", referrer_of));
+    }
+
+    #[test]
+    fn test_referral_task_is_credited_once_per_referee() {
+        let mut tasks = Vec::new();
+        assert!(push_referral_task(&mut tasks, "referee1", 40, 5, 6));
+        assert!(!push_referral_task(&mut tasks, "referee1", 40, 7, 8));
+        assert!(push_referral_task(&mut tasks, "referee2", 15, 9, 9));
+
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].taskid, "referral-referee1");
+        assert_eq!((tasks[0].status.clone(), tasks[0].reward_amount, tasks[0].completed_at), (TaskStatus::Completed, 40, 5));
+        assert!(crate::task_rewards::validate_taskid(&referral_taskid(&bs58::encode([9u8; 32]).into_string())).is_ok());
+    }
+}
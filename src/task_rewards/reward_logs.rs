@@ -0,0 +1,221 @@
+// Reward logs - how every booked reward was calculated, from completion to epoch inclusion
+//
+// Each time a completion, check-in or referral conversion sets a task's reward_amount, one
+// RewardCalculationLog is appended to REWARD_CALCULATION_LOGS and indexed under its wallet.
+// When an epoch snapshot moves the wallet's tasks to RewardPrepared, the logs of those tasks
+// get the epoch in epoch_included. Logs are never removed.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{normalize_wallet, TaskStatus, UserTaskDetail};
+use crate::dry_run;
+use crate::stable_mem_storage::{REWARD_CALCULATION_LOGS, REWARD_LOGS_BY_WALLET};
+
+/// multiplier_bps of a reward booked without a multiplier (1x)
+pub const UNIT_MULTIPLIER_BPS: u16 = 10_000;
+
+/// Most logs returned by one get_reward_calculation_logs call
+pub const MAX_REWARD_LOG_PAGE: u64 = 200;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RewardCalculationLog {
+    pub wallet: String,
+    pub taskid: String,
+    pub base_reward: u64,              // Contract reward, or the amount booked at init under FixedAtInit
+    pub multiplier_bps: u16,           // Streak multiplier of a check-in; UNIT_MULTIPLIER_BPS otherwise
+    pub tier_label: Option<String>,    // Reward tier the evidence matched, which replaces the base
+    pub formula_result: Option<u64>,   // Result of the task's reward_expr when it was evaluated
+    pub final_reward: u64,             // reward_amount booked, referral bonus included
+    pub ts: u64,
+    pub epoch_included: Option<u64>,   // Epoch that prepared the task for claiming
+}
+
+impl Storable for RewardCalculationLog {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize RewardCalculationLog");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize RewardCalculationLog")
+    }
+
+    // Wallets, taskids and tier labels are length-limited, so a log stays well below this
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+}
+
+/// Key of the wallet -> reward logs index
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WalletRewardLogKey {
+    pub wallet: String,
+    pub log_index: u64,
+}
+
+impl Storable for WalletRewardLogKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize WalletRewardLogKey");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize WalletRewardLogKey")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl RewardCalculationLog {
+    /// Log of a reward booked as is, without tier, formula or multiplier
+    pub(crate) fn flat(wallet: &str, taskid: &str, reward: u64, ts: u64) -> Self {
+        RewardCalculationLog {
+            wallet: wallet.to_string(),
+            taskid: taskid.to_string(),
+            base_reward: reward,
+            multiplier_bps: UNIT_MULTIPLIER_BPS,
+            tier_label: None,
+            formula_result: None,
+            final_reward: reward,
+            ts,
+            epoch_included: None,
+        }
+    }
+}
+
+/// Append `log` and index it under its wallet. Traps when stable memory cannot grow, which
+/// rolls back the reward being booked with it.
+pub(crate) fn record(log: &RewardCalculationLog) {
+    let log_index = REWARD_CALCULATION_LOGS.with(|store| {
+        let vec = store.borrow_mut();
+        vec.push(log).expect("Failed to append reward calculation log");
+        vec.len() - 1
+    });
+    REWARD_LOGS_BY_WALLET.with(|store| {
+        store.borrow_mut().insert(WalletRewardLogKey { wallet: log.wallet.clone(), log_index }, ())
+    });
+}
+
+/// Taskids of the RewardPrepared tasks in `tasks`
+pub(crate) fn prepared_taskids(tasks: &[UserTaskDetail]) -> Vec<String> {
+    tasks.iter()
+        .filter(|task| task.status == TaskStatus::RewardPrepared)
+        .map(|task| task.taskid.clone())
+        .collect()
+}
+
+/// Set `epoch` on the wallet's logs of `taskids` that are not in an epoch yet
+pub(crate) fn mark_epoch_included(wallet: &str, taskids: &[String], epoch: u64) {
+    if taskids.is_empty() {
+        return;
+    }
+    let indices = wallet_log_indices(wallet);
+    REWARD_CALCULATION_LOGS.with(|store| {
+        let vec = store.borrow_mut();
+        for log_index in indices {
+            let Some(mut log) = vec.get(log_index) else {
+                continue;
+            };
+            if log.epoch_included.is_some() || !taskids.contains(&log.taskid) {
+                continue;
+            }
+            log.epoch_included = Some(epoch);
+            dry_run::shadow_vec_element(&REWARD_CALCULATION_LOGS, &vec, log_index);
+            vec.set(log_index, &log);
+        }
+    });
+}
+
+fn wallet_log_indices(wallet: &str) -> Vec<u64> {
+    let start = WalletRewardLogKey { wallet: wallet.to_string(), log_index: 0 };
+    REWARD_LOGS_BY_WALLET.with(|store| {
+        store.borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.wallet == wallet)
+            .map(|(key, _)| key.log_index)
+            .collect()
+    })
+}
+
+/// Up to `limit` (at most MAX_REWARD_LOG_PAGE) reward logs of `wallet` after skipping
+/// `offset`, oldest first, with the number of logs the wallet has
+pub fn get_reward_calculation_logs(wallet: String, offset: u64, limit: u64) -> (Vec<RewardCalculationLog>, u64) {
+    let wallet = normalize_wallet(&wallet).unwrap_or(wallet);
+    let indices = wallet_log_indices(&wallet);
+    let total = indices.len() as u64;
+    let logs = REWARD_CALCULATION_LOGS.with(|store| {
+        let vec = store.borrow();
+        indices.into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_REWARD_LOG_PAGE) as usize)
+            .filter_map(|log_index| vec.get(log_index))
+            .collect()
+    });
+    (logs, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use crate::env::TestEnvironment;
+    use crate::task_rewards::{
+        build_epoch_snapshot, init_task_contract, internal_complete_task, BuildEpochOptions, ChainTarget, TaskContractItem,
+    };
+
+    fn item(taskid: &str) -> TaskContractItem {
+        TaskContractItem {
+            taskid: taskid.to_string(),
+            reward: 100,
+            payfor: None,
+            display_order: 0,
+            vesting_cliff_ns: None,
+            cooldown_secs: None,
+            referral_bonus: None,
+            gate: None,
+            active_from: None,
+            active_until: None,
+            campaign_id: None,
+            reward_expr: None,
+            reward_policy: None,
+            reward_tiers: Vec::new(),
+            daily_checkin: false,
+            paused: false,
+            requires_attestation: false,
+        }
+    }
+
+    #[test]
+    fn test_logs_follow_completion_into_epoch() {
+        let env = TestEnvironment::install(Principal::from_slice(&[0xad]), 1_000);
+        let wallet = bs58::encode([7u8; 32]).into_string();
+        let post = TaskContractItem { reward_expr: Some("base * 2".to_string()), ..item("post") };
+        let vesting = TaskContractItem { vesting_cliff_ns: Some(1_000_000), ..item("vesting") };
+        init_task_contract(vec![item("follow"), post, vesting]).unwrap();
+        for taskid in ["follow", "post", "vesting"] {
+            internal_complete_task(wallet.clone(), taskid.to_string(), None, 1_000).unwrap();
+        }
+
+        let (logs, total) = get_reward_calculation_logs(wallet.clone(), 0, 10);
+        assert_eq!(total, 3);
+        assert_eq!(logs[0], RewardCalculationLog::flat(&wallet, "follow", 100, 1_000));
+        assert_eq!((logs[1].base_reward, logs[1].formula_result, logs[1].final_reward), (100, Some(200), 200));
+        assert_eq!(get_reward_calculation_logs(wallet.clone(), 1, 1), (vec![logs[1].clone()], 3));
+        assert_eq!(get_reward_calculation_logs("other".to_string(), 0, 10), (Vec::new(), 0));
+
+        // The epoch takes the vested tasks; the one still in its cliff stays unincluded
+        env.advance(10);
+        let options = BuildEpochOptions { auto_lock: true, ..BuildEpochOptions::default() };
+        build_epoch_snapshot(1, options, ChainTarget::Solana, String::new(), bs58::encode([9u8; 32]).into_string(), None, None).unwrap();
+        let included: Vec<_> = get_reward_calculation_logs(wallet, 0, 10).0.into_iter().map(|log| (log.taskid, log.epoch_included)).collect();
+        assert_eq!(included, vec![
+            ("follow".to_string(), Some(1)),
+            ("post".to_string(), Some(1)),
+            ("vesting".to_string(), None),
+        ]);
+    }
+}
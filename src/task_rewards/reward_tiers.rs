@@ -0,0 +1,158 @@
+// Reward tiers - higher rewards for better evidence of a contract task
+//
+// A task's reward_tiers each name an evidence validator. A completion with evidence books the
+// reward of the best-paying tier whose validator accepts it and records the tier's label on
+// the user task; without a match (or without evidence) the task's normal reward applies.
+// A tier reward replaces reward, reward_expr and reward_policy; a referral bonus is still added.
+//
+// Validators are built in, written `name` or `name:argument`:
+//   nonempty          any evidence that is not blank
+//   min_len:<n>       at least n characters
+//   prefix:<text>     starts with text
+//   contains:<text>   contains text
+//   url               an https:// URL with a host
+// A tier without a validator accepts any evidence.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use super::{validate_taskid, MAX_SINGLE_REWARD};
+use crate::stable_mem_storage::TASK_CONTRACT;
+
+/// Most tiers one task may have
+pub const MAX_REWARD_TIERS: usize = 8;
+
+/// Longest tier label accepted, in bytes
+pub const MAX_TIER_LABEL_LEN: usize = 32;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RewardTier {
+    pub label: String,
+    pub reward: u64,
+    pub evidence_validator: Option<String>,  // Built-in validator spec; None accepts any evidence
+}
+
+enum Validator<'a> {
+    NonEmpty,
+    MinLen(usize),
+    Prefix(&'a str),
+    Contains(&'a str),
+    Url,
+}
+
+fn parse_validator(spec: &str) -> Result<Validator<'_>, String> {
+    let (name, arg) = match spec.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    };
+    match (name, arg) {
+        ("nonempty", None) => Ok(Validator::NonEmpty),
+        ("url", None) => Ok(Validator::Url),
+        ("min_len", Some(n)) => n.parse()
+            .map(Validator::MinLen)
+            .map_err(|_| format!("Invalid evidence validator {}: min_len needs a number", spec)),
+        ("prefix", Some(text)) if !text.is_empty() => Ok(Validator::Prefix(text)),
+        ("contains", Some(text)) if !text.is_empty() => Ok(Validator::Contains(text)),
+        _ => Err(format!("Unknown evidence validator {}", spec)),
+    }
+}
+
+/// Whether `evidence` passes the validator `spec`; an invalid spec accepts nothing
+fn validator_accepts(spec: &str, evidence: &str) -> bool {
+    match parse_validator(spec) {
+        Ok(Validator::NonEmpty) => !evidence.trim().is_empty(),
+        Ok(Validator::MinLen(n)) => evidence.chars().count() >= n,
+        Ok(Validator::Prefix(text)) => evidence.starts_with(text),
+        Ok(Validator::Contains(text)) => evidence.contains(text),
+        Ok(Validator::Url) => evidence.strip_prefix("https://")
+            .and_then(|rest| rest.split('/').next())
+            .is_some_and(|host| !host.is_empty() && !host.contains(char::is_whitespace)),
+        Err(_) => false,
+    }
+}
+
+/// The best-paying tier of `tiers` whose validator accepts `evidence`; the first one listed
+/// wins a tie
+pub(crate) fn matching_tier<'a>(tiers: &'a [RewardTier], evidence: Option<&str>) -> Option<&'a RewardTier> {
+    let evidence = evidence?;
+    tiers.iter()
+        .filter(|tier| tier.evidence_validator.as_deref().is_none_or(|spec| validator_accepts(spec, evidence)))
+        .fold(None, |best: Option<&RewardTier>, tier| match best {
+            Some(best) if best.reward >= tier.reward => Some(best),
+            _ => Some(tier),
+        })
+}
+
+/// Problems with the reward tiers of contract task `taskid`, for validate_task_contract_items
+pub(crate) fn tier_errors(taskid: &str, tiers: &[RewardTier]) -> Vec<String> {
+    let mut errors = Vec::new();
+    if tiers.len() > MAX_REWARD_TIERS {
+        errors.push(format!("Task {} has more than {} reward tiers", taskid, MAX_REWARD_TIERS));
+    }
+    let mut labels = std::collections::BTreeSet::new();
+    for tier in tiers {
+        if tier.label.is_empty() || tier.label.len() > MAX_TIER_LABEL_LEN {
+            errors.push(format!("Task {} tier label {:?} must be 1 to {} bytes", taskid, tier.label, MAX_TIER_LABEL_LEN));
+        }
+        if !labels.insert(tier.label.as_str()) {
+            errors.push(format!("Task {} has duplicate tier label {}", taskid, tier.label));
+        }
+        if tier.reward > MAX_SINGLE_REWARD {
+            errors.push(format!("Task {} tier {} reward {} exceeds maximum {}", taskid, tier.label, tier.reward, MAX_SINGLE_REWARD));
+        }
+        if let Some(Err(e)) = tier.evidence_validator.as_deref().map(parse_validator) {
+            errors.push(format!("Task {} tier {}: {}", taskid, tier.label, e));
+        }
+    }
+    errors
+}
+
+/// Label of the tier a completion of `taskid` with `evidence` would be rewarded at; None if
+/// the task is unknown or no tier matches
+pub fn get_task_tier_for_evidence(taskid: String, evidence: String) -> Option<String> {
+    validate_taskid(&taskid).ok()?;
+    let item = TASK_CONTRACT.with(|store| store.borrow().get(&taskid))?;
+    matching_tier(&item.reward_tiers, Some(&evidence)).map(|tier| tier.label.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier(label: &str, reward: u64, validator: Option<&str>) -> RewardTier {
+        RewardTier { label: label.to_string(), reward, evidence_validator: validator.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_highest_matching_tier_wins() {
+        let tiers = vec![
+            tier("basic", 100, Some("nonempty")),
+            tier("linked", 250, Some("url")),
+            tier("tweet", 400, Some("prefix:https://x.com/")),
+            tier("long", 250, Some("min_len:40")),
+        ];
+        let label = |evidence: Option<&str>| matching_tier(&tiers, evidence).map(|t| t.label.as_str());
+
+        assert_eq!(label(Some("did it")), Some("basic"));
+        assert_eq!(label(Some("https://example.com/post")), Some("linked"));
+        assert_eq!(label(Some("https://x.com/aio/status/1")), Some("tweet"));
+        assert_eq!(label(Some("https:// not a url but quite a long piece of text")), Some("long"));
+        assert_eq!(label(Some("  ")), None);
+        assert_eq!(label(None), None);
+        assert_eq!(matching_tier(&[tier("any", 5, None)], Some("")).map(|t| t.reward), Some(5));
+    }
+
+    #[test]
+    fn test_rejects_bad_tiers() {
+        assert!(tier_errors("t", &[tier("a", 1, Some("contains:gm")), tier("b", 2, Some("min_len:3"))]).is_empty());
+
+        let bad = [
+            tier("a", 1, Some("regex:.*")),
+            tier("a", MAX_SINGLE_REWARD + 1, Some("min_len:x")),
+            tier("", 1, Some("prefix:")),
+        ];
+        assert_eq!(tier_errors("t", &bad).len(), 6);
+        assert_eq!(tier_errors("t", &vec![tier("a", 1, None); MAX_REWARD_TIERS + 1]).len(), 1 + MAX_REWARD_TIERS);
+        assert!(!validator_accepts("regex:.*", "anything"));
+    }
+}
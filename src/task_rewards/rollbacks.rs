@@ -0,0 +1,115 @@
+// Rollbacks - controller-forced status rollbacks of a completed task before it is snapshotted
+//
+// For evidence found to be fraudulent after a task was completed: the task goes back to
+// InProgress (so the wallet can submit new evidence) or to NotStarted. Only Completed and
+// InProgress tasks can be rolled back; from RewardPrepared on the reward is committed to an
+// epoch. Every rollback is recorded in the event log with its reason.
+
+use super::disputes::update_task;
+use super::{normalize_wallet, TaskStatus, UserTaskDetail};
+use crate::event_log::{self, EventKind};
+
+/// Longest rollback reason accepted, in characters
+pub const MAX_ROLLBACK_REASON_LEN: usize = 280;
+
+fn validate_rollback_reason(reason: &str) -> Result<(), String> {
+    if reason.trim().is_empty() {
+        return Err("Rollback reason cannot be empty".to_string());
+    }
+    if reason.chars().count() > MAX_ROLLBACK_REASON_LEN {
+        return Err(format!("Rollback reason exceeds {} characters", MAX_ROLLBACK_REASON_LEN));
+    }
+    Ok(())
+}
+
+/// Move `task` back to `to` (InProgress or NotStarted), clearing its completion
+fn roll_back(task: &mut UserTaskDetail, to: TaskStatus) -> Result<(), String> {
+    let allowed = match to {
+        TaskStatus::InProgress => task.status == TaskStatus::Completed,
+        TaskStatus::NotStarted => task.status == TaskStatus::Completed || task.status == TaskStatus::InProgress,
+        _ => false,
+    };
+    if !allowed {
+        let committed = matches!(task.status, TaskStatus::RewardPrepared | TaskStatus::TicketIssued | TaskStatus::Claimed);
+        return Err(if committed {
+            format!("Task {} is {:?}; its reward is already committed to an epoch", task.taskid, task.status)
+        } else {
+            format!("Task {} is {:?} and cannot be rolled back to {:?}", task.taskid, task.status, to)
+        });
+    }
+    task.status = to;
+    task.completed_at = 0;
+    task.evidence = None;
+    task.reward_tier = None;
+    Ok(())
+}
+
+fn admin_rollback(wallet: String, taskid: String, reason: String, to: TaskStatus) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can roll back tasks".to_string());
+    }
+    let wallet = normalize_wallet(&wallet)?;
+    validate_rollback_reason(&reason)?;
+    crate::icrc_payouts::check_no_payout_in_flight(&wallet)?;
+
+    update_task(&wallet, &taskid, |task| roll_back(task, to.clone()))?;
+    crate::env::println!("Task {} of wallet {} rolled back to {:?}: {}", taskid, wallet, to, reason);
+    event_log::emit(EventKind::TaskRolledBack { wallet, taskid, to_status: to, reason, rolled_back_by: caller.to_text() });
+    Ok(())
+}
+
+/// Move a Completed task back to InProgress, clearing its completion time and evidence
+/// (admin only)
+pub fn admin_rollback_task_to_in_progress(wallet: String, taskid: String, reason: String) -> Result<(), String> {
+    admin_rollback(wallet, taskid, reason, TaskStatus::InProgress)
+}
+
+/// Reset a Completed or InProgress task to NotStarted, clearing its completion time and
+/// evidence (admin only)
+pub fn admin_rollback_task_to_not_started(wallet: String, taskid: String, reason: String) -> Result<(), String> {
+    admin_rollback(wallet, taskid, reason, TaskStatus::NotStarted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(status: TaskStatus) -> UserTaskDetail {
+        UserTaskDetail {
+            taskid: "premium".to_string(),
+            status,
+            completed_at: 5,
+            reward_amount: 10,
+            evidence: Some("forged".to_string()),
+            last_completed_at: 5,
+            disputed: false,
+            dispute_reason: None,
+            campaign_id: None,
+            reward_tier: None,
+            retired: false,
+        }
+    }
+
+    #[test]
+    fn test_rollback_transitions_and_committed_rewards() {
+        let mut completed = task(TaskStatus::Completed);
+        roll_back(&mut completed, TaskStatus::InProgress).unwrap();
+        assert_eq!((completed.status.clone(), completed.completed_at, completed.evidence.clone()), (TaskStatus::InProgress, 0, None));
+        assert!(roll_back(&mut completed, TaskStatus::InProgress).unwrap_err().contains("cannot be rolled back"));
+        roll_back(&mut completed, TaskStatus::NotStarted).unwrap();
+        assert_eq!(completed.status, TaskStatus::NotStarted);
+        assert!(roll_back(&mut completed, TaskStatus::NotStarted).is_err());
+
+        for status in [TaskStatus::RewardPrepared, TaskStatus::TicketIssued, TaskStatus::Claimed] {
+            for to in [TaskStatus::InProgress, TaskStatus::NotStarted] {
+                let mut committed = task(status.clone());
+                assert!(roll_back(&mut committed, to).unwrap_err().contains("already committed"));
+                assert_eq!((committed.status, committed.completed_at), (status.clone(), 5));
+            }
+        }
+
+        assert!(validate_rollback_reason(" ").is_err());
+        assert!(validate_rollback_reason(&"x".repeat(MAX_ROLLBACK_REASON_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+// Root overrides - emergency replacement of an epoch's Merkle root, approved by two admins
+//
+// One controller proposes a new root for an epoch with a justification; a different controller
+// approves it. Approval replaces the root in the epoch meta (re-certifying it if the epoch holds
+// the certified data) and clears the epoch's ticket issuance records, so every wallet's next
+// get_claim_ticket is issued fresh against the new root. Epochs with any successful claim
+// cannot be overridden. Proposal and approval are recorded in the event log.
+//
+// Proofs are still generated from the stored layers, so tickets only verify against the new
+// root if it is the root of that stored tree. A proposal records whether it is
+// (matches_stored_tree); approval refuses any root that is not.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{certify_epoch_root, epoch_issuance_counts, read_layer_hash, EpochLayerKey, EpochWalletKey};
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::{
+    CERTIFIED_EPOCH, EPOCH_LAYER_OFFSETS, EPOCH_META, EPOCH_ROOT_OVERRIDES, EPOCH_VESTED_TRANCHES, TICKET_ISSUANCE,
+};
+
+/// Longest override justification accepted, in characters
+pub const MAX_OVERRIDE_JUSTIFICATION_LEN: usize = 280;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct EpochRootOverride {
+    pub epoch: u64,
+    pub new_root: [u8; 32],
+    pub justification: String,
+    pub proposed_by: Principal,
+    pub proposed_at: u64,
+    pub matches_stored_tree: bool,  // new_root is the root of the epoch's stored layers
+}
+
+impl Storable for EpochRootOverride {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize EpochRootOverride");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize EpochRootOverride")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Root of the epoch's stored layers (the single hash of its top layer)
+fn stored_tree_root(epoch: u64) -> Option<[u8; 32]> {
+    let (layer_id, offset) = EPOCH_LAYER_OFFSETS.with(|store| {
+        store.borrow()
+            .range(EpochLayerKey { epoch, layer_id: 0 }..)
+            .take_while(|(key, _)| key.epoch == epoch)
+            .last()
+            .map(|(key, offset)| (key.layer_id, offset))
+    })?;
+    read_layer_hash(epoch, layer_id, &offset, 0).ok()
+}
+
+fn validate_justification(justification: &str) -> Result<(), String> {
+    if justification.trim().is_empty() {
+        return Err("Override justification cannot be empty".to_string());
+    }
+    if justification.chars().count() > MAX_OVERRIDE_JUSTIFICATION_LEN {
+        return Err(format!("Override justification exceeds {} characters", MAX_OVERRIDE_JUSTIFICATION_LEN));
+    }
+    Ok(())
+}
+
+/// Successful claims against an epoch, immediate and vested leaves
+fn epoch_claim_count(epoch: u64) -> u64 {
+    let vested_claimed = EPOCH_VESTED_TRANCHES.with(|store| {
+        store.borrow()
+            .range(EpochWalletKey { epoch, wallet: String::new() }..)
+            .take_while(|(key, _)| key.epoch == epoch)
+            .filter(|(_, tranche)| tranche.claimed)
+            .count() as u64
+    });
+    epoch_issuance_counts(epoch).1 + vested_claimed
+}
+
+fn build_proposal(epoch: u64, new_root: Vec<u8>, justification: String, proposed_by: Principal, now: u64) -> Result<EpochRootOverride, String> {
+    let new_root: [u8; 32] = new_root.try_into()
+        .map_err(|root: Vec<u8>| format!("New root must be 32 bytes, got {}", root.len()))?;
+    validate_justification(&justification)?;
+    let meta = EPOCH_META.with(|store| store.borrow().get(&epoch))
+        .ok_or_else(|| format!("Epoch {} metadata not found", epoch))?;
+    if meta.pruned {
+        return Err(format!("EpochPruned: epoch {} hash data has been pruned", epoch));
+    }
+    if meta.root == new_root {
+        return Err(format!("Epoch {} already has this root", epoch));
+    }
+    let claims = epoch_claim_count(epoch);
+    if claims > 0 {
+        return Err(format!("Epoch {} has {} claims; cannot override its root", epoch, claims));
+    }
+    Ok(EpochRootOverride {
+        epoch,
+        new_root,
+        justification,
+        proposed_by,
+        proposed_at: now,
+        matches_stored_tree: stored_tree_root(epoch) == Some(new_root),
+    })
+}
+
+/// Propose replacing an epoch's root; a different admin must approve it (admin only).
+/// A new proposal for the same epoch replaces the pending one.
+pub fn propose_epoch_root_override(epoch: u64, new_root: Vec<u8>, justification: String) -> Result<EpochRootOverride, String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can propose an epoch root override".to_string());
+    }
+    let proposal = build_proposal(epoch, new_root, justification, caller, crate::env::time())?;
+    EPOCH_ROOT_OVERRIDES.with(|store| store.borrow_mut().insert(epoch, proposal.clone()));
+    crate::env::println!("Proposed root override for epoch {} (matches stored tree: {})", epoch, proposal.matches_stored_tree);
+    event_log::emit(EventKind::EpochRootOverrideProposed {
+        epoch,
+        new_root: proposal.new_root.to_vec(),
+        justification: proposal.justification.clone(),
+        proposed_by: caller.to_text(),
+    });
+    Ok(proposal)
+}
+
+/// Replace the root with the approved proposal and drop the epoch's issuance records; returns
+/// the old root and how many issuance records were cleared
+fn apply_override(proposal: &EpochRootOverride, approver: Principal) -> Result<([u8; 32], u64), String> {
+    if approver == proposal.proposed_by {
+        return Err("An epoch root override must be approved by a different admin than its proposer".to_string());
+    }
+    let epoch = proposal.epoch;
+    if stored_tree_root(epoch) != Some(proposal.new_root) {
+        return Err(format!(
+            "New root of epoch {} is not the root of its stored tree; tickets would not verify against it",
+            epoch,
+        ));
+    }
+    let claims = epoch_claim_count(epoch);
+    if claims > 0 {
+        return Err(format!("Epoch {} has {} claims; cannot override its root", epoch, claims));
+    }
+    let old_root = EPOCH_META.with(|store| {
+        let mut map = store.borrow_mut();
+        let mut meta = map.get(&epoch)
+            .ok_or_else(|| format!("Epoch {} metadata not found", epoch))?;
+        if meta.pruned {
+            return Err(format!("EpochPruned: epoch {} hash data has been pruned", epoch));
+        }
+        let old_root = meta.root;
+        meta.root = proposal.new_root;
+        map.insert(epoch, meta);
+        Ok(old_root)
+    })?;
+    if CERTIFIED_EPOCH.with(|cell| cell.borrow().get().epoch) == Some(epoch) {
+        certify_epoch_root(epoch, &proposal.new_root);
+    }
+
+    let invalidated = TICKET_ISSUANCE.with(|store| {
+        let mut map = store.borrow_mut();
+        let keys: Vec<EpochWalletKey> = map
+            .range(EpochWalletKey { epoch, wallet: String::new() }..)
+            .take_while(|(key, _)| key.epoch == epoch)
+            .map(|(key, _)| key)
+            .collect();
+        for key in &keys {
+            map.remove(key);
+        }
+        keys.len() as u64
+    });
+    EPOCH_ROOT_OVERRIDES.with(|store| store.borrow_mut().remove(&epoch));
+    Ok((old_root, invalidated))
+}
+
+/// Approve another admin's pending root override for an epoch and apply it (admin only)
+pub fn approve_epoch_root_override(epoch: u64) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can approve an epoch root override".to_string());
+    }
+    let proposal = EPOCH_ROOT_OVERRIDES.with(|store| store.borrow().get(&epoch))
+        .ok_or_else(|| format!("No root override proposed for epoch {}", epoch))?;
+    let (old_root, invalidated) = apply_override(&proposal, caller)?;
+    crate::env::println!("Overrode root of epoch {}; cleared {} ticket issuance records", epoch, invalidated);
+    event_log::emit(EventKind::EpochRootOverridden {
+        epoch,
+        old_root: old_root.to_vec(),
+        new_root: proposal.new_root.to_vec(),
+        justification: proposal.justification,
+        proposed_by: proposal.proposed_by.to_text(),
+        approved_by: caller.to_text(),
+        tickets_invalidated: invalidated,
+    });
+    Ok(())
+}
+
+/// Pending root override of an epoch, if any
+pub fn get_epoch_root_override(epoch: u64) -> Option<EpochRootOverride> {
+    EPOCH_ROOT_OVERRIDES.with(|store| store.borrow().get(&epoch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_rewards::{
+        build_merkle_layers, compute_target_leaf_hash, store_epoch, tests::test_meta, CampaignScope, ChainTarget,
+        ClaimEntry, MerkleSnapshotMeta, TicketIssuance, VestingCheck, CURRENT_TREE_VERSION,
+    };
+
+    fn issue(epoch: u64, wallet: &str, claimed: bool) {
+        TICKET_ISSUANCE.with(|store| store.borrow_mut().insert(
+            EpochWalletKey { epoch, wallet: wallet.to_string() },
+            TicketIssuance { issue_count: 1, first_issued_at: 1, last_issued_at: 1, claimed, claim_tx_sig: None },
+        ));
+    }
+
+    #[test]
+    fn test_override_needs_second_admin_and_no_claims() {
+        let entries: Vec<ClaimEntry> = (0..3u8)
+            .map(|i| ClaimEntry { epoch: 7, index: i as u32, wallet: bs58::encode([i + 40; 32]).into_string(), amount: 10, claimable_after: 0 })
+            .collect();
+        let leaves = entries.iter().map(|e| compute_target_leaf_hash(ChainTarget::Solana, e).unwrap()).collect();
+        let layers = build_merkle_layers(leaves, CURRENT_TREE_VERSION, ChainTarget::Solana);
+        let tree_root = layers[layers.len() - 1][0];
+        // The stored meta carries a bad root; the stored layers are right
+        let meta = MerkleSnapshotMeta {
+            leaves_count: 3,
+            locked: true,
+            created_at: 1,
+            total_reward_amount: 30,
+            ..test_meta(7, [1; 32])
+        };
+        store_epoch(&meta, &entries, &layers, &VestingCheck::load(1), &CampaignScope::load(None)).unwrap();
+        let (alice, bob) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+
+        assert!(build_proposal(7, vec![9; 31], "bad root".to_string(), alice, 5).is_err());
+        assert!(build_proposal(7, vec![9; 32], " ".to_string(), alice, 5).is_err());
+        assert!(build_proposal(8, vec![9; 32], "bad root".to_string(), alice, 5).is_err());
+        assert!(build_proposal(7, vec![1; 32], "bad root".to_string(), alice, 5).is_err());
+        let mismatched = build_proposal(7, vec![9; 32], "bad root".to_string(), alice, 5).unwrap();
+        assert!(!mismatched.matches_stored_tree);
+        assert!(apply_override(&mismatched, bob).unwrap_err().contains("not the root of its stored tree"));
+        assert_eq!(EPOCH_META.with(|store| store.borrow().get(&7)).unwrap().root, meta.root);
+        let proposal = build_proposal(7, tree_root.to_vec(), "bad root".to_string(), alice, 5).unwrap();
+        assert!(proposal.matches_stored_tree);
+
+        issue(7, "w1", false);
+        issue(7, "w2", false);
+        issue(8, "w1", false);
+        assert!(apply_override(&proposal, alice).unwrap_err().contains("different admin"));
+        assert_eq!(apply_override(&proposal, bob), Ok((meta.root, 2)));
+        assert_eq!(EPOCH_META.with(|store| store.borrow().get(&7)).unwrap().root, tree_root);
+        assert_eq!(epoch_issuance_counts(7), (0, 0));
+        assert_eq!(epoch_issuance_counts(8), (1, 0));
+
+        issue(7, "w1", true);
+        assert!(apply_override(&proposal, bob).unwrap_err().contains("1 claims"));
+        assert!(build_proposal(7, vec![3; 32], "again".to_string(), alice, 6).is_err());
+    }
+}
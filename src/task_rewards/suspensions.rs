@@ -0,0 +1,174 @@
+// Suspensions - wallets frozen by an operator (e.g. suspected Sybil or fraud)
+//
+// A suspended wallet keeps its data; it cannot complete tasks, record payments or fetch claim
+// tickets until it is unsuspended or its suspension expires. An expired suspension stays in
+// the map but no longer blocks anything. Suspend and unsuspend actions are recorded in the
+// event log.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::normalize_wallet;
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::SUSPENDED_WALLETS;
+
+/// Longest suspension reason accepted, in characters
+pub const MAX_SUSPENSION_REASON_LEN: usize = 280;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct WalletSuspension {
+    pub wallet: String,
+    pub reason: String,
+    pub suspended_at: u64,
+    pub expires_at: Option<u64>,  // None = until unsuspended
+    pub suspended_by: String,
+}
+
+impl Storable for WalletSuspension {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize WalletSuspension");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize WalletSuspension")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl WalletSuspension {
+    fn in_effect(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+pub(super) fn suspension_in_effect(wallet: &str, now: u64) -> Option<WalletSuspension> {
+    SUSPENDED_WALLETS.with(|store| store.borrow().get(&wallet.to_string()))
+        .filter(|suspension| suspension.in_effect(now))
+}
+
+/// Refuse a suspended wallet; the reason stays with the admins
+pub fn require_wallet_active(wallet: &str) -> Result<(), String> {
+    check_active(wallet, crate::env::time())
+}
+
+fn check_active(wallet: &str, now: u64) -> Result<(), String> {
+    match suspension_in_effect(wallet, now) {
+        Some(WalletSuspension { expires_at: Some(expires_at), .. }) => {
+            Err(format!("WalletSuspended: wallet {} is suspended until {}", wallet, expires_at))
+        }
+        Some(_) => Err(format!("WalletSuspended: wallet {} is suspended", wallet)),
+        None => Ok(()),
+    }
+}
+
+fn validate_suspension_reason(reason: &str) -> Result<(), String> {
+    if reason.trim().is_empty() {
+        return Err("Suspension reason cannot be empty".to_string());
+    }
+    if reason.chars().count() > MAX_SUSPENSION_REASON_LEN {
+        return Err(format!("Suspension reason exceeds {} characters", MAX_SUSPENSION_REASON_LEN));
+    }
+    Ok(())
+}
+
+/// Block a wallet's task completions, payments and claim tickets, for `duration_ns` or until
+/// unsuspended (admin only). Suspending a suspended wallet replaces its suspension.
+pub fn suspend_wallet(wallet: String, reason: String, duration_ns: Option<u64>) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can suspend wallets".to_string());
+    }
+    let wallet = normalize_wallet(&wallet)?;
+    validate_suspension_reason(&reason)?;
+    if duration_ns == Some(0) {
+        return Err("Suspension duration must be positive".to_string());
+    }
+
+    let now = crate::env::time();
+    let suspension = WalletSuspension {
+        wallet: wallet.clone(),
+        reason: reason.clone(),
+        suspended_at: now,
+        expires_at: duration_ns.map(|duration| now.saturating_add(duration)),
+        suspended_by: caller.to_text(),
+    };
+    SUSPENDED_WALLETS.with(|store| store.borrow_mut().insert(wallet.clone(), suspension.clone()));
+    crate::env::println!("Suspended wallet {} until {:?}: {}", wallet, suspension.expires_at, reason);
+    event_log::emit(EventKind::WalletSuspended {
+        wallet,
+        reason,
+        expires_at: suspension.expires_at,
+        suspended_by: suspension.suspended_by,
+    });
+    Ok(())
+}
+
+/// Lift a wallet's suspension, expired or not (admin only)
+pub fn unsuspend_wallet(wallet: String) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can unsuspend wallets".to_string());
+    }
+    let wallet = normalize_wallet(&wallet)?;
+    if SUSPENDED_WALLETS.with(|store| store.borrow_mut().remove(&wallet)).is_none() {
+        return Err(format!("Wallet {} is not suspended", wallet));
+    }
+    crate::env::println!("Unsuspended wallet {}", wallet);
+    event_log::emit(EventKind::WalletUnsuspended { wallet, unsuspended_by: caller.to_text() });
+    Ok(())
+}
+
+/// Suspensions still in effect, in wallet order
+pub fn list_suspended_wallets() -> Vec<WalletSuspension> {
+    let now = crate::env::time();
+    SUSPENDED_WALLETS.with(|store| {
+        store.borrow()
+            .iter()
+            .map(|(_, suspension)| suspension)
+            .filter(|suspension| suspension.in_effect(now))
+            .collect()
+    })
+}
+
+/// Whether a wallet is suspended right now
+pub fn is_wallet_suspended(wallet: String) -> bool {
+    let wallet = normalize_wallet(&wallet).unwrap_or(wallet);
+    suspension_in_effect(&wallet, crate::env::time()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suspend(wallet: &str, expires_at: Option<u64>) {
+        SUSPENDED_WALLETS.with(|store| store.borrow_mut().insert(wallet.to_string(), WalletSuspension {
+            wallet: wallet.to_string(),
+            reason: "sybil cluster 4".to_string(),
+            suspended_at: 100,
+            expires_at,
+            suspended_by: "admin".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_suspension_blocks_until_expiry() {
+        assert!(check_active("w1", 150).is_ok());
+
+        suspend("w1", None);
+        suspend("w2", Some(200));
+        let err = check_active("w1", u64::MAX).unwrap_err();
+        assert!(err.starts_with("WalletSuspended"));
+        assert!(!err.contains("sybil"));
+
+        assert!(check_active("w2", 199).unwrap_err().contains("until 200"));
+        assert!(check_active("w2", 200).is_ok());
+        assert!(suspension_in_effect("w2", 200).is_none());
+
+        assert!(validate_suspension_reason(" ").is_err());
+        assert!(validate_suspension_reason(&"x".repeat(MAX_SUSPENSION_REASON_LEN + 1)).is_err());
+    }
+}
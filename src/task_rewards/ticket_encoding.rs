@@ -0,0 +1,149 @@
+// Ticket Encoding - claim tickets with byte fields as strings, for web3.js-style clients
+//
+// Every byte field (proof elements, root, signature) uses the same encoding:
+//   Raw      comma-separated decimal bytes, e.g. "1,2,255"
+//   HexLower lowercase hex without 0x
+//   Base64   standard alphabet with padding
+//   Base58   Bitcoin alphabet, as used for Solana keys
+// Encoding and decoding are pure and inverse to each other.
+
+use base64::{engine::general_purpose, Engine as _};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use super::{ChainTarget, ClaimTicket};
+
+/// String encoding of the byte fields of a ClaimTicketEncoded
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofEncoding {
+    Raw,
+    HexLower,
+    Base64,
+    Base58,
+}
+
+/// ClaimTicket with proof, root and signature encoded as strings
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ClaimTicketEncoded {
+    pub epoch: u64,
+    pub index: u32,
+    pub wallet: String,
+    pub amount: u64,
+    pub proof: Vec<String>,
+    pub root: String,
+    pub signature: Option<String>,
+    pub target: ChainTarget,
+}
+
+fn encode_bytes(bytes: &[u8], encoding: ProofEncoding) -> String {
+    match encoding {
+        ProofEncoding::Raw => bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(","),
+        ProofEncoding::HexLower => hex::encode(bytes),
+        ProofEncoding::Base64 => general_purpose::STANDARD.encode(bytes),
+        ProofEncoding::Base58 => bs58::encode(bytes).into_string(),
+    }
+}
+
+fn decode_bytes(text: &str, encoding: ProofEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        ProofEncoding::Raw if text.is_empty() => Ok(Vec::new()),
+        ProofEncoding::Raw => text.split(',')
+            .map(|b| b.trim().parse::<u8>().map_err(|e| format!("Invalid raw byte '{}': {}", b, e)))
+            .collect(),
+        ProofEncoding::HexLower => hex::decode(text).map_err(|e| format!("Invalid hex: {}", e)),
+        ProofEncoding::Base64 => general_purpose::STANDARD.decode(text).map_err(|e| format!("Invalid base64: {}", e)),
+        ProofEncoding::Base58 => bs58::decode(text).into_vec().map_err(|e| format!("Invalid base58: {}", e)),
+    }
+}
+
+/// Encode the byte fields of a ticket as strings
+pub fn encode_claim_ticket(ticket: ClaimTicket, encoding: ProofEncoding) -> ClaimTicketEncoded {
+    ClaimTicketEncoded {
+        epoch: ticket.epoch,
+        index: ticket.index,
+        wallet: ticket.wallet,
+        amount: ticket.amount,
+        proof: ticket.proof.iter().map(|node| encode_bytes(node, encoding)).collect(),
+        root: encode_bytes(&ticket.root, encoding),
+        signature: ticket.signature.as_deref().map(|sig| encode_bytes(sig, encoding)),
+        target: ticket.target,
+    }
+}
+
+/// Decode a ticket produced by encode_claim_ticket with the same encoding
+pub fn decode_claim_ticket(encoded: ClaimTicketEncoded, encoding: ProofEncoding) -> Result<ClaimTicket, String> {
+    Ok(ClaimTicket {
+        epoch: encoded.epoch,
+        index: encoded.index,
+        wallet: encoded.wallet,
+        amount: encoded.amount,
+        proof: encoded.proof.iter()
+            .map(|node| decode_bytes(node, encoding))
+            .collect::<Result<_, _>>()?,
+        root: decode_bytes(&encoded.root, encoding)?,
+        signature: encoded.signature.as_deref().map(|sig| decode_bytes(sig, encoding)).transpose()?,
+        target: encoded.target,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_rewards::{build_merkle_layers, compute_leaf_hash, decode_wallet_base58, verify_claim_ticket, CURRENT_TREE_VERSION};
+
+    const ENCODINGS: [ProofEncoding; 4] =
+        [ProofEncoding::Raw, ProofEncoding::HexLower, ProofEncoding::Base64, ProofEncoding::Base58];
+
+    fn wallet(i: u8) -> String {
+        bs58::encode([i; 32]).into_string()
+    }
+
+    // Ticket for leaf 1 of a three-leaf Solana epoch
+    fn ticket() -> ClaimTicket {
+        let epoch = 4;
+        let leaves: Vec<[u8; 32]> = (0..3u8)
+            .map(|i| compute_leaf_hash(epoch, i as u32, &decode_wallet_base58(&wallet(i + 1)).unwrap(), 100 + i as u64))
+            .collect();
+        let layers = build_merkle_layers(leaves, CURRENT_TREE_VERSION, ChainTarget::Solana);
+        ClaimTicket {
+            epoch,
+            index: 1,
+            wallet: wallet(2),
+            amount: 101,
+            proof: vec![layers[0][0].to_vec(), layers[1][1].to_vec()],
+            root: layers[2][0].to_vec(),
+            signature: Some(vec![0, 7, 255]),
+            target: ChainTarget::Solana,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_for_all_encodings() {
+        let original = ticket();
+        for encoding in ENCODINGS {
+            let encoded = encode_claim_ticket(original.clone(), encoding);
+            let decoded = decode_claim_ticket(encoded, encoding).unwrap();
+            assert_eq!(decoded.proof, original.proof, "{:?}", encoding);
+            assert_eq!(decoded.root, original.root, "{:?}", encoding);
+            assert_eq!(decoded.signature, original.signature, "{:?}", encoding);
+            assert_eq!((decoded.epoch, decoded.index, decoded.amount), (4, 1, 101));
+        }
+        let encoded = encode_claim_ticket(original, ProofEncoding::HexLower);
+        assert_eq!(encoded.signature.as_deref(), Some("0007ff"));
+        assert!(decode_claim_ticket(encoded, ProofEncoding::Base58).is_err());
+    }
+
+    #[test]
+    fn test_ticket_decoded_from_base64_verifies() {
+        let original = ticket();
+        assert!(verify_claim_ticket(&original).unwrap());
+
+        let encoded = encode_claim_ticket(original, ProofEncoding::Base64);
+        let decoded = decode_claim_ticket(encoded.clone(), ProofEncoding::Base64).unwrap();
+        assert!(verify_claim_ticket(&decoded).unwrap());
+
+        let tampered = ClaimTicketEncoded { amount: 102, ..encoded };
+        let decoded = decode_claim_ticket(tampered, ProofEncoding::Base64).unwrap();
+        assert!(!verify_claim_ticket(&decoded).unwrap());
+    }
+}
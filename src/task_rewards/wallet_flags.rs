@@ -0,0 +1,137 @@
+// Wallet flags - wallets held out of distributions (e.g. sybil clusters)
+//
+// A flagged wallet keeps its tasks and their statuses; it is only skipped when epochs are
+// built and refused claim tickets. Unflagging puts its Completed tasks back into the next
+// epoch. Flag and unflag actions are recorded in the event log.
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{normalize_wallet, MAX_SCAN_PAGE};
+use crate::event_log::{self, EventKind};
+use crate::stable_mem_storage::FLAGGED_WALLETS;
+
+/// Longest flag reason accepted, in characters
+pub const MAX_FLAG_REASON_LEN: usize = 280;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct WalletFlag {
+    pub wallet: String,
+    pub reason: String,
+    pub flagged_by: Principal,
+    pub flagged_at: u64,
+}
+
+impl Storable for WalletFlag {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize WalletFlag");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize WalletFlag")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub(crate) fn is_flagged(wallet: &str) -> bool {
+    FLAGGED_WALLETS.with(|store| store.borrow().contains_key(&wallet.to_string()))
+}
+
+/// Refuse a flagged wallet; the reason stays with the admins
+pub(crate) fn check_not_flagged(wallet: &str) -> Result<(), String> {
+    if is_flagged(wallet) {
+        return Err(format!("WalletFlagged: wallet {} is excluded from reward distribution", wallet));
+    }
+    Ok(())
+}
+
+fn validate_flag_reason(reason: &str) -> Result<(), String> {
+    if reason.trim().is_empty() {
+        return Err("Flag reason cannot be empty".to_string());
+    }
+    if reason.chars().count() > MAX_FLAG_REASON_LEN {
+        return Err(format!("Flag reason exceeds {} characters", MAX_FLAG_REASON_LEN));
+    }
+    Ok(())
+}
+
+/// Exclude a wallet from epoch snapshots and claim tickets (admin only). Re-flagging a
+/// wallet replaces its reason.
+pub fn flag_wallet(wallet: String, reason: String) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can flag wallets".to_string());
+    }
+    let wallet = normalize_wallet(&wallet)?;
+    validate_flag_reason(&reason)?;
+
+    let flag = WalletFlag {
+        wallet: wallet.clone(),
+        reason: reason.clone(),
+        flagged_by: caller,
+        flagged_at: crate::env::time(),
+    };
+    FLAGGED_WALLETS.with(|store| store.borrow_mut().insert(wallet.clone(), flag));
+    crate::env::println!("Flagged wallet {}: {}", wallet, reason);
+    event_log::emit(EventKind::WalletFlagged { wallet, reason, flagged_by: caller.to_text() });
+    Ok(())
+}
+
+/// Return a flagged wallet to the normal reward flow (admin only)
+pub fn unflag_wallet(wallet: String) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if !crate::env::is_controller(&caller) {
+        return Err("Only controller can unflag wallets".to_string());
+    }
+    let wallet = normalize_wallet(&wallet)?;
+    if FLAGGED_WALLETS.with(|store| store.borrow_mut().remove(&wallet)).is_none() {
+        return Err(format!("Wallet {} is not flagged", wallet));
+    }
+    crate::env::println!("Unflagged wallet {}", wallet);
+    event_log::emit(EventKind::WalletUnflagged { wallet, unflagged_by: caller.to_text() });
+    Ok(())
+}
+
+/// Flagged wallets in wallet order, `limit` (at most MAX_SCAN_PAGE) after skipping `offset`
+/// (admin only)
+pub fn list_flagged_wallets(offset: u64, limit: u64) -> Result<Vec<WalletFlag>, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can list flagged wallets".to_string());
+    }
+    Ok(FLAGGED_WALLETS.with(|store| {
+        store.borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_SCAN_PAGE) as usize)
+            .map(|(_, flag)| flag)
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flagged_wallet_is_refused() {
+        let wallet = bs58::encode([7u8; 32]).into_string();
+        assert!(check_not_flagged(&wallet).is_ok());
+
+        FLAGGED_WALLETS.with(|store| store.borrow_mut().insert(wallet.clone(), WalletFlag {
+            wallet: wallet.clone(),
+            reason: "cluster 12".to_string(),
+            flagged_by: Principal::anonymous(),
+            flagged_at: 0,
+        }));
+        let err = check_not_flagged(&wallet).unwrap_err();
+        assert!(err.starts_with("WalletFlagged"));
+        assert!(!err.contains("cluster 12"));
+
+        assert!(validate_flag_reason("").is_err());
+        assert!(validate_flag_reason(&"x".repeat(MAX_FLAG_REASON_LEN + 1)).is_err());
+    }
+}
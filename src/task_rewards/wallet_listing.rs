@@ -0,0 +1,191 @@
+// Wallet listing - page through every wallet with task state, optionally filtered
+//
+// list_wallets walks USER_TASKS in wallet order from a cursor and keeps the wallets matching
+// the filter; count_wallets counts them for dashboards. Both are open to controllers and to
+// the principals on the wallet viewer allowlist.
+
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::ops::Bound;
+
+use super::{suspensions, EpochWalletKey, TicketIssuance, UserTaskState};
+use crate::stable_mem_storage::{EPOCH_WALLET_INDEX, TICKET_ISSUANCE, USER_TASKS, WALLET_VIEWERS};
+
+/// Most wallets returned by one list_wallets call
+pub const MAX_WALLET_LIST_PAGE: u64 = 500;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum WalletFilter {
+    All,
+    HasUnclaimed,          // total_unclaimed > 0
+    HasPendingEpoch(u64),  // Has a leaf in the epoch that is not claimed yet
+    ByTier(String),        // Bound principal has an active subscription of this tier
+    SuspendedOnly,         // Suspension in effect
+}
+
+impl WalletFilter {
+    fn matches(&self, state: &UserTaskState, now: u64) -> bool {
+        let wallet = &state.wallet;
+        match self {
+            WalletFilter::All => true,
+            WalletFilter::HasUnclaimed => state.total_unclaimed > 0,
+            WalletFilter::HasPendingEpoch(epoch) => {
+                let key = EpochWalletKey { epoch: *epoch, wallet: wallet.clone() };
+                EPOCH_WALLET_INDEX.with(|store| store.borrow().contains_key(&key))
+                    && !TICKET_ISSUANCE.with(|store| store.borrow().get(&key))
+                        .is_some_and(|issuance: TicketIssuance| issuance.claimed)
+            }
+            WalletFilter::ByTier(tier) => subscription_tier(wallet, now).as_ref() == Some(tier),
+            WalletFilter::SuspendedOnly => suspensions::suspension_in_effect(wallet, now).is_some(),
+        }
+    }
+}
+
+/// Tier of the active subscription of the principal bound to `wallet`
+fn subscription_tier(wallet: &str, now: u64) -> Option<String> {
+    crate::ai_sub_service::get_wallet_principal(wallet)
+        .and_then(|principal_id| crate::ai_sub_service::get_subscription(&principal_id))
+        .filter(|sub| now < sub.expires_at)
+        .map(|sub| sub.tier)
+}
+
+fn require_viewer(action: &str) -> Result<(), String> {
+    let caller = crate::env::caller();
+    if crate::env::is_controller(&caller) || WALLET_VIEWERS.with(|store| store.borrow().contains_key(&caller)) {
+        return Ok(());
+    }
+    Err(format!("Only controller or wallet viewers can {}", action))
+}
+
+/// Allow a principal to list and count wallets (admin only)
+pub fn add_wallet_viewer(viewer: Principal) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can add wallet viewers".to_string());
+    }
+    WALLET_VIEWERS.with(|store| store.borrow_mut().insert(viewer, ()));
+    Ok(())
+}
+
+/// Revoke a wallet viewer (admin only)
+pub fn remove_wallet_viewer(viewer: Principal) -> Result<(), String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can remove wallet viewers".to_string());
+    }
+    WALLET_VIEWERS.with(|store| store.borrow_mut().remove(&viewer))
+        .map(|_| ())
+        .ok_or_else(|| format!("{} is not a wallet viewer", viewer))
+}
+
+/// List wallet viewers (admin only)
+pub fn list_wallet_viewers() -> Result<Vec<Principal>, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can list wallet viewers".to_string());
+    }
+    Ok(WALLET_VIEWERS.with(|store| store.borrow().iter().map(|(viewer, _)| viewer).collect()))
+}
+
+/// Up to `limit` (at most MAX_WALLET_LIST_PAGE) wallets after `after_wallet` matching
+/// `filter`, in wallet order, with the cursor for the next page (None after the last one)
+pub fn list_wallets(filter: WalletFilter, after_wallet: Option<String>, limit: u64) -> Result<(Vec<String>, Option<String>), String> {
+    require_viewer("list wallets")?;
+    Ok(filtered_page(&filter, after_wallet, limit.min(MAX_WALLET_LIST_PAGE), crate::env::time()))
+}
+
+fn filtered_page(filter: &WalletFilter, after_wallet: Option<String>, limit: u64, now: u64) -> (Vec<String>, Option<String>) {
+    let limit = limit as usize;
+    let start = after_wallet.map_or(Bound::Unbounded, Bound::Excluded);
+    let mut wallets: Vec<String> = USER_TASKS.with(|store| {
+        store.borrow()
+            .range((start, Bound::Unbounded))
+            .filter(|(_, state)| filter.matches(state, now))
+            .map(|(wallet, _)| wallet)
+            .take(limit.saturating_add(1))
+            .collect()
+    });
+    if wallets.len() <= limit {
+        return (wallets, None);
+    }
+    wallets.truncate(limit);
+    let next = wallets.last().cloned();
+    (wallets, next)
+}
+
+/// Number of wallets matching `filter`
+pub fn count_wallets(filter: WalletFilter) -> Result<u64, String> {
+    require_viewer("count wallets")?;
+    let now = crate::env::time();
+    Ok(USER_TASKS.with(|store| {
+        store.borrow().iter().filter(|(_, state)| filter.matches(state, now)).count() as u64
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::TestEnvironment;
+    use crate::stable_mem_storage::SUSPENDED_WALLETS;
+    use crate::task_rewards::suspensions::WalletSuspension;
+    use crate::task_rewards::{EpochWalletEntry, TaskStatus, UserTaskDetail};
+
+    fn store_wallet(wallet: &str, status: TaskStatus) {
+        let task = UserTaskDetail {
+            taskid: "t".to_string(),
+            status,
+            completed_at: 0,
+            reward_amount: 10,
+            evidence: None,
+            last_completed_at: 0,
+            disputed: false,
+            dispute_reason: None,
+            campaign_id: None,
+            reward_tier: None,
+            retired: false,
+        };
+        USER_TASKS.with(|store| store.borrow_mut().insert(wallet.to_string(), UserTaskState::new(wallet.to_string(), vec![task])));
+    }
+
+    #[test]
+    fn test_filtered_pages_and_viewer_access() {
+        let admin = Principal::from_slice(&[1]);
+        let viewer = Principal::from_slice(&[2]);
+        let env = TestEnvironment::install(admin, 1_000);
+        for (wallet, status) in [("a", TaskStatus::Completed), ("b", TaskStatus::NotStarted), ("c", TaskStatus::TicketIssued), ("d", TaskStatus::Claimed)] {
+            store_wallet(wallet, status);
+        }
+        for wallet in ["c", "d"] {
+            EPOCH_WALLET_INDEX.with(|store| store.borrow_mut().insert(
+                EpochWalletKey { epoch: 3, wallet: wallet.to_string() },
+                EpochWalletEntry { index: 0, amount: 10 },
+            ));
+        }
+        TICKET_ISSUANCE.with(|store| store.borrow_mut().insert(
+            EpochWalletKey { epoch: 3, wallet: "d".to_string() },
+            TicketIssuance { issue_count: 1, first_issued_at: 0, last_issued_at: 0, claimed: true, claim_tx_sig: None },
+        ));
+        SUSPENDED_WALLETS.with(|store| store.borrow_mut().insert("b".to_string(), WalletSuspension {
+            wallet: "b".to_string(),
+            reason: "sybil".to_string(),
+            suspended_at: 0,
+            expires_at: Some(2_000),
+            suspended_by: admin.to_text(),
+        }));
+
+        assert_eq!(list_wallets(WalletFilter::All, None, 2), Ok((vec!["a".to_string(), "b".to_string()], Some("b".to_string()))));
+        assert_eq!(list_wallets(WalletFilter::All, Some("b".to_string()), 2), Ok((vec!["c".to_string(), "d".to_string()], None)));
+        assert_eq!(list_wallets(WalletFilter::HasUnclaimed, None, 1), Ok((vec!["a".to_string()], Some("a".to_string()))));
+        assert_eq!(list_wallets(WalletFilter::HasUnclaimed, Some("a".to_string()), 1), Ok((vec!["c".to_string()], None)));
+        assert_eq!(list_wallets(WalletFilter::HasPendingEpoch(3), None, 10), Ok((vec!["c".to_string()], None)));
+        assert_eq!(count_wallets(WalletFilter::SuspendedOnly), Ok(1));
+        assert_eq!(count_wallets(WalletFilter::ByTier("premium".to_string())), Ok(0));
+        env.advance(1_000);
+        assert_eq!(count_wallets(WalletFilter::SuspendedOnly), Ok(0));
+
+        env.set_caller(viewer);
+        assert!(count_wallets(WalletFilter::All).is_err());
+        env.set_caller(admin);
+        add_wallet_viewer(viewer).unwrap();
+        env.set_caller(viewer);
+        assert_eq!(count_wallets(WalletFilter::All), Ok(4));
+        assert!(add_wallet_viewer(viewer).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+// Wallet meta - when a wallet first appeared and when it last interacted (anti-Sybil input)
+//
+// get_or_init_user_tasks records a task interaction and apply_payment a payment interaction;
+// both count towards total_interactions and set first_seen_ts on a wallet's first interaction.
+// WALLETS_BY_FIRST_SEEN indexes wallets by first_seen_ts for list_newest_wallets. Canister time
+// is the same for every wallet touched in one message, so a wallet whose first_seen_ts is
+// already taken in the index is stored under the next free nanosecond.
+
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::{Storable, storable::Bound};
+use serde::Serialize;
+use std::borrow::Cow;
+
+use super::{normalize_wallet, MAX_SCAN_PAGE};
+use crate::stable_mem_storage::{WALLETS_BY_FIRST_SEEN, WALLET_META};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct WalletMeta {
+    pub first_seen_ts: u64,
+    pub last_task_ts: u64,     // 0 if the wallet never had a task interaction
+    pub last_payment_ts: u64,  // 0 if the wallet never paid
+    pub total_interactions: u64,
+}
+
+impl Storable for WalletMeta {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize WalletMeta");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        bincode::deserialize(&bytes).expect("Failed to deserialize WalletMeta")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// What a wallet did
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Interaction {
+    Task,
+    Payment,
+}
+
+/// Record an interaction of `wallet` at `ts`
+pub(crate) fn record_interaction(wallet: &str, interaction: Interaction, ts: u64) {
+    let mut meta = WALLET_META.with(|store| store.borrow().get(&wallet.to_string())).unwrap_or_default();
+    if meta.first_seen_ts == 0 {
+        meta.first_seen_ts = ts;
+        WALLETS_BY_FIRST_SEEN.with(|store| {
+            let mut index = store.borrow_mut();
+            let mut key = ts;
+            while index.contains_key(&key) {
+                key += 1;
+            }
+            index.insert(key, wallet.to_string());
+        });
+    }
+    match interaction {
+        Interaction::Task => meta.last_task_ts = ts,
+        Interaction::Payment => meta.last_payment_ts = ts,
+    }
+    meta.total_interactions += 1;
+    WALLET_META.with(|store| store.borrow_mut().insert(wallet.to_string(), meta));
+}
+
+/// First and last interactions of a wallet; None if it never interacted
+pub fn get_wallet_meta(wallet: String) -> Option<WalletMeta> {
+    let wallet = normalize_wallet(&wallet).unwrap_or(wallet);
+    WALLET_META.with(|store| store.borrow().get(&wallet))
+}
+
+/// The `limit` (at most MAX_SCAN_PAGE) most recently first-seen wallets, newest first (admin only)
+pub fn list_newest_wallets(limit: u32) -> Result<Vec<(String, WalletMeta)>, String> {
+    if !crate::env::is_controller(&crate::env::caller()) {
+        return Err("Only controller can list newest wallets".to_string());
+    }
+    Ok(newest_wallets(limit))
+}
+
+fn newest_wallets(limit: u32) -> Vec<(String, WalletMeta)> {
+    let wallets: Vec<String> = WALLETS_BY_FIRST_SEEN.with(|store| {
+        store.borrow()
+            .iter()
+            .rev()
+            .take((limit as u64).min(MAX_SCAN_PAGE) as usize)
+            .map(|(_, wallet)| wallet)
+            .collect()
+    });
+    WALLET_META.with(|store| {
+        let map = store.borrow();
+        wallets.into_iter()
+            .filter_map(|wallet| map.get(&wallet).map(|meta| (wallet, meta)))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interactions_update_meta_and_newest_order() {
+        record_interaction("w1", Interaction::Task, 10);
+        record_interaction("w2", Interaction::Payment, 20);
+        record_interaction("w3", Interaction::Task, 20);
+        record_interaction("w1", Interaction::Payment, 30);
+        record_interaction("w1", Interaction::Task, 40);
+
+        assert_eq!(get_wallet_meta("w1".to_string()), Some(WalletMeta {
+            first_seen_ts: 10,
+            last_task_ts: 40,
+            last_payment_ts: 30,
+            total_interactions: 3,
+        }));
+        assert_eq!(get_wallet_meta("w2".to_string()).map(|m| (m.first_seen_ts, m.last_task_ts)), Some((20, 0)));
+        assert_eq!(get_wallet_meta("w4".to_string()), None);
+
+        let newest: Vec<String> = newest_wallets(10).into_iter().map(|(wallet, _)| wallet).collect();
+        assert_eq!(newest, vec!["w3", "w2", "w1"]);
+        assert_eq!(newest_wallets(1).len(), 1);
+    }
+}